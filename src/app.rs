@@ -2,9 +2,11 @@ use leptos::prelude::*;
 use leptos_meta::{provide_meta_context, MetaTags, Title};
 use leptos_router::{
     components::{Route, Router, Routes},
-    StaticSegment,
+    hooks::use_params_map,
+    ParamSegment, StaticSegment,
 };
 use crate::chat::ChatInterface;
+use crate::sidebar::{list_chats, ChatSidebar};
 
 pub fn shell(options: LeptosOptions) -> impl IntoView {
     view! {
@@ -39,16 +41,40 @@ pub fn App() -> impl IntoView {
             <main>
                 <Routes fallback=|| "Page not found.".into_view()>
                     <Route path=StaticSegment("") view=ChatPage/>
+                    <Route path=(StaticSegment("chat"), ParamSegment("id")) view=ChatPage/>
+                    <Route
+                        path=(StaticSegment("chat"), ParamSegment("id"), StaticSegment("topic"), ParamSegment("topic"))
+                        view=ChatPage
+                    />
                 </Routes>
             </main>
         </Router>
     }
 }
 
-/// Renders the chat page of your application.
+/// Renders the chat sidebar next to the chat selected by the `id` route
+/// parameter, if any, narrowed to the forum topic in `topic` if given.
 #[component]
 fn ChatPage() -> impl IntoView {
+    let params = use_params_map();
+    let selected = Signal::derive(move || params.with(|params| params.get("id")?.parse::<i64>().ok()));
+    let topic = Signal::derive(move || params.with(|params| params.get("topic")?.parse::<i32>().ok()));
+    let chats = Resource::new(|| (), |_| list_chats());
+
     view! {
-        <ChatInterface/>
+        <div class="chat-layout">
+            <ChatSidebar chats=chats selected=selected />
+            {move || match (selected.get(), topic.get()) {
+                (Some(chat_id), topic) => {
+                    let name = Signal::derive(move || {
+                        chats.get()?.ok()?.into_iter().find(|chat| chat.id == chat_id).map(|chat| chat.name)
+                    });
+                    view! { <ChatInterface chat_id=chat_id topic=topic name=name /> }.into_any()
+                }
+                (None, _) => view! {
+                    <div class="chat-placeholder">"Select a chat to view its history."</div>
+                }.into_any(),
+            }}
+        </div>
     }
 }
@@ -0,0 +1,621 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use crate::search::{SearchHit, SearchQuery, SNIPPET_END, SNIPPET_START};
+use crate::telegram::{ChatMessage, ForumTopic, ReplyPreview};
+
+/// On-disk log of every message seen for the logged chats.
+///
+/// Rows are keyed by `(chat_id, id)` and never deleted, so the archive keeps
+/// messages that were later removed on Telegram. The full `ChatMessage` is
+/// stored as JSON next to a few columns used for ordering and lookups.
+#[derive(Clone)]
+pub struct Archive {
+    conn: Arc<Mutex<Connection>>,
+}
+
+/// How far the history backfill of a chat has progressed.
+#[derive(Clone, Debug, Default)]
+pub struct BackfillProgress {
+    /// Oldest message id fetched so far; the next page starts below it.
+    pub oldest_id: Option<i32>,
+    /// Whether the beginning of the chat has been reached.
+    pub complete: bool,
+    /// Newest message id up to which the archive is known to have every
+    /// message. Messages above it are fetched again on the next run.
+    pub synced_id: Option<i32>,
+}
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS messages (
+        chat_id      INTEGER NOT NULL,
+        id           INTEGER NOT NULL,
+        timestamp    INTEGER NOT NULL,
+        sender       TEXT    NOT NULL,
+        message_type TEXT    NOT NULL,
+        text         TEXT    NOT NULL,
+        data         TEXT    NOT NULL,
+        PRIMARY KEY (chat_id, id)
+    );
+    CREATE INDEX IF NOT EXISTS messages_by_time ON messages (chat_id, timestamp);
+
+    -- Full-text index over searchable fields, sharing rowids with messages
+    CREATE VIRTUAL TABLE IF NOT EXISTS message_search USING fts5(
+        text, caption, file_name, sender,
+        has_link UNINDEXED, has_media UNINDEXED,
+        tokenize = 'unicode61 remove_diacritics 2'
+    );
+
+    CREATE TABLE IF NOT EXISTS chats (
+        id   INTEGER PRIMARY KEY,
+        name TEXT    NOT NULL
+    );
+
+    -- The message each downloadable document was last seen in, which is
+    -- where it is fetched from on demand
+    CREATE TABLE IF NOT EXISTS documents (
+        id         INTEGER PRIMARY KEY,
+        chat_id    INTEGER NOT NULL,
+        message_id INTEGER NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS backfill (
+        chat_id   INTEGER PRIMARY KEY,
+        oldest_id INTEGER,
+        complete  INTEGER NOT NULL DEFAULT 0,
+        synced_id INTEGER
+    );
+
+    -- Topics of forum chats as last listed by Telegram, in its order
+    CREATE TABLE IF NOT EXISTS forum_topics (
+        chat_id  INTEGER NOT NULL,
+        id       INTEGER NOT NULL,
+        position INTEGER NOT NULL,
+        data     TEXT    NOT NULL,
+        PRIMARY KEY (chat_id, id)
+    );
+";
+
+/// Columns added to tables after their first release, as `(table, column,
+/// definition, fill)`, added to older archives when they are opened. `fill`
+/// sets the new column of existing rows.
+const ADDED_COLUMNS: &[(&str, &str, &str, Option<&str>)] = &[
+    ("backfill", "synced_id", "INTEGER", None),
+    (
+        "messages",
+        "topic_id",
+        "INTEGER NOT NULL DEFAULT 1",
+        Some("UPDATE messages SET topic_id = json_extract(data, '$.topic_id')
+              WHERE json_extract(data, '$.topic_id') IS NOT NULL"),
+    ),
+];
+
+/// Indexes over added columns, created once the columns exist.
+const ADDED_INDEXES: &str = "
+    CREATE INDEX IF NOT EXISTS messages_by_topic ON messages (chat_id, topic_id, id);
+    CREATE INDEX IF NOT EXISTS messages_by_poll ON messages (json_extract(data, '$.poll.id'))
+        WHERE message_type = 'poll';
+";
+
+impl Archive {
+    /// Opens the archive at `path`, creating the file and schema if needed.
+    pub fn open<P: AsRef<Path>>(path: P) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(SCHEMA)?;
+        add_missing_columns(&conn)?;
+        conn.execute_batch(ADDED_INDEXES)?;
+        rebuild_search_index_if_empty(&conn)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Inserts `messages`, merging each into any stored copy with the same
+    /// key: deletion marks are kept, and if the content changed the stored
+    /// version is pushed onto `revisions`. Returns the messages as stored.
+    pub fn store_messages(&self, messages: &[ChatMessage]) -> rusqlite::Result<Vec<ChatMessage>> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let mut stored = Vec::with_capacity(messages.len());
+        for message in messages {
+            let merged = match load_message(&tx, message.chat_id, message.id)? {
+                Some(previous) => merge_versions(previous, message.clone()),
+                None => message.clone(),
+            };
+            save_message(&tx, &merged)?;
+            stored.push(merged);
+        }
+        tx.commit()?;
+        Ok(stored)
+    }
+
+    /// Flags archived messages `ids` of `chat_id` as deleted at `deleted_at`.
+    /// Returns the messages that were found in the archive.
+    pub fn mark_deleted(&self, chat_id: i64, ids: &[i32], deleted_at: i64) -> rusqlite::Result<Vec<ChatMessage>> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let mut deleted = Vec::new();
+        for &id in ids {
+            if let Some(mut message) = load_message(&tx, chat_id, id)? {
+                message.deleted_at.get_or_insert(deleted_at);
+                save_message(&tx, &message)?;
+                deleted.push(message);
+            }
+        }
+        tx.commit()?;
+        Ok(deleted)
+    }
+
+    /// Returns up to `limit` messages older than `before_id` (or the newest
+    /// ones when `None`), oldest first. With `topic` set, only messages of
+    /// that forum topic are returned.
+    pub fn messages_before(
+        &self,
+        chat_id: i64,
+        topic: Option<i32>,
+        before_id: Option<i32>,
+        limit: i32,
+    ) -> rusqlite::Result<Vec<ChatMessage>> {
+        let conn = self.conn.lock().unwrap();
+        let mut query = conn.prepare_cached(
+            "SELECT data FROM messages
+             WHERE chat_id = ?1 AND (?4 IS NULL OR topic_id = ?4) AND id < ?2
+             ORDER BY id DESC LIMIT ?3",
+        )?;
+        let rows = query.query_map(
+            params![chat_id, before_id.unwrap_or(i32::MAX), limit, topic],
+            |row| row.get::<_, String>(0),
+        )?;
+
+        let mut messages = rows
+            .map(|data| data.and_then(|data| from_json(&data)))
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        messages.reverse();
+        Ok(messages)
+    }
+
+    /// Returns up to `limit` messages newer than `after_id`, oldest first.
+    pub fn messages_after(&self, chat_id: i64, after_id: i32, limit: i32) -> rusqlite::Result<Vec<ChatMessage>> {
+        let conn = self.conn.lock().unwrap();
+        let mut query = conn.prepare_cached(
+            "SELECT data FROM messages
+             WHERE chat_id = ?1 AND id > ?2
+             ORDER BY id ASC LIMIT ?3",
+        )?;
+        let rows = query.query_map(params![chat_id, after_id, limit], |row| row.get::<_, String>(0))?;
+        rows.map(|data| data.and_then(|data| from_json(&data))).collect()
+    }
+
+    /// Every archived message of `chat_id`, oldest first.
+    pub fn chat_messages(&self, chat_id: i64) -> rusqlite::Result<Vec<ChatMessage>> {
+        let conn = self.conn.lock().unwrap();
+        let mut query = conn.prepare_cached("SELECT data FROM messages WHERE chat_id = ?1 ORDER BY id ASC")?;
+        let rows = query.query_map(params![chat_id], |row| row.get::<_, String>(0))?;
+        rows.map(|data| data.and_then(|data| from_json(&data))).collect()
+    }
+
+    /// The archived messages carrying poll `poll_id`, in any chat. A poll
+    /// forwarded to several chats is the same poll in each.
+    pub fn poll_messages(&self, poll_id: i64) -> rusqlite::Result<Vec<ChatMessage>> {
+        let conn = self.conn.lock().unwrap();
+        let mut query = conn.prepare_cached(
+            "SELECT data FROM messages
+             WHERE message_type = 'poll' AND json_extract(data, '$.poll.id') = ?1",
+        )?;
+        let rows = query.query_map(params![poll_id], |row| row.get::<_, String>(0))?;
+        rows.map(|data| data.and_then(|data| from_json(&data))).collect()
+    }
+
+    /// The archived message `id` of `chat_id`.
+    pub fn message(&self, chat_id: i64, id: i32) -> rusqlite::Result<Option<ChatMessage>> {
+        let conn = self.conn.lock().unwrap();
+        load_message(&conn, chat_id, id)
+    }
+
+    /// Fills in `reply_preview` of each of `messages` that replies to an
+    /// archived message, or to another of `messages`.
+    pub fn attach_reply_previews(&self, messages: &mut [ChatMessage]) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let positions: HashMap<i32, usize> = messages.iter().enumerate().map(|(index, message)| (message.id, index)).collect();
+        let mut previews = Vec::new();
+        for (index, message) in messages.iter().enumerate() {
+            let Some(reply_to) = message.reply_to else {
+                continue;
+            };
+            let preview = match positions.get(&reply_to) {
+                Some(&replied) => Some(ReplyPreview::of(&messages[replied])),
+                None => load_message(&conn, message.chat_id, reply_to)?.map(|replied| ReplyPreview::of(&replied)),
+            };
+            previews.push((index, preview));
+        }
+        for (index, preview) in previews {
+            messages[index].reply_preview = preview;
+        }
+        Ok(())
+    }
+
+    /// Ids of every archived message of `chat_id`.
+    pub fn message_ids(&self, chat_id: i64) -> rusqlite::Result<HashSet<i32>> {
+        let conn = self.conn.lock().unwrap();
+        let mut query = conn.prepare_cached("SELECT id FROM messages WHERE chat_id = ?1")?;
+        let rows = query.query_map(params![chat_id], |row| row.get(0))?;
+        rows.collect()
+    }
+
+    /// Finds messages matching `query`, newest first, along with a snippet of
+    /// the matched text where `SNIPPET_START`/`SNIPPET_END` mark the hits.
+    pub fn search(&self, query: &SearchQuery, limit: i32) -> rusqlite::Result<Vec<SearchHit>> {
+        let conn = self.conn.lock().unwrap();
+        let match_expression = fts_match_expression(&query.text);
+        let snippet = if match_expression.is_some() {
+            format!(
+                "snippet(message_search, -1, '{}', '{}', '…', 16)",
+                SNIPPET_START, SNIPPET_END
+            )
+        } else {
+            "NULL".to_string()
+        };
+        let sql = format!(
+            "SELECT m.data, {snippet}
+             FROM message_search JOIN messages m ON m.rowid = message_search.rowid
+             WHERE {matches}
+               AND (?2 IS NULL OR m.chat_id = ?2)
+               AND (?3 IS NULL OR m.sender LIKE '%' || ?3 || '%' ESCAPE '\\')
+               AND (?4 IS NULL OR m.message_type = ?4)
+               AND (?5 IS NULL OR m.timestamp >= ?5)
+               AND (?6 IS NULL OR m.timestamp < ?6)
+               AND (?7 = 0 OR message_search.has_link = 1)
+               AND (?8 = 0 OR message_search.has_media = 1)
+             ORDER BY m.timestamp DESC, m.id DESC
+             LIMIT ?9",
+            snippet = snippet,
+            matches = if match_expression.is_some() { "message_search MATCH ?1" } else { "?1 IS NULL" },
+        );
+
+        let mut statement = conn.prepare(&sql)?;
+        let rows = statement.query_map(
+            params![
+                match_expression,
+                query.chat_id,
+                query.sender.as_deref().filter(|sender| !sender.is_empty()).map(escape_like),
+                query.message_type.as_ref().map(|message_type| message_type.to_string()),
+                query.from,
+                query.until,
+                query.has_link,
+                query.has_media,
+                limit,
+            ],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?)),
+        )?;
+        rows.map(|row| {
+            let (data, snippet) = row?;
+            Ok(SearchHit {
+                message: from_json(&data)?,
+                snippet,
+            })
+        })
+        .collect()
+    }
+
+    /// Remembers the display name of a chat for when Telegram is unreachable.
+    pub fn store_chat_name(&self, chat_id: i64, name: &str) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO chats (id, name) VALUES (?1, ?2)",
+            params![chat_id, name],
+        )?;
+        Ok(())
+    }
+
+    pub fn chat_name(&self, chat_id: i64) -> rusqlite::Result<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row("SELECT name FROM chats WHERE id = ?1", params![chat_id], |row| row.get(0))
+            .optional()
+    }
+
+    /// The archived message the document `document_id` was last seen in.
+    pub fn document_message(&self, document_id: i64) -> rusqlite::Result<Option<ChatMessage>> {
+        let conn = self.conn.lock().unwrap();
+        let location = conn
+            .query_row(
+                "SELECT chat_id, message_id FROM documents WHERE id = ?1",
+                params![document_id],
+                |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i32>(1)?)),
+            )
+            .optional()?;
+        match location {
+            Some((chat_id, message_id)) => load_message(&conn, chat_id, message_id),
+            None => Ok(None),
+        }
+    }
+
+    /// Replaces the remembered topics of forum `chat_id` with `topics`, for
+    /// when Telegram is unreachable.
+    pub fn store_forum_topics(&self, chat_id: i64, topics: &[ForumTopic]) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM forum_topics WHERE chat_id = ?1", params![chat_id])?;
+        for (position, topic) in topics.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO forum_topics (chat_id, id, position, data) VALUES (?1, ?2, ?3, ?4)",
+                params![chat_id, topic.id, position as i64, to_json(topic)?],
+            )?;
+        }
+        tx.commit()
+    }
+
+    pub fn forum_topics(&self, chat_id: i64) -> rusqlite::Result<Vec<ForumTopic>> {
+        let conn = self.conn.lock().unwrap();
+        let mut query = conn.prepare_cached("SELECT data FROM forum_topics WHERE chat_id = ?1 ORDER BY position")?;
+        let rows = query.query_map(params![chat_id], |row| row.get::<_, String>(0))?;
+        rows.map(|data| data.and_then(|data| from_json(&data))).collect()
+    }
+
+    pub fn backfill_progress(&self, chat_id: i64) -> rusqlite::Result<BackfillProgress> {
+        let conn = self.conn.lock().unwrap();
+        let progress = conn
+            .query_row(
+                "SELECT oldest_id, complete, synced_id FROM backfill WHERE chat_id = ?1",
+                params![chat_id],
+                |row| Ok(BackfillProgress {
+                    oldest_id: row.get(0)?,
+                    complete: row.get(1)?,
+                    synced_id: row.get(2)?,
+                }),
+            )
+            .optional()?;
+        Ok(progress.unwrap_or_default())
+    }
+
+    pub fn set_backfill_progress(&self, chat_id: i64, progress: &BackfillProgress) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO backfill (chat_id, oldest_id, complete, synced_id) VALUES (?1, ?2, ?3, ?4)",
+            params![chat_id, progress.oldest_id, progress.complete, progress.synced_id],
+        )?;
+        Ok(())
+    }
+}
+
+fn add_missing_columns(conn: &Connection) -> rusqlite::Result<()> {
+    for (table, column, definition, fill) in ADDED_COLUMNS {
+        let exists: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info(?1) WHERE name = ?2",
+            params![table, column],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            conn.execute_batch(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))?;
+            if let Some(fill) = fill {
+                conn.execute_batch(fill)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn load_message(conn: &Connection, chat_id: i64, id: i32) -> rusqlite::Result<Option<ChatMessage>> {
+    let data = conn
+        .prepare_cached("SELECT data FROM messages WHERE chat_id = ?1 AND id = ?2")?
+        .query_row(params![chat_id, id], |row| row.get::<_, String>(0))
+        .optional()?;
+    data.map(|data| from_json(&data)).transpose()
+}
+
+fn save_message(conn: &Connection, message: &ChatMessage) -> rusqlite::Result<()> {
+    // Upsert rather than replace so the rowid, which the search index
+    // refers to, stays the same
+    let rowid: i64 = conn.prepare_cached(
+        "INSERT INTO messages (chat_id, id, timestamp, sender, message_type, text, data, topic_id)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+         ON CONFLICT (chat_id, id) DO UPDATE SET
+             timestamp = excluded.timestamp,
+             sender = excluded.sender,
+             message_type = excluded.message_type,
+             text = excluded.text,
+             data = excluded.data,
+             topic_id = excluded.topic_id
+         RETURNING rowid",
+    )?
+    .query_row(params![
+        message.chat_id,
+        message.id,
+        message.timestamp,
+        message.sender,
+        message.message_type.to_string(),
+        message.text,
+        to_json(message)?,
+        message.topic(),
+    ], |row| row.get(0))?;
+
+    // A deleted message can no longer be downloaded from
+    let document_id = message.media_info.as_ref().and_then(|media| media.document_id);
+    if let (Some(document_id), None) = (document_id, message.deleted_at) {
+        conn.prepare_cached(
+            "INSERT OR REPLACE INTO documents (id, chat_id, message_id) VALUES (?1, ?2, ?3)",
+        )?
+        .execute(params![document_id, message.chat_id, message.id])?;
+    }
+    index_message(conn, rowid, message)
+}
+
+/// Replaces the search index entry of the message stored at `rowid`.
+fn index_message(conn: &Connection, rowid: i64, message: &ChatMessage) -> rusqlite::Result<()> {
+    let media = message.media_info.as_ref();
+    conn.prepare_cached("DELETE FROM message_search WHERE rowid = ?1")?
+        .execute(params![rowid])?;
+    conn.prepare_cached(
+        "INSERT INTO message_search (rowid, text, caption, file_name, sender, has_link, has_media)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+    )?
+    .execute(params![
+        rowid,
+        message.text,
+        media.and_then(|media| media.caption.as_deref()).unwrap_or_default(),
+        media.and_then(|media| media.file_name.as_deref()).unwrap_or_default(),
+        message.sender,
+        message.has_link(),
+        message.has_media(),
+    ])?;
+    Ok(())
+}
+
+/// Indexes every archived message when the search index is new, e.g. for
+/// archives created before search existed.
+fn rebuild_search_index_if_empty(conn: &Connection) -> rusqlite::Result<()> {
+    let indexed: i64 = conn.query_row("SELECT COUNT(*) FROM message_search", [], |row| row.get(0))?;
+    if indexed > 0 {
+        return Ok(());
+    }
+
+    let mut query = conn.prepare("SELECT rowid, data FROM messages")?;
+    let rows = query.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?;
+    let mut count = 0;
+    for row in rows {
+        let (rowid, data) = row?;
+        index_message(conn, rowid, &from_json(&data)?)?;
+        count += 1;
+    }
+    if count > 0 {
+        eprintln!("Indexed {} archived messages for search", count);
+    }
+    Ok(())
+}
+
+/// Combines a freshly fetched `current` message with the archived `previous`
+/// copy of it.
+fn merge_versions(previous: ChatMessage, mut current: ChatMessage) -> ChatMessage {
+    // Imports may format text differently from Telegram; that is no edit
+    let changed = !previous.imported && current.content_differs(&previous);
+    current.deleted_at = current.deleted_at.or(previous.deleted_at);
+    // Looking up who a message was forwarded from can fail; a name found
+    // before is still right
+    current.forwarded_from = current.forwarded_from.or_else(|| previous.forwarded_from.clone());
+    // So do the last results seen of a poll
+    current.poll = current.poll.or_else(|| previous.poll.clone());
+    current.revisions = previous.revisions.clone();
+    if changed {
+        current.revisions.push(previous.to_revision());
+    }
+    current
+}
+
+/// Turns free text into an FTS5 query matching every word as a prefix, so
+/// that user input can never be parsed as query syntax.
+fn fts_match_expression(text: &str) -> Option<String> {
+    let terms: Vec<String> = text
+        .split_whitespace()
+        .map(|term| format!("\"{}\"*", term.replace('"', "\"\"")))
+        .collect();
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+/// Escapes the `LIKE` wildcards in `text`, for patterns using `ESCAPE '\'`.
+fn escape_like(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn to_json<T: Serialize>(value: &T) -> rusqlite::Result<String> {
+    serde_json::to_string(value).map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))
+}
+
+fn from_json<T: DeserializeOwned>(data: &str) -> rusqlite::Result<T> {
+    serde_json::from_str(data).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn message(id: i32, sender: &str, text: &str) -> ChatMessage {
+        serde_json::from_value(json!({
+            "id": id,
+            "text": text,
+            "formatted_text": [],
+            "timestamp": 1_700_000_000 + i64::from(id),
+            "sender": sender,
+            "chat_id": 1,
+            "message_type": "Text",
+            "media_info": null,
+            "reply_to": null,
+            "forwarded_from": null,
+        }))
+        .unwrap()
+    }
+
+    fn archive() -> Archive {
+        let archive = Archive::open(":memory:").unwrap();
+        archive
+            .store_messages(&[
+                message(1, "Alice", "say \"hello\" to everyone"),
+                message(2, "Bob_1", "hello AND goodbye"),
+                message(3, "Bob21", "NEAR(a b) OR c*"),
+                message(4, "100% Carol", "wildcards * everywhere"),
+                message(5, "Alice", "unrelated"),
+            ])
+            .unwrap();
+        archive
+    }
+
+    fn search(archive: &Archive, text: &str, sender: Option<&str>) -> Vec<i32> {
+        let query = SearchQuery {
+            text: text.to_string(),
+            sender: sender.map(str::to_string),
+            ..SearchQuery::default()
+        };
+        archive.search(&query, 10).unwrap().into_iter().map(|hit| hit.message.id).collect()
+    }
+
+    #[test]
+    fn match_expression_quotes_every_word() {
+        assert_eq!(fts_match_expression("  "), None);
+        assert_eq!(fts_match_expression("hello world").as_deref(), Some("\"hello\"* \"world\"*"));
+        assert_eq!(fts_match_expression("say \"hi\"").as_deref(), Some("\"say\"* \"\"\"hi\"\"\"*"));
+        assert_eq!(fts_match_expression("a OR b*").as_deref(), Some("\"a\"* \"OR\"* \"b*\"*"));
+    }
+
+    #[test]
+    fn search_treats_query_syntax_as_text() {
+        let archive = archive();
+        assert_eq!(search(&archive, "\"hello\"", None), vec![2, 1]);
+        assert_eq!(search(&archive, "hello AND", None), vec![2]);
+        assert_eq!(search(&archive, "NEAR(a", None), vec![3]);
+        assert_eq!(search(&archive, "OR c*", None), vec![3]);
+        assert_eq!(search(&archive, "*", None), Vec::<i32>::new());
+        assert_eq!(search(&archive, "wild", None), vec![4]);
+    }
+
+    #[test]
+    fn sender_filter_matches_wildcards_literally() {
+        let archive = archive();
+        assert_eq!(search(&archive, "", Some("o_2")), Vec::<i32>::new());
+        assert_eq!(search(&archive, "", Some("b_1")), vec![2]);
+        assert_eq!(search(&archive, "", Some("%")), vec![4]);
+        assert_eq!(search(&archive, "", Some("ali")), vec![5, 1]);
+        assert_eq!(search(&archive, "hello", Some("ali")), vec![1]);
+    }
+
+    #[test]
+    fn context_around_a_hit() {
+        let archive = archive();
+        let ids = |messages: Vec<ChatMessage>| messages.into_iter().map(|message| message.id).collect::<Vec<_>>();
+        assert_eq!(ids(archive.messages_before(1, None, Some(3), 10).unwrap()), vec![1, 2]);
+        assert_eq!(ids(archive.messages_before(1, None, Some(3), 1).unwrap()), vec![2]);
+        assert_eq!(ids(archive.messages_after(1, 3, 10).unwrap()), vec![4, 5]);
+        assert_eq!(ids(archive.messages_before(1, None, None, 2).unwrap()), vec![4, 5]);
+    }
+}
@@ -0,0 +1,133 @@
+use std::time::Duration;
+use grammers_client::Client;
+use tokio::task::JoinHandle;
+use crate::archive::{Archive, BackfillProgress};
+use crate::telegram::{convert_message, find_chat, fetch_raw_details, TelegramError, TelegramService};
+
+/// Messages written to the archive per transaction; Telegram returns at most
+/// this many per history request anyway.
+const BATCH_SIZE: usize = 100;
+
+/// Longest wait before trying a chat again after errors other than
+/// FLOOD_WAIT, which says how long to wait itself.
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Brings the archive of `chat_id` up to date with Telegram.
+///
+/// Messages newer than the last complete sync are fetched first, then the
+/// walk towards the start of the chat resumes from wherever the previous run
+/// stopped. Either step can be interrupted at any point without leaving gaps.
+pub async fn backfill_chat(client: &Client, archive: &Archive, chat_id: i64) -> Result<(), TelegramError> {
+    let chat = find_chat(client, chat_id).await?;
+    let mut progress = archive.backfill_progress(chat_id)?;
+
+    // The update loop starts before the backfill, so the newest archived
+    // message says nothing about what was missed while nobody was syncing.
+    // Without `synced_id`, from archives that predate it, the whole history
+    // is walked again.
+    if progress.synced_id.is_none() {
+        progress = BackfillProgress::default();
+    }
+
+    if let Some(synced_id) = progress.synced_id {
+        // Newest first, so an interrupted run has to start over from the top
+        let mut iter = client.iter_messages(chat);
+        let mut newest_id = synced_id;
+        let mut batch = Vec::new();
+        while let Some(message) = iter.next().await? {
+            if message.id() <= synced_id {
+                break;
+            }
+            newest_id = newest_id.max(message.id());
+            batch.push(convert_message(client, &message, chat_id));
+            if batch.len() >= BATCH_SIZE {
+                fetch_raw_details(client, chat, &mut batch).await?;
+                archive.store_messages(&batch)?;
+                batch.clear();
+            }
+        }
+        fetch_raw_details(client, chat, &mut batch).await?;
+        archive.store_messages(&batch)?;
+        progress.synced_id = Some(newest_id);
+        archive.set_backfill_progress(chat_id, &progress)?;
+    }
+
+    if progress.complete {
+        return Ok(());
+    }
+
+    let mut iter = client.iter_messages(chat);
+    if let Some(oldest_id) = progress.oldest_id {
+        iter = iter.offset_id(oldest_id);
+    }
+
+    let mut batch = Vec::new();
+    loop {
+        let message = iter.next().await?;
+        if let Some(message) = &message {
+            batch.push(convert_message(client, message, chat_id));
+        }
+
+        if batch.len() >= BATCH_SIZE || message.is_none() {
+            fetch_raw_details(client, chat, &mut batch).await?;
+            archive.store_messages(&batch)?;
+            // The first page of a fresh walk starts at the newest message
+            if progress.synced_id.is_none() {
+                progress.synced_id = batch.first().map(|m| m.id);
+            }
+            progress.oldest_id = batch.last().map(|m| m.id).or(progress.oldest_id);
+            progress.complete = message.is_none();
+            archive.set_backfill_progress(chat_id, &progress)?;
+            eprintln!("Backfilled {} messages of chat {}, oldest id {:?}", batch.len(), chat_id, progress.oldest_id);
+            batch.clear();
+        }
+
+        if progress.complete {
+            return Ok(());
+        }
+    }
+}
+
+/// Runs `backfill_chat` in the background on the shared client for each of
+/// `chat_ids`, one chat after the other.
+///
+/// Archive writes happen between awaits, so aborting the task never leaves a
+/// batch half written.
+pub fn spawn_backfill(telegram: TelegramService, archive: Archive, chat_ids: Vec<i64>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        for chat_id in chat_ids {
+            match backfill_with_retry(&telegram, &archive, chat_id).await {
+                Ok(()) => eprintln!("Archive of chat {} is complete", chat_id),
+                Err(e) => eprintln!("Backfill of chat {} stopped: {}", chat_id, e),
+            }
+        }
+    })
+}
+
+/// Runs `backfill_chat` until it succeeds or fails in a way waiting cannot
+/// fix. Each attempt resumes from the progress saved by the one before.
+async fn backfill_with_retry(telegram: &TelegramService, archive: &Archive, chat_id: i64) -> Result<(), TelegramError> {
+    let mut backoff = Duration::from_secs(1);
+    loop {
+        let result = telegram
+            .run(|client| {
+                let archive = archive.clone();
+                async move { backfill_chat(&client, &archive, chat_id).await }
+            })
+            .await;
+        let (delay, error) = match result {
+            Ok(()) => return Ok(()),
+            Err(e @ (TelegramError::NotAuthorized | TelegramError::ChatNotFound { .. } | TelegramError::NoAccess { .. })) => {
+                return Err(e);
+            }
+            Err(e @ TelegramError::FloodWait { seconds }) => (Duration::from_secs(seconds.into()), e),
+            Err(e) => {
+                let delay = backoff;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                (delay, e)
+            }
+        };
+        eprintln!("Backfill of chat {} interrupted, retrying in {:?}: {}", chat_id, delay, error);
+        tokio::time::sleep(delay).await;
+    }
+}
@@ -1,13 +1,18 @@
 use leptos::prelude::*;
 use leptos::html;
+use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
-use crate::telegram::ChatMessage;
+use crate::search::{get_message_context, SearchPanel};
+use crate::telegram::{
+    format_amount, thumbnail_file_name, votes, ChatMessage, ForumTopic, GeoPoint, MediaDetails, MessageType, Poll,
+    ReplyPreview, TelegramError, WebPagePreview,
+};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ChatState {
     pub messages: Vec<ChatMessage>,
     pub loading: bool,
-    pub error: Option<String>,
+    pub error: Option<TelegramError>,
     pub chat_id: Option<i64>,
     pub has_more_history: bool,
 }
@@ -24,53 +29,263 @@ impl Default for ChatState {
     }
 }
 
+/// Loads the latest messages of `chat_id`, or of one of its forum topics,
+/// through the server's shared Telegram client.
+#[server]
+pub async fn get_messages(chat_id: i64, topic: Option<i32>) -> Result<Vec<ChatMessage>, TelegramError> {
+    let mut messages = load_history_page(chat_id, topic, None).await?;
+    crate::downloads::attach_custom_emoji(&mut messages);
+    expect_context::<crate::state::AppState>().archive.attach_reply_previews(&mut messages)?;
+    Ok(messages)
+}
+
+/// Loads the page of messages of `chat_id` directly preceding `offset_id`.
+#[server]
+pub async fn get_older_messages(chat_id: i64, topic: Option<i32>, offset_id: i32) -> Result<Vec<ChatMessage>, TelegramError> {
+    let mut messages = load_history_page(chat_id, topic, Some(offset_id)).await?;
+    crate::downloads::attach_custom_emoji(&mut messages);
+    expect_context::<crate::state::AppState>().archive.attach_reply_previews(&mut messages)?;
+    Ok(messages)
+}
+
+/// Lists the topics of `chat_id` if it is a forum. When Telegram cannot be
+/// reached, the topics it last listed are served from the archive.
+#[server]
+pub async fn list_forum_topics(chat_id: i64) -> Result<Vec<ForumTopic>, TelegramError> {
+    use crate::state::AppState;
+    use crate::telegram::get_forum_topics;
+
+    let state = expect_context::<AppState>();
+    if !state.chats.contains(&chat_id) {
+        return Err(TelegramError::ChatNotFound { chat: chat_id.to_string() });
+    }
+    match state.telegram.run(|client| async move { get_forum_topics(&client, chat_id).await }).await {
+        Ok(topics) => {
+            state.archive.store_forum_topics(chat_id, &topics)?;
+            Ok(topics)
+        }
+        Err(e) => {
+            eprintln!("Listing topics of chat {} from the archive only: {}", chat_id, e);
+            Ok(state.archive.forum_topics(chat_id)?)
+        }
+    }
+}
+
+/// The message `message_id` of `chat_id` as shown above replies to it. Messages
+/// the archive does not have yet are fetched from Telegram and archived;
+/// `None` if Telegram no longer has it either.
+#[server]
+pub async fn get_reply_preview(chat_id: i64, message_id: i32) -> Result<Option<ReplyPreview>, TelegramError> {
+    use crate::state::AppState;
+    use crate::telegram::{convert_message, fetch_raw_details, find_chat};
+
+    let state = expect_context::<AppState>();
+    if !state.chats.contains(&chat_id) {
+        return Err(TelegramError::ChatNotFound { chat: chat_id.to_string() });
+    }
+    if let Some(message) = state.archive.message(chat_id, message_id)? {
+        return Ok(Some(ReplyPreview::of(&message)));
+    }
+
+    let fetched = state.telegram
+        .run(|client| async move {
+            let chat = find_chat(&client, chat_id).await?;
+            let message = client.get_messages_by_id(chat, &[message_id]).await?.pop().flatten();
+            let mut fetched: Vec<ChatMessage> = message
+                .map(|message| convert_message(&client, &message, chat_id))
+                .into_iter()
+                .collect();
+            if let Err(e) = fetch_raw_details(&client, chat, &mut fetched).await {
+                eprintln!("Failed to fetch the details of message {}: {}", message_id, e);
+            }
+            Ok(fetched)
+        })
+        .await?;
+    state.archive.store_messages(&fetched)?;
+    Ok(fetched.first().map(ReplyPreview::of))
+}
+
+/// Serves a history page from the archive, topping it up from Telegram while
+/// the backfill has not reached that far yet.
+#[cfg(feature = "ssr")]
+async fn load_history_page(chat_id: i64, topic: Option<i32>, offset_id: Option<i32>) -> Result<Vec<ChatMessage>, TelegramError> {
+    use crate::state::AppState;
+    use crate::telegram::{get_chat_history, get_topic_history, GENERAL_TOPIC_ID};
+
+    let state = expect_context::<AppState>();
+    let page_size = crate::config::config().page_size;
+    if !state.chats.contains(&chat_id) {
+        return Err(TelegramError::ChatNotFound { chat: chat_id.to_string() });
+    }
+
+    let archived = state.archive.messages_before(chat_id, topic, offset_id, page_size)?;
+    let backfill_complete = state.archive.backfill_progress(chat_id)?.complete;
+    if archived.len() as i32 >= page_size || backfill_complete {
+        return Ok(archived);
+    }
+
+    let fetched = state.telegram
+        .run(|client| async move {
+            match topic {
+                Some(topic_id) if topic_id != GENERAL_TOPIC_ID => {
+                    get_topic_history(&client, chat_id, topic_id, page_size, offset_id).await
+                }
+                _ => get_chat_history(&client, chat_id, page_size, offset_id).await,
+            }
+        })
+        .await;
+    match fetched {
+        Ok(fetched) => {
+            state.archive.store_messages(&fetched)?;
+            Ok(state.archive.messages_before(chat_id, topic, offset_id, page_size)?)
+        }
+        // Telegram is unreachable, but whatever was archived is still worth showing
+        Err(e) if !archived.is_empty() => {
+            eprintln!("Serving archived messages only: {}", e);
+            Ok(archived)
+        }
+        Err(e) => Err(e),
+    }
+}
+
 #[component]
-pub fn ChatInterface() -> impl IntoView {
+pub fn ChatInterface(
+    chat_id: i64,
+    /// Forum topic to show instead of the whole chat.
+    topic: Option<i32>,
+    #[prop(into)] name: Signal<Option<String>>,
+) -> impl IntoView {
     let scroll_container_ref = NodeRef::<html::Div>::new();
     let is_auto_scroll = RwSignal::new(true);
     
-    // Use Resource::new for SSR data loading
-    let messages_resource: Resource<Result<Vec<ChatMessage>, String>> = Resource::new(
+    let messages_resource: Resource<Result<Vec<ChatMessage>, TelegramError>> = Resource::new(
         || (),
-        move |_| async move {
-            #[cfg(feature = "ssr")]
-            {
-                use crate::telegram::{create_telegram_client, get_chat_history};
-                use crate::config::TARGET_CHAT;
-                
-                match create_telegram_client().await {
-                    Ok(client) => {
-                        match get_chat_history(&client, *TARGET_CHAT, 50).await {
-                            Ok(messages) => Ok(messages),
-                            Err(e) => Err(format!("Failed to load messages: {}", e)),
-                        }
-                    }
-                    Err(e) => Err(format!("Failed to connect to Telegram: {}", e)),
+        move |_| get_messages(chat_id, topic)
+    );
+
+    // Older pages loaded by scrolling up, kept in front of the initial page
+    let history = RwSignal::new(ChatState::default());
+
+    let load_older = move || {
+        let state = history.get_untracked();
+        if state.loading || !state.has_more_history {
+            return;
+        }
+        let Some(Ok(initial)) = messages_resource.get_untracked() else {
+            return;
+        };
+        let oldest = state.messages.first().or(initial.first()).map(|m| m.id);
+        let Some(oldest) = oldest else {
+            history.update(|state| state.has_more_history = false);
+            return;
+        };
+
+        history.update(|state| {
+            state.loading = true;
+            state.error = None;
+        });
+        let (previous_top, previous_height) = scroll_container_ref
+            .get_untracked()
+            .map(|container| (container.scroll_top(), container.scroll_height()))
+            .unwrap_or_default();
+
+        leptos::task::spawn_local(async move {
+            match get_older_messages(chat_id, topic, oldest).await {
+                Ok(mut page) => history.update(|state| {
+                    // The page size is a server setting, so only an empty page
+                    // reliably marks the start of the chat
+                    state.has_more_history = !page.is_empty();
+                    page.append(&mut state.messages);
+                    state.messages = page;
+                }),
+                Err(e) => history.update(|state| {
+                    state.error = Some(e);
+                }),
+            }
+            history.update(|state| state.loading = false);
+
+            // Keep the message that was at the top of the viewport in place
+            request_animation_frame(move || {
+                if let Some(container) = scroll_container_ref.get_untracked() {
+                    container.set_scroll_top(container.scroll_height() - previous_height + previous_top);
                 }
+            });
+        });
+    };
+
+    // Messages pushed by the server after the history was loaded
+    let live_messages = RwSignal::new(Vec::<ChatMessage>::new());
+    // Latest version of messages edited or deleted since they were loaded
+    let updated_messages = RwSignal::new(HashMap::<i32, ChatMessage>::new());
+
+    #[cfg(feature = "hydrate")]
+    Effect::new(move |_| {
+        use send_wrapper::SendWrapper;
+        use wasm_bindgen::{closure::Closure, JsCast};
+
+        let Ok(source) = web_sys::EventSource::new(&format!("/live/{}", chat_id)) else {
+            return;
+        };
+        let on_message = Closure::<dyn FnMut(web_sys::MessageEvent)>::new(move |event: web_sys::MessageEvent| {
+            let message = parse_live_message(&event)
+                .filter(|message| topic.is_none_or(|topic| message.topic() == topic));
+            if let Some(message) = message {
+                live_messages.update(|messages| messages.push(message));
             }
-            #[cfg(not(feature = "ssr"))]
-            {
-                // Client-side fallback
-                Ok(vec![
-                    ChatMessage {
-                        id: 1,
-                        text: "Welcome to the chat! (Demo mode - not connected to Telegram)".to_string(),
-                        formatted_text: vec![],
-                        timestamp: chrono::Utc::now().timestamp(),
-                        sender: "System".to_string(),
-                        chat_id: -1001234567890,
-                        message_type: crate::telegram::MessageType::System,
-                        media_info: None,
-                        reply_to: None,
-                        forwarded_from: None,
-                    }
-                ])
+        });
+        let on_updated = Closure::<dyn FnMut(web_sys::MessageEvent)>::new(move |event: web_sys::MessageEvent| {
+            if let Some(message) = parse_live_message(&event) {
+                updated_messages.update(|updated| {
+                    updated.insert(message.id, message);
+                });
             }
+        });
+        let _ = source.add_event_listener_with_callback("message", on_message.as_ref().unchecked_ref());
+        let _ = source.add_event_listener_with_callback("updated", on_updated.as_ref().unchecked_ref());
+
+        let subscription = SendWrapper::new((source, on_message, on_updated));
+        on_cleanup(move || subscription.take().0.close());
+    });
+
+    let show_search = RwSignal::new(false);
+    // Archived messages around a search hit, shown instead of the latest ones
+    let context = RwSignal::new(None::<(i32, Result<Vec<ChatMessage>, TelegramError>)>);
+
+    let jump_to = move |message_id: i32| {
+        is_auto_scroll.set(false);
+        leptos::task::spawn_local(async move {
+            let result = get_message_context(chat_id, message_id).await;
+            context.set(Some((message_id, result)));
+            request_animation_frame(move || {
+                if let Some(element) = document().get_element_by_id(&format!("message-{}", message_id)) {
+                    element.scroll_into_view_with_bool(true);
+                }
+            });
+        });
+    };
+
+    // Shows a replied-to message: in place if it is loaded, otherwise in
+    // context like a search hit
+    let show_message = Callback::new(move |message_id: i32| {
+        match document().get_element_by_id(&format!("message-{}", message_id)) {
+            Some(element) => element.scroll_into_view_with_bool(true),
+            None => jump_to(message_id),
         }
-    );
+    });
+
+    let back_to_latest = move || {
+        context.set(None);
+        is_auto_scroll.set(true);
+        request_animation_frame(move || {
+            if let Some(container) = scroll_container_ref.get_untracked() {
+                container.set_scroll_top(container.scroll_height());
+            }
+        });
+    };
 
     // Auto-scroll to bottom when new messages arrive
     Effect::new(move |_| {
+        live_messages.track();
         let messages = messages_resource.get();
         if let Some(Ok(messages)) = messages {
             if is_auto_scroll.get() && !messages.is_empty() {
@@ -91,25 +306,44 @@ pub fn ChatInterface() -> impl IntoView {
             // Check if scrolled to bottom (with small tolerance)
             let at_bottom = scroll_top + client_height >= scroll_height - 10;
             is_auto_scroll.set(at_bottom);
+
+            if scroll_top < 50 && context.with_untracked(Option::is_none) {
+                load_older();
+            }
         }
     };
 
     view! {
         <div class="chat-container">
             <div class="chat-header">
-                <h2>"Telegram Chat"</h2>
-                {move || {
-                    #[cfg(feature = "ssr")]
-                    {
-                        use crate::config::TARGET_CHAT;
-                        view! { <span class="chat-id">"Chat ID: " {*TARGET_CHAT}</span> }.into_any()
-                    }
-                    #[cfg(not(feature = "ssr"))]
-                    {
-                        view! { <span class="chat-id">"Chat ID: Demo Mode"</span> }.into_any()
-                    }
-                }}
+                <h2>{move || name.get().unwrap_or_else(|| "Telegram Chat".to_string())}</h2>
+                <div class="chat-header-actions">
+                    <TopicPicker chat_id=chat_id selected=topic />
+                    <span class="chat-id">"Chat ID: " {chat_id}</span>
+                    <details class="export-menu">
+                        <summary title="Export">"⤓"</summary>
+                        <div class="export-options">
+                            {[("json", "Telegram JSON"), ("html", "HTML page"), ("markdown", "Markdown"), ("text", "Plain text")]
+                                .into_iter()
+                                .map(|(format, label)| view! {
+                                    <a href=format!("/export/{}?format={}", chat_id, format) download>{label}</a>
+                                })
+                                .collect::<Vec<_>>()}
+                        </div>
+                    </details>
+                    <button
+                        class="search-toggle"
+                        title="Search"
+                        on:click=move |_| show_search.update(|shown| *shown = !*shown)
+                    >
+                        "🔍"
+                    </button>
+                </div>
             </div>
+
+            {move || show_search.get().then(|| view! {
+                <SearchPanel chat_id=chat_id on_select=Callback::new(move |message: ChatMessage| jump_to(message.id)) />
+            }.into_any())}
             
             <div 
                 class="messages-container"
@@ -124,11 +358,40 @@ pub fn ChatInterface() -> impl IntoView {
                     }
                 >
                     {move || {
+                        if let Some((hit_id, result)) = context.get() {
+                            return view! {
+                                <div class="context-banner">
+                                    "Showing an earlier message in context. "
+                                    <button on:click=move |_| back_to_latest()>"Back to latest"</button>
+                                </div>
+                                {match result {
+                                    Ok(messages) => messages.into_iter().map(|message| {
+                                        let message = updated_messages.with(|updated| {
+                                            updated.get(&message.id).cloned()
+                                        }).unwrap_or(message);
+                                        let highlighted = message.id == hit_id;
+                                        view! { <MessageComponent message=message on_jump=show_message highlighted=highlighted /> }
+                                    }).collect::<Vec<_>>().into_any(),
+                                    Err(error) => view! {
+                                        <ErrorNotice error=error on_retry=move || jump_to(hit_id) />
+                                    }.into_any(),
+                                }}
+                            }.into_any();
+                        }
+
                         let messages = messages_resource.get();
                         
                         match messages {
                             None => view! { <div></div> }.into_any(),
-                            Some(Ok(messages)) => {
+                            Some(Ok(initial)) => {
+                                let state = history.get();
+                                let newest = initial.last().map(|m| m.id).unwrap_or(0);
+                                let mut messages = state.messages;
+                                messages.extend(initial);
+                                messages.extend(
+                                    live_messages.get().into_iter().filter(|m| m.id > newest)
+                                );
+
                                 if messages.is_empty() {
                                     view! {
                                         <div class="no-messages">
@@ -138,9 +401,18 @@ pub fn ChatInterface() -> impl IntoView {
                                 } else {
                                     view! {
                                         <>
+                                            {state.loading.then(|| view! {
+                                                <div class="loading-more">"Loading older messages..."</div>
+                                            })}
+                                            {state.error.map(|error| view! {
+                                                <ErrorNotice error=error on_retry=move || load_older() />
+                                            })}
                                             {messages.into_iter().map(|message| {
+                                                let message = updated_messages.with(|updated| {
+                                                    updated.get(&message.id).cloned()
+                                                }).unwrap_or(message);
                                                 view! {
-                                                    <MessageComponent message=message />
+                                                    <MessageComponent message=message on_jump=show_message />
                                                 }
                                             }).collect::<Vec<_>>()}
                                         </>
@@ -149,9 +421,7 @@ pub fn ChatInterface() -> impl IntoView {
                             }
                             Some(Err(error)) => {
                                 view! {
-                                    <div class="error-message">
-                                        {error}
-                                    </div>
+                                    <ErrorNotice error=error on_retry=move || messages_resource.refetch() />
                                 }.into_any()
                             }
                         }
@@ -182,90 +452,385 @@ pub fn ChatInterface() -> impl IntoView {
     }
 }
 
+/// Menu of the topics of a forum chat, each linking to
+/// `/chat/:id/topic/:topic`. Chats that are not forums get no menu.
 #[component]
-fn MessageComponent(message: ChatMessage) -> impl IntoView {
+fn TopicPicker(chat_id: i64, selected: Option<i32>) -> impl IntoView {
+    let topics = Resource::new(|| (), move |_| list_forum_topics(chat_id));
+
+    view! {
+        <Suspense fallback=|| ()>
+            {move || {
+                let topics = topics.get()?.ok().filter(|topics| !topics.is_empty())?;
+                let current = selected
+                    .and_then(|id| topics.iter().find(|topic| topic.id == id))
+                    .map_or_else(|| "All topics".to_string(), |topic| topic.title.clone());
+                Some(view! {
+                    <details class="topic-menu">
+                        <summary title="Topics">{current}</summary>
+                        <div class="topic-options">
+                            <a href=format!("/chat/{}", chat_id) class:selected=selected.is_none()>"All topics"</a>
+                            {topics.into_iter().map(|topic| view! {
+                                <a
+                                    href=format!("/chat/{}/topic/{}", chat_id, topic.id)
+                                    class:selected=selected == Some(topic.id)
+                                    class:closed=topic.closed
+                                >
+                                    <span class="topic-icon" style=format!("background: #{:06x}", topic.icon_color)></span>
+                                    <span class="topic-title">{topic.title}</span>
+                                    {topic.pinned.then(|| view! { <span class="topic-pinned" title="Pinned">"📌"</span> })}
+                                    {(topic.unread_count > 0).then(|| view! {
+                                        <span class="unread-badge">{topic.unread_count}</span>
+                                    })}
+                                </a>
+                            }).collect::<Vec<_>>()}
+                        </div>
+                    </details>
+                })
+            }}
+        </Suspense>
+    }
+}
+
+/// What a message replies to: the sender and start of the replied-to
+/// message. Replies to messages the archive does not have yet look them up
+/// when clicked; clicking a known one shows it.
+#[component]
+fn ReplyHeader(
+    chat_id: i64,
+    reply_to: i32,
+    preview: Option<ReplyPreview>,
+    #[prop(into)] on_jump: Callback<i32>,
+) -> impl IntoView {
+    let preview = RwSignal::new(preview);
+    let loading = RwSignal::new(false);
+    // The replied-to message is gone from Telegram, or the lookup failed
+    let missing = RwSignal::new(None::<String>);
+
+    let on_click = move |_| {
+        if preview.with_untracked(Option::is_some) {
+            on_jump.run(reply_to);
+            return;
+        }
+        if loading.get_untracked() {
+            return;
+        }
+        loading.set(true);
+        leptos::task::spawn_local(async move {
+            match get_reply_preview(chat_id, reply_to).await {
+                Ok(Some(found)) => {
+                    preview.set(Some(found));
+                    on_jump.run(reply_to);
+                }
+                Ok(None) => missing.set(Some("The message no longer exists".to_string())),
+                Err(e) => missing.set(Some(e.to_string())),
+            }
+            loading.set(false);
+        });
+    };
+
+    view! {
+        <button
+            class="reply-indicator"
+            class:reply-deleted=move || preview.with(|preview| preview.as_ref().is_some_and(|preview| preview.deleted))
+            on:click=on_click
+        >
+            {move || match (preview.get(), missing.get()) {
+                (Some(preview), _) => view! {
+                    <span class="reply-sender">{preview.sender}</span>
+                    <span class="reply-snippet">
+                        {if preview.snippet.is_empty() { "Media".to_string() } else { preview.snippet }}
+                    </span>
+                }.into_any(),
+                (None, Some(reason)) => view! {
+                    <span class="reply-snippet">{format!("↳ Reply to message #{}: {}", reply_to, reason)}</span>
+                }.into_any(),
+                (None, None) => view! {
+                    <span class="reply-snippet">
+                        {move || if loading.get() { "Loading…".to_string() } else { format!("↳ Reply to message #{}", reply_to) }}
+                    </span>
+                }.into_any(),
+            }}
+        </button>
+    }
+}
+
+/// Explains `error` and what can be done about it. A flood wait counts down
+/// and calls `on_retry` by itself once Telegram allows requests again.
+#[component]
+pub fn ErrorNotice(error: TelegramError, #[prop(into)] on_retry: Callback<()>) -> impl IntoView {
+    let countdown = RwSignal::new(match error {
+        TelegramError::FloodWait { seconds } => Some(seconds),
+        _ => None,
+    });
+
+    #[cfg(feature = "hydrate")]
+    if countdown.get_untracked().is_some() {
+        if let Ok(handle) = set_interval_with_handle(
+            move || match countdown.get_untracked() {
+                Some(0) => {
+                    countdown.set(None);
+                    on_retry.run(());
+                }
+                Some(seconds) => countdown.set(Some(seconds - 1)),
+                None => {}
+            },
+            std::time::Duration::from_secs(1),
+        ) {
+            on_cleanup(move || handle.clear());
+        }
+    }
+
+    let guidance = match &error {
+        TelegramError::NotAuthorized => {
+            Some("Log in by running `cargo run --bin cli-auth` on the server, then retry.".to_string())
+        }
+        TelegramError::ChatNotFound { .. } => {
+            Some("Check the chat id, username or link in the configured chats.".to_string())
+        }
+        TelegramError::NoAccess { .. } => {
+            Some("Join the chat with the logged-in account, or remove it from the configured chats.".to_string())
+        }
+        TelegramError::Network(_) => {
+            Some("The server reconnects on its own; check its internet connection if this persists.".to_string())
+        }
+        TelegramError::MediaDownloadFailed { .. } => {
+            Some("The file may no longer be available on Telegram.".to_string())
+        }
+        TelegramError::FloodWait { .. } | TelegramError::Rpc(_) | TelegramError::Server(_) => None,
+    };
+
+    view! {
+        <div class="error-message">
+            <div class="error-summary">{error.to_string()}</div>
+            {guidance.map(|guidance| view! { <div class="error-guidance">{guidance}</div> })}
+            {move || match countdown.get() {
+                Some(seconds) => view! {
+                    <div class="error-guidance">{format!("Retrying in {}s...", seconds)}</div>
+                }.into_any(),
+                None => view! {
+                    <button class="retry-button" on:click=move |_| on_retry.run(())>"Retry"</button>
+                }.into_any(),
+            }}
+        </div>
+    }
+}
+
+#[cfg(feature = "hydrate")]
+fn parse_live_message(event: &web_sys::MessageEvent) -> Option<ChatMessage> {
+    let data = event.data().as_string()?;
+    serde_json::from_str(&data)
+        .map_err(|e| leptos::logging::warn!("Ignoring malformed live message: {}", e))
+        .ok()
+}
+
+#[component]
+fn MessageComponent(
+    message: ChatMessage,
+    /// Shows the message with the given id, for replies.
+    #[prop(into)]
+    on_jump: Callback<i32>,
+    /// Marks the message a search jumped to.
+    #[prop(optional)]
+    highlighted: bool,
+) -> impl IntoView {
     let formatted_time = {
         use chrono::{DateTime, Utc};
         let dt = DateTime::<Utc>::from_timestamp(message.timestamp, 0)
-            .unwrap_or_else(|| Utc::now());
+            .unwrap_or_else(Utc::now);
         dt.format("%H:%M:%S").to_string()
     };
 
-    let message_class = format!("message message-type-{}", 
-        message.message_type.to_string()
+    let mut message_class = format!("message message-type-{}", 
+        message.message_type
     );
+    if message.deleted_at.is_some() {
+        message_class.push_str(" message-deleted");
+    }
+    if highlighted {
+        message_class.push_str(" message-highlighted");
+    }
+    let show_revisions = RwSignal::new(false);
+    let revisions = message.revisions.clone();
+    let link_preview = message.message_type == MessageType::WebPage;
+
+    // Joins, pins, calls and the like read as a single centered line
+    if message.service.is_some() {
+        let mut service_class = "service-message".to_string();
+        if highlighted {
+            service_class.push_str(" message-highlighted");
+        }
+        let pinned = message.reply_preview.clone().filter(|_| {
+            matches!(message.service, Some(crate::service::ServiceAction::MessagePinned))
+        });
+        return view! {
+            <div class={service_class} id=format!("message-{}", message.id)>
+                <span class="service-text">{message.text}</span>
+                {pinned.map(|preview| {
+                    let id = preview.id;
+                    view! {
+                        <button class="service-pinned" on:click=move |_| on_jump.run(id)>
+                            {preview.snippet}
+                        </button>
+                    }
+                })}
+                <span class="timestamp">{formatted_time}</span>
+            </div>
+        }
+        .into_any();
+    }
 
     view! {
-        <div class={message_class}>
-            // Reply indicator
-            {message.reply_to.map(|reply_id| {
-                view! {
-                    <div class="reply-indicator">
-                        "↳ Reply to message #{}" {reply_id}
-                    </div>
-                }.into_any()
+        <div class={message_class} id=format!("message-{}", message.id)>
+            {message.reply_to.map(|reply_to| view! {
+                <ReplyHeader chat_id=message.chat_id reply_to=reply_to preview=message.reply_preview.clone() on_jump=on_jump />
             })}
-            
-            // Forward indicator
-            {message.forwarded_from.as_ref().map(|from| {
+
+            {message.forward_source().map(|source| {
+                let date = message.forward.as_ref().map(|forward| format_timestamp(forward.date));
                 view! {
                     <div class="forward-indicator">
-                        "↪ Forwarded from: " {from.clone()}
+                        "↪ Forwarded from " <span class="forward-source">{source}</span>
+                        {date.map(|date| view! { <span class="forward-date">" · sent " {date}</span> })}
                     </div>
-                }.into_any()
+                }
             })}
             
             <div class="message-header">
                 <span class="sender">{message.sender}</span>
                 <div class="message-meta">
+                    {message.deleted_at.map(|at| view! {
+                        <span class="deleted-badge" title={format!("Deleted at {}", format_timestamp(at))}>"deleted"</span>
+                    })}
+                    {message.edited_at.map(|at| view! {
+                        <span class="edited-badge" title={format!("Edited at {}", format_timestamp(at))}>"edited"</span>
+                    })}
                     <span class="message-type-badge">{message.message_type.get_emoji()}</span>
                     <span class="timestamp">{formatted_time}</span>
                 </div>
             </div>
             
-            // Media info
-            {message.media_info.as_ref().map(|media| {
+            // Media info, except link previews which go under the text
+            {message.media_info.as_ref().filter(|_| !link_preview).map(|media| {
                 render_media_info(media)
             })}
             
-            <div class="message-text">
-                {render_formatted_text(&message.text, &message.formatted_text)}
-            </div>
+            // The text of polls only names the question
+            {match &message.poll {
+                Some(poll) => render_poll(poll),
+                None => view! {
+                    <div class="message-text">
+                        {render_formatted_text(&message.text, &message.formatted_text)}
+                    </div>
+                }.into_any(),
+            }}
+
+            {message.media_info.as_ref().filter(|_| link_preview).map(render_media_info)}
+
+            {(!revisions.is_empty()).then(|| {
+                let count = revisions.len();
+                view! {
+                    <div class="revisions">
+                        <button
+                            class="revisions-toggle"
+                            on:click=move |_| show_revisions.update(|shown| *shown = !*shown)
+                        >
+                            {move || if show_revisions.get() { "Hide earlier versions".to_string() } else { format!("Show {} earlier version(s)", count) }}
+                        </button>
+                        <Show when=move || show_revisions.get()>
+                            <ol class="revision-list">
+                                {revisions.iter().rev().map(|revision| view! {
+                                    <li class="revision">
+                                        <span class="revision-time">{format_timestamp(revision.timestamp)}</span>
+                                        <div class="message-text">
+                                            {render_formatted_text(&revision.text, &revision.formatted_text)}
+                                        </div>
+                                    </li>
+                                }).collect::<Vec<_>>()}
+                            </ol>
+                        </Show>
+                    </div>
+                }
+            })}
         </div>
     }
+    .into_any()
+}
+
+pub fn format_timestamp(timestamp: i64) -> String {
+    use chrono::{DateTime, Utc};
+    DateTime::<Utc>::from_timestamp(timestamp, 0)
+        .unwrap_or_else(Utc::now)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
 }
 
 fn render_media_info(media: &crate::telegram::MediaInfo) -> impl IntoView {
-    // Check if this is a sticker (webp image)
-    if let Some(mime) = &media.mime_type {
-        if mime == "image/webp" && media.file_name.as_ref().map_or(false, |name| name.starts_with("sticker_")) {
-            let file_name = media.file_name.as_ref().unwrap();
-            let sticker_url = format!("/stickers/{}", file_name);
-            
-            // Render sticker as actual image with fallback
-            return view! {
-                <div class="sticker-container">
-                    <img 
-                        src={sticker_url.clone()}
-                        alt="Sticker"
-                        class="sticker-image"
-                        loading="lazy"
-                        onerror="this.style.display='none'; this.nextElementSibling.style.display='flex';"
-                    />
-                    <div class="sticker-fallback" style="display: none;">
-                        "🎭"
-                        <div class="sticker-info">
-                            "Sticker (" {format_file_size(media.file_size.unwrap_or(0))} ")"
-                        </div>
+    if let Some(details) = &media.details {
+        return render_media_details(details);
+    }
+
+    // Stickers are WebP images, Lottie animations (TGS) or WebM videos
+    if let Some(file_name) = media.file_name.as_ref().filter(|name| name.starts_with("sticker_")) {
+        let sticker_url = format!("/stickers/{}", file_name);
+        let sticker = match media.mime_type.as_deref() {
+            Some("application/x-tgsticker") => view! {
+                <LottieSticker src=format!("/lottie/{}", file_name) />
+            }.into_any(),
+            Some("video/webm") => view! {
+                <video
+                    src={sticker_url}
+                    class="sticker-image"
+                    autoplay
+                    loop
+                    muted
+                    playsinline
+                    onerror="this.style.display='none'; this.nextElementSibling.style.display='flex';"
+                ></video>
+            }.into_any(),
+            _ => view! {
+                <img 
+                    src={sticker_url}
+                    alt="Sticker"
+                    class="sticker-image"
+                    loading="lazy"
+                    onerror="this.style.display='none'; this.nextElementSibling.style.display='flex';"
+                />
+            }.into_any(),
+        };
+
+        // Render sticker with fallback
+        return view! {
+            <div class="sticker-container">
+                {sticker}
+                <div class="sticker-fallback" style="display: none;">
+                    "🎭"
+                    <div class="sticker-info">
+                        "Sticker (" {format_file_size(media.file_size.unwrap_or(0))} ")"
                     </div>
                 </div>
-            }.into_any();
-        }
+            </div>
+        }.into_any();
+    }
+
+    if let (Some(photo_url), Some(thumbnail_url)) = (media.photo_url(), media.thumbnail_url()) {
+        return view! {
+            <PhotoViewer
+                photo_url=photo_url
+                thumbnail_url=thumbnail_url
+                width=media.width
+                height=media.height
+                file_size=media.file_size
+            />
+        }.into_any();
     }
 
-    // Regular media info display
+    // Regular media info display, with a player and download link for
+    // files that can be fetched
+    let media_url = media.media_url();
     view! {
         <div class="media-info">
+            {media_url.clone().and_then(|url| render_player(media, url))}
             {media.file_name.as_ref().map(|name| {
                 view! {
                     <div class="file-name">"📎 " {name.clone()}</div>
@@ -281,11 +846,350 @@ fn render_media_info(media: &crate::telegram::MediaInfo) -> impl IntoView {
                     <div class="mime-type">"Type: " {mime.clone()}</div>
                 }.into_any()
             })}
+            {media_url.map(|url| view! {
+                <a class="media-download" href=url download="">"⤓ Download"</a>
+            })}
         </div>
     }.into_any()
 }
 
-fn format_file_size(bytes: u64) -> String {
+/// A poll or quiz as a bar chart of its answers. Bars stay empty while the
+/// results are hidden.
+pub(crate) fn render_poll(poll: &Poll) -> AnyView {
+    let answers = poll.answers.iter().map(|answer| {
+        let percentage = poll.percentage(answer);
+        let width = format!("width: {}%", percentage.unwrap_or(0));
+        view! {
+            <li class="poll-answer" class:poll-chosen=answer.chosen class:poll-correct=answer.correct>
+                <div class="poll-answer-label">
+                    <span class="poll-answer-text">
+                        {answer.text.clone()}
+                        {answer.correct.then_some(" ✓")}
+                    </span>
+                    <span class="poll-percentage" title=answer.voters.map(votes)>
+                        {percentage.map(|percentage| format!("{}%", percentage))}
+                    </span>
+                </div>
+                <div class="poll-bar">
+                    <div class="poll-bar-fill" style=width></div>
+                </div>
+            </li>
+        }
+    }).collect::<Vec<_>>();
+
+    view! {
+        <div class="poll" class:poll-closed=poll.closed>
+            <div class="poll-question">{poll.question.clone()}</div>
+            <div class="poll-kind">{poll.kind_label()}</div>
+            <ul class="poll-answers">{answers}</ul>
+            <div class="poll-total">
+                {match poll.total_voters {
+                    Some(total) => votes(total),
+                    None => "Results are shown after voting".to_string(),
+                }}
+            </div>
+            {poll.solution.clone().map(|solution| view! {
+                <div class="poll-solution">"💡 " {solution}</div>
+            })}
+        </div>
+    }.into_any()
+}
+
+/// Media that is not a file: places, dice, games, invoices and link previews.
+fn render_media_details(details: &MediaDetails) -> AnyView {
+    match details.clone() {
+        MediaDetails::Location { point } => render_location(point),
+        MediaDetails::Venue { point, title, address, provider, venue_id } => {
+            render_venue(point, title, address, provider.zip(venue_id))
+        }
+        MediaDetails::LiveLocation { point, period, heading } => render_live_location(point, period, heading),
+        MediaDetails::Dice { emoji, value } => render_dice(emoji, value),
+        MediaDetails::Game { title, description, short_name } => render_game(title, description, short_name),
+        MediaDetails::Invoice { title, description, currency, total_amount, test } => {
+            render_invoice(title, description, format_amount(total_amount, &currency), test)
+        }
+        MediaDetails::WebPage(page) => render_web_page(page),
+    }
+}
+
+fn render_location(point: GeoPoint) -> AnyView {
+    view! {
+        <div class="media-details location-card">
+            <span class="details-icon">"📍"</span>
+            <div class="details-body">
+                <div class="details-title">"Location"</div>
+                <a class="map-link" href=point.map_url() target="_blank" rel="noopener noreferrer">{point.to_string()}</a>
+                {point.accuracy_radius.map(|radius| view! { <div class="details-note">"within " {radius} " m"</div> })}
+            </div>
+        </div>
+    }.into_any()
+}
+
+/// `place` is the directory the venue is listed in and its id there.
+fn render_venue(point: Option<GeoPoint>, title: String, address: String, place: Option<(String, String)>) -> AnyView {
+    view! {
+        <div class="media-details venue-card">
+            <span class="details-icon">"🏛️"</span>
+            <div class="details-body">
+                <div class="details-title">{title}</div>
+                {(!address.is_empty()).then(|| view! { <div class="details-text">{address}</div> })}
+                {point.map(|point| view! {
+                    <a class="map-link" href=point.map_url() target="_blank" rel="noopener noreferrer">{point.to_string()}</a>
+                })}
+                {place.map(|(provider, id)| view! { <div class="details-note">{provider} " · " {id}</div> })}
+            </div>
+        </div>
+    }.into_any()
+}
+
+fn render_live_location(point: Option<GeoPoint>, period: i32, heading: Option<i32>) -> AnyView {
+    // Sharing "until turned off" is sent as the largest period there is
+    let shared_for = if period == i32::MAX {
+        "until turned off".to_string()
+    } else if period >= 3600 {
+        format!("for {} h", period / 3600)
+    } else {
+        format!("for {} min", period / 60)
+    };
+    view! {
+        <div class="media-details live-location-card">
+            <span class="details-icon">"🛰️"</span>
+            <div class="details-body">
+                <div class="details-title">"Live location"</div>
+                {point.map(|point| view! {
+                    <a class="map-link" href=point.map_url() target="_blank" rel="noopener noreferrer">{point.to_string()}</a>
+                })}
+                <div class="details-note">
+                    "Shared " {shared_for}
+                    {heading.map(|heading| format!(" · heading {}°", heading))}
+                </div>
+            </div>
+        </div>
+    }.into_any()
+}
+
+fn render_dice(emoji: String, value: i32) -> AnyView {
+    let title = format!("{} rolled {}", emoji, value);
+    view! {
+        <div class="media-details dice-card" title=title>
+            <span class="dice-emoji">{emoji}</span>
+            <span class="dice-value">{value}</span>
+        </div>
+    }.into_any()
+}
+
+fn render_game(title: String, description: String, short_name: String) -> AnyView {
+    view! {
+        <div class="media-details game-card">
+            <span class="details-icon">"🎮"</span>
+            <div class="details-body">
+                <div class="details-title">{title}</div>
+                {(!description.is_empty()).then(|| view! { <div class="details-text">{description}</div> })}
+                {(!short_name.is_empty()).then(|| view! { <div class="details-note">{short_name}</div> })}
+            </div>
+        </div>
+    }.into_any()
+}
+
+fn render_invoice(title: String, description: String, amount: String, test: bool) -> AnyView {
+    view! {
+        <div class="media-details invoice-card">
+            <span class="details-icon">"🧾"</span>
+            <div class="details-body">
+                <div class="details-title">{title}</div>
+                {(!description.is_empty()).then(|| view! { <div class="details-text">{description}</div> })}
+                <div class="invoice-amount">
+                    {amount}
+                    {test.then(|| view! { <span class="invoice-test" title="Sent by a bot in test mode">"test"</span> })}
+                </div>
+            </div>
+        </div>
+    }.into_any()
+}
+
+fn render_web_page(page: WebPagePreview) -> AnyView {
+    let image = page.image.as_deref().map(|name| {
+        (format!("/photos/{}", thumbnail_file_name(name)), format!("/photos/{}", name))
+    });
+    let url = crate::formatting::safe_link(&page.url);
+    view! {
+        <a class="media-details link-preview" href=url target="_blank" rel="noopener noreferrer">
+            <div class="details-body">
+                {page.site_name.map(|site| view! { <div class="link-site">{site}</div> })}
+                {page.title.map(|title| view! { <div class="details-title">{title}</div> })}
+                {page.description.map(|description| view! { <div class="details-text">{description}</div> })}
+                <div class="details-note">{page.url}</div>
+            </div>
+            // Falls back to the full photo, which link previews may only have
+            {image.map(|(thumbnail, full)| view! {
+                <img
+                    class="link-image"
+                    src=thumbnail
+                    data-full=full
+                    alt=""
+                    loading="lazy"
+                    onerror="if (this.dataset.full) { this.src = this.dataset.full; this.dataset.full = ''; } else { this.style.display='none'; }"
+                />
+            })}
+        </a>
+    }.into_any()
+}
+
+/// An inline player for video and audio, including voice messages. Nothing
+/// is downloaded until it is played.
+fn render_player(media: &crate::telegram::MediaInfo, url: String) -> Option<AnyView> {
+    let mime = media.mime_type.as_deref()?;
+    if mime.starts_with("video/") {
+        Some(view! {
+            <video
+                class="media-player video-player"
+                src=url
+                width=media.width
+                height=media.height
+                controls
+                preload="none"
+            ></video>
+        }.into_any())
+    } else if mime.starts_with("audio/") {
+        Some(view! {
+            <audio class="media-player audio-player" src=url controls preload="none"></audio>
+        }.into_any())
+    } else {
+        None
+    }
+}
+
+/// An animated sticker, played by lottie-web. The player is loaded the first
+/// time a sticker is shown; until then, or if it cannot be loaded, the
+/// placeholder stays.
+///
+/// With `emoji` it is drawn inline as a custom emoji, with that emoji as the
+/// placeholder.
+#[component]
+fn LottieSticker(src: String, #[prop(optional)] emoji: Option<String>) -> impl IntoView {
+    let container = NodeRef::<leptos::html::Div>::new();
+    #[cfg(feature = "hydrate")]
+    {
+        let src = src.clone();
+        Effect::new(move || {
+            if let Some(container) = container.get() {
+                let animation = send_wrapper::SendWrapper::new(lottie_play(container.as_ref(), &src));
+                on_cleanup(move || lottie_stop(&animation));
+            }
+        });
+    }
+
+    match emoji {
+        Some(emoji) => view! {
+            <div class="custom-emoji lottie-emoji" node_ref=container data-src=src title=emoji.clone()>
+                <span class="lottie-placeholder">{emoji.clone()}</span>
+            </div>
+        }.into_any(),
+        None => view! {
+            <div class="sticker-image lottie-sticker" node_ref=container data-src=src>
+                <span class="lottie-placeholder">"🎭"</span>
+            </div>
+        }.into_any(),
+    }
+}
+
+#[cfg(feature = "hydrate")]
+#[wasm_bindgen::prelude::wasm_bindgen(inline_js = r#"
+// Vendored from lottie-web 5.12.2 (build/player/lottie_light.min.js) and
+// served with the other files in `public/`.
+const PLAYER_URL = "/lottie_light.min.js";
+let player;
+
+function loadPlayer() {
+    player ??= new Promise((resolve, reject) => {
+        const script = document.createElement("script");
+        script.src = PLAYER_URL;
+        script.onload = () => resolve(window.lottie);
+        script.onerror = reject;
+        document.head.appendChild(script);
+    });
+    return player;
+}
+
+export function play(container, path) {
+    const state = { stopped: false, animation: null };
+    loadPlayer().then((lottie) => {
+        if (state.stopped) return;
+        state.animation = lottie.loadAnimation({ container, path, renderer: "svg", loop: true, autoplay: true });
+        state.animation.addEventListener("DOMLoaded", () => container.querySelector(".lottie-placeholder")?.remove());
+    }, () => console.warn("Cannot load the Lottie player; animated stickers stay placeholders"));
+    return state;
+}
+
+export function stop(state) {
+    state.stopped = true;
+    state.animation?.destroy();
+}
+"#)]
+extern "C" {
+    #[wasm_bindgen(js_name = play)]
+    fn lottie_play(container: &wasm_bindgen::JsValue, path: &str) -> wasm_bindgen::JsValue;
+    #[wasm_bindgen(js_name = stop)]
+    fn lottie_stop(state: &wasm_bindgen::JsValue);
+}
+
+/// A photo shown as its thumbnail that opens full size in a lightbox.
+#[component]
+fn PhotoViewer(
+    photo_url: String,
+    thumbnail_url: String,
+    width: Option<u32>,
+    height: Option<u32>,
+    file_size: Option<u64>,
+) -> impl IntoView {
+    let open = RwSignal::new(false);
+    let handle = window_event_listener(leptos::ev::keydown, move |ev| {
+        if ev.key() == "Escape" && open.get_untracked() {
+            open.set(false);
+        }
+    });
+    on_cleanup(move || handle.remove());
+
+    let full_url = photo_url.clone();
+    view! {
+        <div class="photo-container">
+            // Imported photos have no thumbnail, so fall back to the photo
+            // and then to a placeholder
+            <img
+                src=thumbnail_url
+                data-full=photo_url
+                width=width
+                height=height
+                alt="Photo"
+                class="photo-thumbnail"
+                loading="lazy"
+                on:click=move |_| open.set(true)
+                onerror="if (this.dataset.full) { this.src = this.dataset.full; this.dataset.full = ''; } else { this.style.display='none'; this.nextElementSibling.style.display='flex'; }"
+            />
+            <div class="photo-fallback" style="display: none;">
+                "📷"
+                <div class="photo-info">
+                    "Photo" {file_size.map(|size| format!(" ({})", format_file_size(size)))}
+                </div>
+            </div>
+            {move || open.get().then(|| view! {
+                <div class="lightbox" on:click=move |_| open.set(false)>
+                    <img class="lightbox-image" src=full_url.clone() alt="Photo" />
+                    <a
+                        class="lightbox-original"
+                        href=full_url.clone()
+                        target="_blank"
+                        on:click=|ev| ev.stop_propagation()
+                    >
+                        "Open original"
+                    </a>
+                </div>
+            }.into_any())}
+        </div>
+    }
+}
+
+pub fn format_file_size(bytes: u64) -> String {
     const UNITS: &[&str] = &["B", "KB", "MB", "GB"];
     let mut size = bytes as f64;
     let mut unit_index = 0;
@@ -302,102 +1206,116 @@ fn format_file_size(bytes: u64) -> String {
     }
 }
 
-fn render_formatted_text(text: &str, entities: &[crate::telegram::TextEntity]) -> impl IntoView {
-    use crate::telegram::EntityType;
-    
+pub fn render_formatted_text(text: &str, entities: &[crate::telegram::TextEntity]) -> impl IntoView {
     if entities.is_empty() {
-        return view! { <span>{text}</span> }.into_any();
+        return view! { <span>{text.to_string()}</span> }.into_any();
     }
-    
-    let mut sorted_entities = entities.to_vec();
-    sorted_entities.sort_by_key(|e| e.offset);
-    
-    let mut result_parts = Vec::new();
-    let mut current_pos = 0;
-    
-    for entity in sorted_entities {
-        // Add text before the entity
-        if entity.offset > current_pos {
-            let before_text = &text[current_pos..entity.offset];
-            if !before_text.is_empty() {
-                result_parts.push(view! { <span>{before_text}</span> }.into_any());
+
+    let parts: Vec<AnyView> = crate::formatting::span_tree(text, entities).iter().map(render_span).collect();
+    view! {
+        <span class="formatted-text">
+            {parts}
+        </span>
+    }.into_any()
+}
+
+/// One span of formatted text with everything nested in it.
+fn render_span(span: &crate::formatting::Span) -> AnyView {
+    use crate::formatting::Span;
+    use crate::telegram::EntityType;
+
+    let (entity, entity_text, children) = match span {
+        Span::Text(text) => return view! { <span>{text.to_string()}</span> }.into_any(),
+        Span::Entity { entity, text, children } => (entity, text.to_string(), children),
+    };
+    let inner: Vec<AnyView> = children.iter().map(render_span).collect();
+
+    match entity.entity_type {
+        // Code, custom emoji and addresses show the text as it is, without
+        // anything nested in them
+        EntityType::Code => view! { <code class="inline-code">{entity_text}</code> }.into_any(),
+        EntityType::Pre => render_code_block(&entity_text, entity.language.as_deref()),
+        EntityType::CustomEmoji { .. } => render_custom_emoji(&entity_text, entity.url.as_deref()),
+        EntityType::Email => view! {
+            <a href=format!("mailto:{}", entity_text) class="email-link">{entity_text.clone()}</a>
+        }.into_any(),
+        EntityType::Phone => view! {
+            <a href=format!("tel:{}", entity_text) class="phone-link">{entity_text.clone()}</a>
+        }.into_any(),
+        EntityType::Bold => view! { <strong>{inner}</strong> }.into_any(),
+        EntityType::Italic => view! { <em>{inner}</em> }.into_any(),
+        EntityType::Link | EntityType::TextLink => {
+            match crate::formatting::safe_link(entity.url.as_deref().unwrap_or(&entity_text)) {
+                Some(href) => view! {
+                    <a href=href target="_blank" rel="noopener noreferrer" class="message-link">{inner}</a>
+                }.into_any(),
+                None => view! { <span class="message-link">{inner}</span> }.into_any(),
             }
         }
-        
-        // Add the formatted entity
-        let entity_end = entity.offset + entity.length;
-        let entity_text = &text[entity.offset..entity_end];
-        
-        let formatted_element = match entity.entity_type {
-            EntityType::Bold => {
-                // Telegram entities point to plain text - no markdown stripping needed
-                view! { <strong>{entity_text}</strong> }.into_any()
-            },
-            EntityType::Italic => {
-                // Telegram entities point to plain text - no markdown stripping needed
-                view! { <em>{entity_text}</em> }.into_any()
-            },
-            EntityType::Code => {
-                // Telegram entities point to plain text - no markdown stripping needed
-                view! { <code class="inline-code">{entity_text}</code> }.into_any()
-            },
-            EntityType::Pre => {
-                // Telegram entities point to plain text - no markdown stripping needed
-                view! { <pre class="code-block">{entity_text}</pre> }.into_any()
-            },
-            EntityType::Link | EntityType::TextLink => {
-                let href = entity.url.as_deref().unwrap_or(entity_text);
-                view! { 
-                    <a href={href} target="_blank" rel="noopener noreferrer" class="message-link">
-                        {entity_text}
-                    </a> 
-                }.into_any()
-            },
-            EntityType::Mention => view! { 
-                <span class="mention">{entity_text}</span> 
-            }.into_any(),
-            EntityType::Hashtag => view! { 
-                <span class="hashtag">{entity_text}</span> 
-            }.into_any(),
-            EntityType::BotCommand => view! { 
-                <span class="bot-command">{entity_text}</span> 
-            }.into_any(),
-            EntityType::Email => view! { 
-                <a href={format!("mailto:{}", entity_text)} class="email-link">
-                    {entity_text}
-                </a> 
-            }.into_any(),
-            EntityType::Phone => view! { 
-                <a href={format!("tel:{}", entity_text)} class="phone-link">
-                    {entity_text}
-                </a> 
-            }.into_any(),
-            EntityType::Underline => view! { 
-                <span class="underline">{entity_text}</span> 
-            }.into_any(),
-            EntityType::Strikethrough => view! { 
-                <span class="strikethrough">{entity_text}</span> 
+        EntityType::Mention => view! { <span class="mention">{inner}</span> }.into_any(),
+        EntityType::MentionName => match entity.user_id {
+            Some(user_id) => view! {
+                <a href=format!("tg://user?id={}", user_id) class="mention">{inner}</a>
             }.into_any(),
-            EntityType::Spoiler => view! { 
-                <span class="spoiler">{entity_text}</span> 
-            }.into_any(),
-        };
-        
-        result_parts.push(formatted_element);
-        current_pos = entity_end;
-    }
-    
-    // Add any remaining text
-    if current_pos < text.len() {
-        let remaining_text = &text[current_pos..];
-        if !remaining_text.is_empty() {
-            result_parts.push(view! { <span>{remaining_text}</span> }.into_any());
-        }
+            None => view! { <span class="mention">{inner}</span> }.into_any(),
+        },
+        EntityType::Hashtag => view! { <span class="hashtag">{inner}</span> }.into_any(),
+        EntityType::Cashtag => view! { <span class="cashtag">{inner}</span> }.into_any(),
+        EntityType::BotCommand => view! { <span class="bot-command">{inner}</span> }.into_any(),
+        EntityType::BankCard => view! { <span class="bank-card">{inner}</span> }.into_any(),
+        EntityType::Underline => view! { <span class="underline">{inner}</span> }.into_any(),
+        EntityType::Strikethrough => view! { <span class="strikethrough">{inner}</span> }.into_any(),
+        EntityType::Spoiler => view! { <span class="spoiler">{inner}</span> }.into_any(),
+        EntityType::Blockquote => view! { <blockquote class="message-quote">{inner}</blockquote> }.into_any(),
+        EntityType::ExpandableBlockquote => view! { <ExpandableQuote>{inner}</ExpandableQuote> }.into_any(),
     }
-    
+}
+
+/// A preformatted block, highlighted when its language is one
+/// `highlight` knows.
+fn render_code_block(code: &str, language: Option<&str>) -> AnyView {
+    use crate::highlight::highlight;
+
+    let tokens: Vec<AnyView> = highlight(code, language.unwrap_or_default())
+        .into_iter()
+        .map(|(kind, text)| view! { <span class=kind.class()>{text.to_string()}</span> }.into_any())
+        .collect();
     view! {
-        <span class="formatted-text">
-            {result_parts}
-        </span>
+        <pre class="code-block" data-language=language.map(str::to_string)>
+            <code>{tokens}</code>
+        </pre>
     }.into_any()
 }
+
+/// A blockquote cut to a few lines until it is clicked.
+#[component]
+fn ExpandableQuote(children: Children) -> impl IntoView {
+    let expanded = RwSignal::new(false);
+    view! {
+        <blockquote
+            class="message-quote expandable"
+            class:expanded=move || expanded.get()
+            title="Click to expand"
+            on:click=move |_| expanded.update(|expanded| *expanded = !*expanded)
+        >
+            {children()}
+        </blockquote>
+    }
+}
+
+/// A custom emoji drawn from its cached sticker file at `url`, or the plain
+/// `emoji` until it is downloaded.
+fn render_custom_emoji(emoji: &str, url: Option<&str>) -> AnyView {
+    match url {
+        Some(url) if url.starts_with("/lottie/") => view! {
+            <LottieSticker src=url.to_string() emoji=emoji.to_string() />
+        }.into_any(),
+        Some(url) if url.ends_with(".webm") => view! {
+            <video class="custom-emoji" src=url.to_string() title=emoji.to_string() autoplay loop muted playsinline></video>
+        }.into_any(),
+        Some(url) => view! {
+            <img class="custom-emoji" src=url.to_string() alt=emoji.to_string() title=emoji.to_string() loading="lazy" />
+        }.into_any(),
+        None => view! { <span class="custom-emoji-fallback">{emoji.to_string()}</span> }.into_any(),
+    }
+}
@@ -1,18 +1,21 @@
 use grammers_client::{Client, Config, SignInError};
 use grammers_session::Session;
 use std::io::{self, Write};
-use tg_log_new::config::*;
+use tg_log_new::config::Config as AppConfig;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let list_mode = std::env::args().any(|arg| arg == "--list");
 
+    let settings = AppConfig::load()?;
+    let session_path = &settings.session_path;
+
     println!("Loading session...");
-    let session = Session::load_file_or_create("session")?;
+    let session = Session::load_file_or_create(session_path)?;
     println!("Session loaded successfully");
     
-    if std::path::Path::new("session").exists() {
-        let metadata = std::fs::metadata("session")?;
+    if session_path.exists() {
+        let metadata = std::fs::metadata(session_path)?;
         println!("Session file exists: {} bytes", metadata.len());
     } else {
         println!("No existing session file found, will create new one");
@@ -20,8 +23,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let config = Config {
         session,
-        api_id: *API_ID,
-        api_hash: API_HASH.clone(),
+        api_id: settings.api_id,
+        api_hash: settings.api_hash.clone(),
         params: Default::default(),
     };
 
@@ -50,7 +53,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
         
         println!("Saving session...");
-        match client.session().save_to_file("session") {
+        match client.session().save_to_file(session_path) {
             Ok(_) => println!("Session saved successfully!"),
             Err(e) => {
                 eprintln!("Warning: Failed to save session: {}", e);
@@ -1,23 +1,607 @@
-use once_cell::sync::Lazy;
-
-pub static API_ID: Lazy<i32> = Lazy::new(|| {
-    std::env::var("TELEGRAM_API_ID")
-        .expect("TELEGRAM_API_ID environment variable not set")
-        .trim()
-        .parse()
-        .expect("TELEGRAM_API_ID must be a valid integer")
-});
-
-pub static API_HASH: Lazy<String> = Lazy::new(|| {
-    std::env::var("TELEGRAM_API_HASH")
-        .expect("TELEGRAM_API_HASH environment variable not set")
-        .trim().to_string()
-});
-
-pub static TARGET_CHAT: Lazy<i64> = Lazy::new(|| {
-    std::env::var("TELEGRAM_TARGET_CHAT")
-        .expect("TELEGRAM_TARGET_CHAT environment variable not set")
-        .trim()
-        .parse()
-        .expect("TELEGRAM_TARGET_CHAT must be a valid integer")
-});
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use serde::Deserialize;
+
+/// Configuration file read when `TELEGRAM_CONFIG` does not name another one.
+pub const DEFAULT_CONFIG_PATH: &str = "tg-log.toml";
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Settings shared by the server and `cli-auth`.
+///
+/// Values come from the TOML file at `TELEGRAM_CONFIG` (default
+/// `tg-log.toml`, optional), and each can be overridden by the environment
+/// variable noted on the field.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// `TELEGRAM_API_ID`, from https://my.telegram.org.
+    pub api_id: i32,
+    /// `TELEGRAM_API_HASH`.
+    pub api_hash: String,
+    /// `TELEGRAM_SESSION_PATH`, the login created by `cli-auth`.
+    pub session_path: PathBuf,
+    /// `TELEGRAM_ARCHIVE_PATH`, the SQLite message archive.
+    pub archive_path: PathBuf,
+    /// `TELEGRAM_MEDIA_DIR`, where stickers, avatars, photos and other media are cached.
+    pub media_dir: PathBuf,
+    /// `TELEGRAM_MEDIA_MAX_FILE_MB`, the largest document fetched on demand.
+    pub media_max_file_mb: u64,
+    /// `TELEGRAM_MEDIA_CACHE_MB`, how much space on-demand downloads may
+    /// take before the least recently used are removed.
+    pub media_cache_mb: u64,
+    /// `TELEGRAM_CHATS` (or the single `TELEGRAM_TARGET_CHAT`), comma-separated.
+    pub chats: Vec<ChatRef>,
+    /// `TELEGRAM_PAGE_SIZE`, messages per history request.
+    pub page_size: i32,
+    /// `TELEGRAM_BIND_ADDRESS`; Leptos' `site-addr` when unset.
+    pub bind_address: Option<SocketAddr>,
+}
+
+/// Everything that is wrong with the configuration, reported at once.
+#[derive(Debug)]
+pub struct ConfigError {
+    pub problems: Vec<String>,
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Invalid configuration:")?;
+        for problem in &self.problems {
+            writeln!(f, "  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// The configuration file as written; everything is optional until the
+/// environment has been applied.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    api_id: Option<i32>,
+    api_hash: Option<String>,
+    session_path: Option<PathBuf>,
+    archive_path: Option<PathBuf>,
+    media_dir: Option<PathBuf>,
+    media_max_file_mb: Option<u64>,
+    media_cache_mb: Option<u64>,
+    chats: Option<Vec<ChatRef>>,
+    page_size: Option<i32>,
+    bind_address: Option<SocketAddr>,
+}
+
+impl Config {
+    /// Reads the configuration file and environment and validates the result.
+    pub fn load() -> Result<Self, ConfigError> {
+        let mut problems = Vec::new();
+
+        // Without the file's values, every other check would only add noise
+        let path = std::env::var("TELEGRAM_CONFIG").ok();
+        let file = read_config_file(path.as_deref()).map_err(|problem| ConfigError {
+            problems: vec![problem],
+        })?;
+
+
+        let api_id = env_parsed("TELEGRAM_API_ID", &mut problems).or(file.api_id);
+        let api_hash = env("TELEGRAM_API_HASH").or(file.api_hash);
+        let chats = match env("TELEGRAM_CHATS").or_else(|| env("TELEGRAM_TARGET_CHAT")) {
+            Some(list) => parse_chat_list(&list, &mut problems),
+            None => file.chats.unwrap_or_default(),
+        };
+        let page_size = env_parsed("TELEGRAM_PAGE_SIZE", &mut problems).or(file.page_size).unwrap_or(50);
+        let bind_address = env_parsed("TELEGRAM_BIND_ADDRESS", &mut problems).or(file.bind_address);
+        let media_max_file_mb = env_parsed("TELEGRAM_MEDIA_MAX_FILE_MB", &mut problems)
+            .or(file.media_max_file_mb).unwrap_or(100);
+        let media_cache_mb = env_parsed("TELEGRAM_MEDIA_CACHE_MB", &mut problems)
+            .or(file.media_cache_mb).unwrap_or(2048);
+
+        match api_id {
+            None => problems.push("api_id is not set (TELEGRAM_API_ID)".to_string()),
+            Some(id) if id <= 0 => problems.push(format!("api_id must be positive, got {}", id)),
+            Some(_) => {}
+        }
+        match &api_hash {
+            None => problems.push("api_hash is not set (TELEGRAM_API_HASH)".to_string()),
+            Some(hash) if hash.len() != 32 || !hash.chars().all(|c| c.is_ascii_hexdigit()) => {
+                problems.push("api_hash must be the 32 hex digits shown on my.telegram.org".to_string())
+            }
+            Some(_) => {}
+        }
+        if !(1..=100).contains(&page_size) {
+            problems.push(format!("page_size must be between 1 and 100, got {}", page_size));
+        }
+        if media_max_file_mb == 0 {
+            problems.push("media_max_file_mb must be positive".to_string());
+        }
+        if media_cache_mb < media_max_file_mb {
+            problems.push(format!(
+                "media_cache_mb ({}) must be at least media_max_file_mb ({})",
+                media_cache_mb, media_max_file_mb,
+            ));
+        }
+
+        if !problems.is_empty() {
+            return Err(ConfigError { problems });
+        }
+        Ok(Self {
+            api_id: api_id.unwrap_or_default(),
+            api_hash: api_hash.unwrap_or_default(),
+            session_path: env("TELEGRAM_SESSION_PATH").map(PathBuf::from).or(file.session_path)
+                .unwrap_or_else(|| PathBuf::from("session")),
+            archive_path: env("TELEGRAM_ARCHIVE_PATH").map(PathBuf::from).or(file.archive_path)
+                .unwrap_or_else(|| PathBuf::from("archive.sqlite3")),
+            media_dir: env("TELEGRAM_MEDIA_DIR").map(PathBuf::from).or(file.media_dir)
+                .unwrap_or_else(|| PathBuf::from("target/site")),
+            media_max_file_mb,
+            media_cache_mb,
+            chats,
+            page_size,
+            bind_address,
+        })
+    }
+
+    /// Makes `self` the configuration returned by `config()`. Only the first
+    /// call has an effect.
+    pub fn install(self) -> &'static Config {
+        CONFIG.get_or_init(|| self)
+    }
+
+    /// Where resolved chats are cached, next to the session file.
+    pub fn peer_cache_path(&self) -> PathBuf {
+        let mut path = self.session_path.clone().into_os_string();
+        path.push(".peers.json");
+        PathBuf::from(path)
+    }
+
+    pub fn sticker_dir(&self) -> PathBuf {
+        self.media_dir.join("stickers")
+    }
+
+    pub fn avatar_dir(&self) -> PathBuf {
+        self.media_dir.join("avatars")
+    }
+
+    pub fn photo_dir(&self) -> PathBuf {
+        self.media_dir.join("photos")
+    }
+
+    /// Documents, videos and audio downloaded on demand, named by their
+    /// Telegram document id.
+    pub fn file_dir(&self) -> PathBuf {
+        self.media_dir.join("files")
+    }
+}
+
+/// The configuration installed at startup.
+///
+/// # Panics
+///
+/// If called before `Config::install`.
+pub fn config() -> &'static Config {
+    CONFIG.get().expect("configuration is loaded at startup")
+}
+
+/// Installs the configuration tests share, whatever order they run in. Its
+/// files live in a temporary directory that lasts as long as the process.
+#[cfg(test)]
+pub(crate) fn install_test_config() -> &'static Config {
+    static DIR: OnceLock<tempfile::TempDir> = OnceLock::new();
+    let dir = DIR.get_or_init(|| tempfile::tempdir().expect("cannot create a temporary directory")).path();
+    Config {
+        api_id: 1,
+        api_hash: "0123456789abcdef0123456789abcdef".to_string(),
+        session_path: dir.join("session"),
+        archive_path: dir.join("archive.sqlite3"),
+        media_dir: dir.join("media"),
+        media_max_file_mb: 1,
+        media_cache_mb: 1,
+        chats: Vec::new(),
+        page_size: 50,
+        bind_address: None,
+    }
+    .install()
+}
+
+/// Reads the file at `path`, or `DEFAULT_CONFIG_PATH` if it exists.
+fn read_config_file(path: Option<&str>) -> Result<ConfigFile, String> {
+    let path = match path {
+        Some(path) => Path::new(path),
+        None if Path::new(DEFAULT_CONFIG_PATH).exists() => Path::new(DEFAULT_CONFIG_PATH),
+        None => return Ok(ConfigFile::default()),
+    };
+    let data = std::fs::read_to_string(path)
+        .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    toml::from_str(&data).map_err(|e| format!("{}: {}", path.display(), e.message()))
+}
+
+/// Parses the environment variable `name`, recording a problem if it is set
+/// but malformed.
+fn env_parsed<T>(name: &str, problems: &mut Vec<String>) -> Option<T>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    match env(name)?.parse() {
+        Ok(value) => Some(value),
+        Err(e) => {
+            problems.push(format!("{} is invalid: {}", name, e));
+            None
+        }
+    }
+}
+
+fn parse_chat_list(list: &str, problems: &mut Vec<String>) -> Vec<ChatRef> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|chat| !chat.is_empty())
+        .filter_map(|chat| chat.parse().map_err(|e| problems.push(format!("invalid chat: {}", e))).ok())
+        .collect()
+}
+
+/// A configured chat: a numeric id, a public `@username` or a `t.me` link,
+/// including `t.me/+...` invite links.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ChatRef {
+    Id(i64),
+    Username(String),
+    Invite(String),
+}
+
+impl ChatRef {
+    /// A chat by id, given either as Telegram itself numbers chats or in
+    /// the Bot API form, where groups are negative and channels and
+    /// supergroups prefixed with `-100`.
+    pub fn from_id(id: i64) -> Self {
+        /// What the Bot API subtracts from channel ids.
+        const CHANNEL_OFFSET: i64 = 1_000_000_000_000;
+
+        ChatRef::Id(match id {
+            _ if id <= -CHANNEL_OFFSET => -id - CHANNEL_OFFSET,
+            _ if id < 0 => -id,
+            _ => id,
+        })
+    }
+}
+
+impl std::str::FromStr for ChatRef {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(id) = s.parse() {
+            return Ok(ChatRef::from_id(id));
+        }
+        if let Some(username) = s.strip_prefix('@') {
+            return parse_username(username).ok_or_else(|| format!("invalid username: {}", s));
+        }
+
+        let link = s
+            .trim_start_matches("https://")
+            .trim_start_matches("http://")
+            .trim_start_matches("www.");
+        let path = link
+            .strip_prefix("t.me/")
+            .or_else(|| link.strip_prefix("telegram.me/"))
+            .or_else(|| link.strip_prefix("telegram.dog/"));
+        match path {
+            Some(path) => {
+                let path = path.split(['?', '#']).next().unwrap_or_default();
+                if let Some(hash) = path.strip_prefix('+').or_else(|| path.strip_prefix("joinchat/")) {
+                    let hash = hash.trim_end_matches('/');
+                    if hash.is_empty() {
+                        return Err(format!("invite link without hash: {}", s));
+                    }
+                    return Ok(ChatRef::Invite(hash.to_string()));
+                }
+                let path = path.strip_prefix("s/").unwrap_or(path);
+                let username = path.split('/').next().unwrap_or_default();
+                parse_username(username).ok_or_else(|| format!("invalid chat link: {}", s))
+            }
+            None => parse_username(s).ok_or_else(|| format!("not a chat id, username or t.me link: {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for ChatRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChatRef::Id(id) => write!(f, "{}", id),
+            ChatRef::Username(username) => write!(f, "@{}", username),
+            ChatRef::Invite(hash) => write!(f, "t.me/+{}", hash),
+        }
+    }
+}
+
+/// Usernames are 4-32 letters, digits and underscores, starting with a
+/// letter. They are case-insensitive, so they are kept lowercase.
+fn parse_username(username: &str) -> Option<ChatRef> {
+    let valid = (4..=32).contains(&username.len())
+        && username.starts_with(|c: char| c.is_ascii_alphabetic())
+        && username.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    valid.then(|| ChatRef::Username(username.to_ascii_lowercase()))
+}
+
+impl<'de> Deserialize<'de> for ChatRef {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        /// Ids may be written as TOML integers as well as strings.
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Entry {
+            Id(i64),
+            Text(String),
+        }
+
+        match Entry::deserialize(deserializer)? {
+            Entry::Id(id) => Ok(ChatRef::from_id(id)),
+            Entry::Text(text) => text.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+/// Reads the environment variable `name`, treating blank values as unset.
+fn env(name: &str) -> Option<String> {
+    let value = std::env::var(name).ok()?.trim().to_string();
+    (!value.is_empty()).then_some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chat_refs_parse_from_every_form() {
+        let username = |name: &str| Ok(ChatRef::Username(name.to_string()));
+        let invite = |hash: &str| Ok(ChatRef::Invite(hash.to_string()));
+        let cases = [
+            ("1234567", Ok(ChatRef::Id(1234567))),
+            ("-1001234567890", Ok(ChatRef::Id(1234567890))),
+            ("-1234567", Ok(ChatRef::Id(1234567))),
+            ("@tg_log", username("tg_log")),
+            ("@TG_Log", username("tg_log")),
+            ("  @tg_log  ", username("tg_log")),
+            ("tg_log", username("tg_log")),
+            ("t.me/tg_log", username("tg_log")),
+            ("https://t.me/tg_log", username("tg_log")),
+            ("http://www.t.me/tg_log", username("tg_log")),
+            ("https://telegram.me/tg_log", username("tg_log")),
+            ("https://telegram.dog/tg_log", username("tg_log")),
+            ("https://t.me/tg_log/", username("tg_log")),
+            ("https://t.me/tg_log/123", username("tg_log")),
+            ("https://t.me/tg_log?start=1", username("tg_log")),
+            ("https://t.me/tg_log#top", username("tg_log")),
+            ("https://t.me/s/tg_log", username("tg_log")),
+            ("https://t.me/s/tg_log/", username("tg_log")),
+            ("https://t.me/+AbC-dEf_123", invite("AbC-dEf_123")),
+            ("t.me/+AbC-dEf_123/", invite("AbC-dEf_123")),
+            ("https://t.me/+AbC-dEf_123?ref=x", invite("AbC-dEf_123")),
+            ("https://t.me/joinchat/AbC-dEf_123", invite("AbC-dEf_123")),
+            ("https://t.me/joinchat/AbC-dEf_123/", invite("AbC-dEf_123")),
+            ("https://t.me/+", Err(())),
+            ("https://t.me/joinchat/", Err(())),
+            ("https://t.me/+/", Err(())),
+            ("@abc", Err(())),
+            ("@1abc", Err(())),
+            ("@tg-log", Err(())),
+            ("https://t.me/", Err(())),
+            ("https://example.com/tg_log", Err(())),
+            ("", Err(())),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(input.parse::<ChatRef>().map_err(|_| ()), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn bot_api_ids_become_telegram_ids() {
+        let cases = [
+            (1234567, 1234567),
+            (-1234567, 1234567),
+            (-1001234567890, 1234567890),
+            (-1001234567, 1001234567),
+            (-1_000_000_000_001, 1),
+            (-999_999_999_999, 999_999_999_999),
+        ];
+        for (id, expected) in cases {
+            assert_eq!(ChatRef::from_id(id), ChatRef::Id(expected), "{}", id);
+        }
+    }
+
+    /// Every variable `Config::load` reads.
+    const VARIABLES: [&str; 12] = [
+        "TELEGRAM_CONFIG",
+        "TELEGRAM_API_ID",
+        "TELEGRAM_API_HASH",
+        "TELEGRAM_CHATS",
+        "TELEGRAM_TARGET_CHAT",
+        "TELEGRAM_PAGE_SIZE",
+        "TELEGRAM_BIND_ADDRESS",
+        "TELEGRAM_MEDIA_MAX_FILE_MB",
+        "TELEGRAM_MEDIA_CACHE_MB",
+        "TELEGRAM_SESSION_PATH",
+        "TELEGRAM_ARCHIVE_PATH",
+        "TELEGRAM_MEDIA_DIR",
+    ];
+
+    const API_HASH: &str = "0123456789abcdef0123456789abcdef";
+
+    /// Loads the configuration from a file containing `file` and only the
+    /// environment variables in `vars`. The environment is shared by the
+    /// whole process, so loads take turns.
+    fn load(file: &str, vars: &[(&str, &str)]) -> Result<Config, ConfigError> {
+        static ENV: std::sync::Mutex<()> = std::sync::Mutex::new(());
+        let _guard = ENV.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tg-log.toml");
+        std::fs::write(&path, file).unwrap();
+        for name in VARIABLES {
+            std::env::remove_var(name);
+        }
+        std::env::set_var("TELEGRAM_CONFIG", &path);
+        for (name, value) in vars {
+            std::env::set_var(name, value);
+        }
+        let config = Config::load();
+        for name in VARIABLES {
+            std::env::remove_var(name);
+        }
+        config
+    }
+
+    fn problems(file: &str, vars: &[(&str, &str)]) -> Vec<String> {
+        load(file, vars).expect_err("configuration should be invalid").problems
+    }
+
+    #[test]
+    fn file_values_and_defaults_are_loaded() {
+        let file = format!(
+            "api_id = 12345\napi_hash = \"{}\"\nchats = [\"@tg_log\", -1001234567890, \"t.me/+AbC\"]\npage_size = 20\n",
+            API_HASH,
+        );
+        let config = load(&file, &[]).unwrap();
+        assert_eq!(config.api_id, 12345);
+        assert_eq!(config.api_hash, API_HASH);
+        assert_eq!(
+            config.chats,
+            [ChatRef::Username("tg_log".to_string()), ChatRef::Id(1234567890), ChatRef::Invite("AbC".to_string())],
+        );
+        assert_eq!(config.page_size, 20);
+        assert_eq!(config.session_path, PathBuf::from("session"));
+        assert_eq!(config.archive_path, PathBuf::from("archive.sqlite3"));
+        assert_eq!(config.media_dir, PathBuf::from("target/site"));
+        assert_eq!((config.media_max_file_mb, config.media_cache_mb), (100, 2048));
+        assert_eq!(config.bind_address, None);
+    }
+
+    #[test]
+    fn environment_overrides_the_file() {
+        let file = format!("api_id = 1\napi_hash = \"{}\"\nchats = [\"@tg_log\"]\npage_size = 20\n", API_HASH);
+        let config = load(
+            &file,
+            &[
+                ("TELEGRAM_API_ID", "777"),
+                ("TELEGRAM_API_HASH", "fedcba9876543210fedcba9876543210"),
+                ("TELEGRAM_CHATS", "@other_chat, 42 ,,"),
+                ("TELEGRAM_TARGET_CHAT", "@ignored_chat"),
+                ("TELEGRAM_PAGE_SIZE", "100"),
+                ("TELEGRAM_BIND_ADDRESS", "127.0.0.1:8080"),
+                ("TELEGRAM_MEDIA_MAX_FILE_MB", "5"),
+                ("TELEGRAM_MEDIA_CACHE_MB", "10"),
+                ("TELEGRAM_SESSION_PATH", "/data/session"),
+                ("TELEGRAM_ARCHIVE_PATH", "/data/archive.sqlite3"),
+                ("TELEGRAM_MEDIA_DIR", "/data/media"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(config.api_id, 777);
+        assert_eq!(config.api_hash, "fedcba9876543210fedcba9876543210");
+        assert_eq!(config.chats, [ChatRef::Username("other_chat".to_string()), ChatRef::Id(42)]);
+        assert_eq!(config.page_size, 100);
+        assert_eq!(config.bind_address, Some("127.0.0.1:8080".parse().unwrap()));
+        assert_eq!((config.media_max_file_mb, config.media_cache_mb), (5, 10));
+        assert_eq!(config.session_path, PathBuf::from("/data/session"));
+        assert_eq!(config.archive_path, PathBuf::from("/data/archive.sqlite3"));
+        assert_eq!(config.media_dir, PathBuf::from("/data/media"));
+    }
+
+    #[test]
+    fn target_chat_is_used_without_a_chat_list() {
+        let file = format!("api_id = 1\napi_hash = \"{}\"\nchats = [\"@tg_log\"]\n", API_HASH);
+        let config = load(&file, &[("TELEGRAM_TARGET_CHAT", "@other_chat")]).unwrap();
+        assert_eq!(config.chats, [ChatRef::Username("other_chat".to_string())]);
+
+        // Blank variables count as unset
+        let config = load(&file, &[("TELEGRAM_CHATS", "  "), ("TELEGRAM_API_ID", "")]).unwrap();
+        assert_eq!(config.chats, [ChatRef::Username("tg_log".to_string())]);
+        assert_eq!(config.api_id, 1);
+    }
+
+    #[test]
+    fn missing_credentials_are_reported() {
+        assert_eq!(
+            problems("", &[]),
+            ["api_id is not set (TELEGRAM_API_ID)", "api_hash is not set (TELEGRAM_API_HASH)"],
+        );
+    }
+
+    #[test]
+    fn invalid_credentials_are_reported() {
+        let cases = [
+            ("api_id = 0", &[("TELEGRAM_API_HASH", API_HASH)][..], "api_id must be positive, got 0"),
+            ("api_id = -5", &[("TELEGRAM_API_HASH", API_HASH)][..], "api_id must be positive, got -5"),
+            (
+                "",
+                &[("TELEGRAM_API_ID", "abc"), ("TELEGRAM_API_HASH", API_HASH)][..],
+                "TELEGRAM_API_ID is invalid: invalid digit found in string",
+            ),
+            (
+                "api_id = 1\napi_hash = \"0123\"",
+                &[][..],
+                "api_hash must be the 32 hex digits shown on my.telegram.org",
+            ),
+            (
+                "api_id = 1",
+                &[("TELEGRAM_API_HASH", "0123456789abcdef0123456789abcdeg")][..],
+                "api_hash must be the 32 hex digits shown on my.telegram.org",
+            ),
+            (
+                "api_id = 1",
+                &[("TELEGRAM_API_HASH", "0123456789abcdef0123456789abcdef0")][..],
+                "api_hash must be the 32 hex digits shown on my.telegram.org",
+            ),
+        ];
+        for (file, vars, expected) in cases {
+            let problems = problems(file, vars);
+            assert!(problems.iter().any(|problem| problem == expected), "{:?} {:?}: {:?}", file, vars, problems);
+        }
+    }
+
+    #[test]
+    fn bad_chat_refs_are_reported() {
+        assert_eq!(
+            problems(
+                "",
+                &[("TELEGRAM_API_ID", "1"), ("TELEGRAM_API_HASH", API_HASH), ("TELEGRAM_CHATS", "@tg_log, @abc, t.me/+")],
+            ),
+            ["invalid chat: invalid username: @abc", "invalid chat: invite link without hash: t.me/+"],
+        );
+
+        // A bad chat in the file is a bad file, reported on its own
+        let in_file = problems("chats = [\"@abc\"]", &[]);
+        assert_eq!(in_file.len(), 1);
+        assert!(in_file[0].contains("invalid username: @abc"), "{:?}", in_file);
+    }
+
+    #[test]
+    fn every_problem_is_collected() {
+        let problems = problems(
+            "api_id = 0\npage_size = 0\nmedia_max_file_mb = 10\nmedia_cache_mb = 5",
+            &[("TELEGRAM_BIND_ADDRESS", "nowhere"), ("TELEGRAM_CHATS", "@abc")],
+        );
+        assert_eq!(
+            problems,
+            [
+                "invalid chat: invalid username: @abc",
+                "TELEGRAM_BIND_ADDRESS is invalid: invalid socket address syntax",
+                "api_id must be positive, got 0",
+                "api_hash is not set (TELEGRAM_API_HASH)",
+                "page_size must be between 1 and 100, got 0",
+                "media_cache_mb (5) must be at least media_max_file_mb (10)",
+            ],
+        );
+    }
+
+    #[test]
+    fn unreadable_files_are_the_only_problem() {
+        let unknown_field = problems("api_id = 1\nchat = [\"@tg_log\"]", &[("TELEGRAM_PAGE_SIZE", "0")]);
+        assert_eq!(unknown_field.len(), 1);
+        assert!(unknown_field[0].contains("unknown field `chat`"), "{:?}", unknown_field);
+
+        let missing = problems("", &[("TELEGRAM_CONFIG", "/nonexistent/tg-log.toml")]);
+        assert_eq!(missing.len(), 1);
+        assert!(missing[0].starts_with("cannot read /nonexistent/tg-log.toml"), "{:?}", missing);
+    }
+}
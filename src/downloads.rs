@@ -0,0 +1,440 @@
+//! Shared background queues for sticker and photo downloads.
+//!
+//! Every converted message asks for its sticker or photo, so while a chat
+//! loads the same file is requested over and over. A queue downloads each
+//! file at most once at a time, runs at most `MAX_CONCURRENT` downloads, and
+//! waits out FLOOD_WAIT and connection errors before trying again.
+//!
+//! Custom emoji are stickers too, but messages only carry their document id.
+//! Ids are collected for `CUSTOM_EMOJI_DELAY` and looked up together, so a
+//! page of messages costs one request rather than one per emoji.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use grammers_client::types::media::Document;
+use grammers_client::types::{Downloadable, Media, Photo};
+use grammers_client::client::files::DownloadIter;
+use grammers_client::Client;
+use grammers_tl_types as tl;
+use once_cell::sync::Lazy;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Semaphore;
+use crate::config::config;
+use crate::telegram::{photo_file_name, thumbnail_file_name, ChatMessage, EntityType, TelegramError};
+
+/// Downloads running at once; the rest wait for a free slot.
+const MAX_CONCURRENT: usize = 4;
+
+/// Tries per file before giving up until it is requested again.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// How long custom emoji ids are collected before being looked up.
+const CUSTOM_EMOJI_DELAY: Duration = Duration::from_millis(250);
+
+/// Most custom emoji Telegram describes in one request.
+const CUSTOM_EMOJI_BATCH: usize = 200;
+
+pub static STICKER_DOWNLOADS: Lazy<DownloadQueue> = Lazy::new(|| DownloadQueue::new(MAX_CONCURRENT));
+
+/// Photos of messages and link previews, queued apart from stickers so a
+/// chat full of photos does not hold up its stickers.
+pub static PHOTO_DOWNLOADS: Lazy<DownloadQueue> = Lazy::new(|| DownloadQueue::new(MAX_CONCURRENT));
+
+/// Bytes asked for per request when downloading a photo size; Telegram
+/// allows at most 1 MB, in multiples of 4 KB.
+const CHUNK_SIZE: i32 = 512 * 1024;
+
+/// Thumbnails are picked as the smallest photo size at least this wide.
+const THUMBNAIL_WIDTH: i32 = 320;
+
+/// Numbers temporary files, so a download never writes into another one's.
+static TEMP_FILES: AtomicU64 = AtomicU64::new(0);
+
+pub struct DownloadQueue {
+    permits: Arc<Semaphore>,
+    /// Document or photo ids queued or downloading.
+    in_flight: Arc<Mutex<HashSet<i64>>>,
+    /// Custom emoji waiting to be looked up, with their in-flight guards.
+    /// Empty whenever no lookup is scheduled.
+    custom_emoji: Arc<Mutex<Vec<InFlight>>>,
+}
+
+impl DownloadQueue {
+    fn new(max_concurrent: usize) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(max_concurrent)),
+            in_flight: Arc::new(Mutex::new(HashSet::new())),
+            custom_emoji: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Downloads the sticker `document` into the sticker cache in the
+    /// background, unless it is cached or already queued.
+    pub fn enqueue_sticker(&self, client: &Client, document: Document) {
+        let sticker_id = document.id();
+        let cached = sticker_path(sticker_id, document.mime_type()).exists();
+        if cached || !self.in_flight.lock().unwrap().insert(sticker_id) {
+            return;
+        }
+
+        let queued = InFlight { ids: self.in_flight.clone(), id: sticker_id };
+        let permits = self.permits.clone();
+        let client = client.clone();
+        tokio::spawn(async move {
+            let _queued = queued;
+            let Ok(_permit) = permits.acquire_owned().await else {
+                return;
+            };
+            let media = format!("sticker {}", sticker_id);
+            if let Err(e) = download_with_retry(&media, || download_sticker(&client, document.clone())).await {
+                eprintln!("Failed to download {}: {}", media, e);
+            }
+        });
+    }
+
+    /// Downloads `photo` and its thumbnail into the photo cache in the
+    /// background, unless they are cached or already queued.
+    pub fn enqueue_photo(&self, client: &Client, photo: Photo) {
+        let photo_id = photo.id();
+        let (file_path, thumbnail_path) = photo_paths(photo_id);
+        let cached = file_path.exists() && thumbnail_path.exists();
+        if cached || !self.in_flight.lock().unwrap().insert(photo_id) {
+            return;
+        }
+
+        let queued = InFlight { ids: self.in_flight.clone(), id: photo_id };
+        let permits = self.permits.clone();
+        let client = client.clone();
+        tokio::spawn(async move {
+            let _queued = queued;
+            let Ok(_permit) = permits.acquire_owned().await else {
+                return;
+            };
+            let media = format!("photo {}", photo_id);
+            if let Err(e) = download_with_retry(&media, || download_photo(&client, photo.clone())).await {
+                eprintln!("Failed to download {}: {}", media, e);
+            }
+        });
+    }
+
+    /// Looks up the custom emoji `document_ids` together with any others
+    /// requested shortly before or after, then downloads the ones not cached
+    /// like stickers.
+    pub fn enqueue_custom_emoji(&'static self, client: &Client, document_ids: Vec<i64>) {
+        let mut pending = self.custom_emoji.lock().unwrap();
+        let schedule = pending.is_empty();
+        for document_id in document_ids {
+            if custom_emoji_file_name(document_id).is_none() && self.in_flight.lock().unwrap().insert(document_id) {
+                pending.push(InFlight { ids: self.in_flight.clone(), id: document_id });
+            }
+        }
+        if !schedule || pending.is_empty() {
+            return;
+        }
+
+        let client = client.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(CUSTOM_EMOJI_DELAY).await;
+            let mut queued = std::mem::take(&mut *self.custom_emoji.lock().unwrap());
+            while !queued.is_empty() {
+                let mut batch: Vec<InFlight> = queued.drain(..queued.len().min(CUSTOM_EMOJI_BATCH)).collect();
+                let documents = {
+                    let Ok(_permit) = self.permits.acquire().await else {
+                        return;
+                    };
+                    let document_id = batch.iter().map(|queued| queued.id).collect();
+                    match client.invoke(&tl::functions::messages::GetCustomEmojiDocuments { document_id }).await {
+                        Ok(documents) => documents,
+                        Err(e) => {
+                            eprintln!("Failed to look up {} custom emoji: {}", batch.len(), e);
+                            continue;
+                        }
+                    }
+                };
+                for document in documents {
+                    let document = Document::from_media(
+                        tl::types::MessageMediaDocument {
+                            nopremium: false,
+                            spoiler: false,
+                            video: false,
+                            round: false,
+                            voice: false,
+                            document: Some(document),
+                            alt_document: None,
+                            ttl_seconds: None,
+                        },
+                        client.clone(),
+                    );
+                    // Hand the id over from the lookup to the download
+                    if let Some(index) = batch.iter().position(|queued| queued.id == document.id()) {
+                        drop(batch.swap_remove(index));
+                        self.enqueue_sticker(&client, document);
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Takes a document id off the in-flight set when its task ends, even if it
+/// panicked.
+struct InFlight {
+    ids: Arc<Mutex<HashSet<i64>>>,
+    id: i64,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if let Ok(mut ids) = self.ids.lock() {
+            ids.remove(&self.id);
+        }
+    }
+}
+
+/// MIME type of animated stickers: gzipped Lottie JSON.
+pub const TGS_MIME_TYPE: &str = "application/x-tgsticker";
+
+/// Name under which the sticker with document id `sticker_id` is cached,
+/// with the extension of its format: `.webp` images, `.tgs` Lottie
+/// animations or `.webm` videos.
+pub fn sticker_file_name(sticker_id: i64, mime_type: Option<&str>) -> String {
+    let extension = match mime_type {
+        Some(TGS_MIME_TYPE) => "tgs",
+        Some("video/webm") => "webm",
+        _ => "webp",
+    };
+    format!("sticker_{}.{}", sticker_id, extension)
+}
+
+/// Name under which the custom emoji `document_id` is cached, if it is; the
+/// format is only known once it has been downloaded.
+pub fn custom_emoji_file_name(document_id: i64) -> Option<String> {
+    [TGS_MIME_TYPE, "video/webm", "image/webp"]
+        .into_iter()
+        .map(|mime_type| sticker_file_name(document_id, Some(mime_type)))
+        .find(|file_name| config().sticker_dir().join(file_name).exists())
+}
+
+/// Points the custom emoji in `messages` at their cached files, which the
+/// archive does not record. Emoji not downloaded yet keep no URL and are
+/// shown as their plain emoji.
+pub fn attach_custom_emoji(messages: &mut [ChatMessage]) {
+    let entities = messages.iter_mut().flat_map(|message| {
+        message.formatted_text.iter_mut().chain(
+            message.revisions.iter_mut().flat_map(|revision| revision.formatted_text.iter_mut()),
+        )
+    });
+    for entity in entities {
+        if let EntityType::CustomEmoji { document_id } = entity.entity_type {
+            entity.url = custom_emoji_file_name(document_id).map(|file_name| {
+                // Animated ones go through the Lottie decoder
+                if file_name.ends_with(".tgs") {
+                    format!("/lottie/{}", file_name)
+                } else {
+                    format!("/stickers/{}", file_name)
+                }
+            });
+        }
+    }
+}
+
+fn sticker_path(sticker_id: i64, mime_type: Option<&str>) -> PathBuf {
+    config().sticker_dir().join(sticker_file_name(sticker_id, mime_type))
+}
+
+/// Where photo `photo_id` and its thumbnail are cached.
+fn photo_paths(photo_id: i64) -> (PathBuf, PathBuf) {
+    let file_name = photo_file_name(photo_id);
+    let photo_dir = config().photo_dir();
+    (photo_dir.join(&file_name), photo_dir.join(thumbnail_file_name(&file_name)))
+}
+
+/// A name for a temporary file next to `path`, unique to this download.
+fn partial_path(path: &std::path::Path) -> PathBuf {
+    path.with_extension(format!("{}.part", TEMP_FILES.fetch_add(1, Ordering::Relaxed)))
+}
+
+/// Runs `download` until it succeeds, waiting out FLOOD_WAIT and connection
+/// errors up to `MAX_ATTEMPTS` times. `media` names what is downloaded.
+async fn download_with_retry<F, Fut>(media: &str, download: F) -> Result<(), TelegramError>
+where
+    F: Fn() -> Fut,
+    Fut: std::future::Future<Output = Result<(), TelegramError>>,
+{
+    let mut backoff = Duration::from_secs(1);
+    for attempt in 1.. {
+        let delay = match download().await {
+            Err(TelegramError::FloodWait { seconds }) => Duration::from_secs(seconds.into()).max(backoff),
+            Err(TelegramError::Network(_)) => backoff,
+            result => return result,
+        };
+        if attempt == MAX_ATTEMPTS {
+            break;
+        }
+        eprintln!("Download of {} delayed, retrying in {:?}", media, delay);
+        tokio::time::sleep(delay).await;
+        backoff = (backoff * 2).min(Duration::from_secs(60));
+    }
+    Err(TelegramError::MediaDownloadFailed {
+        media: media.to_string(),
+        reason: format!("still failing after {} attempts", MAX_ATTEMPTS),
+    })
+}
+
+/// Bytes of a file being downloaded, a chunk at a time. Chunk by chunk
+/// rather than `Client::download_media`, which hides FLOOD_WAIT inside an
+/// I/O error.
+enum Chunks {
+    /// A whole document or photo.
+    File(DownloadIter),
+    /// One size of a photo, which grammers can only download in a way that
+    /// panics on errors.
+    Location { client: Client, location: tl::enums::InputFileLocation, offset: i64, done: bool },
+}
+
+impl Chunks {
+    async fn next(&mut self) -> Result<Option<Vec<u8>>, TelegramError> {
+        match self {
+            Chunks::File(download) => Ok(download.next().await?),
+            Chunks::Location { done: true, .. } => Ok(None),
+            Chunks::Location { client, location, offset, done } => {
+                let request = tl::functions::upload::GetFile {
+                    precise: false,
+                    cdn_supported: false,
+                    location: location.clone(),
+                    offset: *offset,
+                    limit: CHUNK_SIZE,
+                };
+                let tl::enums::upload::File::File(file) = client.invoke(&request).await? else {
+                    return Err(TelegramError::Rpc("file is only available from a CDN".to_string()));
+                };
+                *offset += file.bytes.len() as i64;
+                *done = file.bytes.len() < CHUNK_SIZE as usize;
+                Ok(Some(file.bytes))
+            }
+        }
+    }
+}
+
+/// Writes `chunks` to `path`. The file is written under a temporary name and
+/// renamed once complete, so a cached file is never a partial one.
+async fn save_download(
+    mut chunks: Chunks,
+    path: &Path,
+    failed: impl Fn(&str, std::io::Error) -> TelegramError,
+) -> Result<(), TelegramError> {
+    use tokio::fs;
+
+    let partial = partial_path(path);
+    let downloaded = async {
+        let mut file = fs::File::create(&partial).await.map_err(|e| failed("creating file", e))?;
+        while let Some(chunk) = chunks.next().await? {
+            file.write_all(&chunk).await.map_err(|e| failed("writing", e))?;
+        }
+        file.flush().await.map_err(|e| failed("writing", e))
+    }.await;
+    if let Err(e) = downloaded {
+        let _ = fs::remove_file(&partial).await;
+        return Err(e);
+    }
+    fs::rename(&partial, path).await.map_err(|e| failed("saving", e))
+}
+
+/// Downloads the sticker `document` into the sticker cache.
+pub async fn download_sticker(client: &Client, document: Document) -> Result<(), TelegramError> {
+    let sticker_id = document.id();
+    let file_path = sticker_path(sticker_id, document.mime_type());
+    let failed = |what: &str, e: std::io::Error| TelegramError::MediaDownloadFailed {
+        media: format!("sticker {}", sticker_id),
+        reason: format!("{}: {}", what, e),
+    };
+    if file_path.exists() {
+        return Ok(());
+    }
+
+    eprintln!("Downloading sticker {} from Telegram", sticker_id);
+    tokio::fs::create_dir_all(config().sticker_dir()).await.map_err(|e| failed("creating directory", e))?;
+    let chunks = Chunks::File(client.iter_download(&Downloadable::Media(Media::Document(document))));
+    save_download(chunks, &file_path, failed).await?;
+
+    eprintln!("Sticker {} cached to {}", sticker_id, file_path.display());
+    Ok(())
+}
+
+/// Downloads the largest size of `photo` and a thumbnail about
+/// `THUMBNAIL_WIDTH` pixels wide into the photo cache, skipping files that
+/// are already there.
+async fn download_photo(client: &Client, photo: Photo) -> Result<(), TelegramError> {
+    use grammers_client::types::photo_sizes::{PhotoSize, VecExt};
+    use tokio::fs;
+
+    let photo_id = photo.id();
+    let (file_path, thumbnail_path) = photo_paths(photo_id);
+    let failed = |what: &str, e: std::io::Error| TelegramError::MediaDownloadFailed {
+        media: format!("photo {}", photo_id),
+        reason: format!("{}: {}", what, e),
+    };
+
+    fs::create_dir_all(config().photo_dir()).await.map_err(|e| failed("creating directory", e))?;
+
+    if !file_path.exists() {
+        eprintln!("Downloading photo {} from Telegram", photo_id);
+        let chunks = Chunks::File(client.iter_download(&Downloadable::Media(Media::Photo(photo.clone()))));
+        save_download(chunks, &file_path, failed).await?;
+    }
+
+    if thumbnail_path.exists() {
+        return Ok(());
+    }
+    let thumbs = photo.thumbs();
+    let largest = thumbs.largest().map(PhotoSize::photo_type);
+    let thumbnail = thumbs
+        .into_iter()
+        .filter_map(|size| match size {
+            PhotoSize::Size(ref sized) => Some((sized.width, size.photo_type())),
+            _ => None,
+        })
+        .min_by_key(|(width, _)| if *width >= THUMBNAIL_WIDTH { (false, *width) } else { (true, -width) })
+        .map(|(_, photo_type)| photo_type)
+        .filter(|photo_type| Some(photo_type) != largest.as_ref());
+
+    match thumbnail.zip(raw_photo(&photo)) {
+        Some((thumb_size, raw)) => {
+            let location = tl::types::InputPhotoFileLocation {
+                id: raw.id,
+                access_hash: raw.access_hash,
+                file_reference: raw.file_reference,
+                thumb_size,
+            };
+            let chunks = Chunks::Location { client: client.clone(), location: location.into(), offset: 0, done: false };
+            save_download(chunks, &thumbnail_path, failed).await?;
+        }
+        // Small photos are their own thumbnail
+        None => {
+            let partial = partial_path(&thumbnail_path);
+            let copied = fs::copy(&file_path, &partial).await.map_err(|e| failed("saving thumbnail", e));
+            if let Err(e) = copied {
+                let _ = fs::remove_file(&partial).await;
+                return Err(e);
+            }
+            fs::rename(&partial, &thumbnail_path).await.map_err(|e| failed("saving thumbnail", e))?;
+        }
+    }
+
+    eprintln!("Photo {} cached to {}", photo_id, file_path.display());
+    Ok(())
+}
+
+/// The photo as Telegram describes it, for locating its sizes.
+fn raw_photo(photo: &Photo) -> Option<tl::types::Photo> {
+    let tl::enums::MessageMedia::Photo(media) = tl::enums::MessageMedia::from(Media::Photo(photo.clone())) else {
+        return None;
+    };
+    match media.photo? {
+        tl::enums::Photo::Photo(photo) => Some(photo),
+        tl::enums::Photo::Empty(_) => None,
+    }
+}
@@ -0,0 +1,1098 @@
+use std::io::Write;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use base64::Engine;
+use grammers_session::PackedType;
+use leptos::prelude::*;
+use serde::{Deserialize, Serialize};
+use strum::EnumString;
+use crate::archive::Archive;
+use crate::chat::{format_file_size, format_timestamp, render_formatted_text, render_poll};
+use crate::config::{config, ChatRef};
+use crate::downloads::TGS_MIME_TYPE;
+use crate::formatting::{byte_range, safe_link, span_tree, Span};
+use crate::peers::{cached_chat_id, PEER_CACHE};
+use crate::service::{ServiceAction, ServiceUser};
+use crate::state::AppState;
+use crate::telegram::{
+    thumbnail_file_name, ChatMessage, EntityType, GeoPoint, MediaDetails, MediaInfo, MessageType, Poll,
+    TextEntity,
+};
+
+/// Stylesheet inlined into HTML exports.
+const EXPORT_CSS: &str = include_str!("../style/export.css");
+
+/// What Telegram Desktop writes in place of files it did not download.
+const FILE_NOT_INCLUDED: &str = "(File not included. Change data exporting settings to download.)";
+
+const USAGE: &str = "usage: tg-log-new export <chat> [--format json|html|markdown|text] [--output <file>|-]";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumString)]
+#[strum(ascii_case_insensitive)]
+pub enum ExportFormat {
+    /// Telegram Desktop's machine-readable `result.json`.
+    #[strum(serialize = "json")]
+    Json,
+    /// A single HTML page with its styles, stickers and photo thumbnails inlined.
+    #[strum(serialize = "html")]
+    Html,
+    #[strum(serialize = "markdown", serialize = "md")]
+    Markdown,
+    #[strum(serialize = "text", serialize = "txt")]
+    Text,
+}
+
+impl ExportFormat {
+    /// Name of the exported file, as Telegram Desktop calls it where it has
+    /// an equivalent.
+    pub fn file_name(self) -> &'static str {
+        match self {
+            ExportFormat::Json => "result.json",
+            ExportFormat::Html => "messages.html",
+            ExportFormat::Markdown => "messages.md",
+            ExportFormat::Text => "messages.txt",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Json => "application/json",
+            ExportFormat::Html => "text/html; charset=utf-8",
+            ExportFormat::Markdown => "text/markdown; charset=utf-8",
+            ExportFormat::Text => "text/plain; charset=utf-8",
+        }
+    }
+}
+
+/// An archived chat, ready to be written out in any `ExportFormat`.
+pub struct ChatExport {
+    pub id: i64,
+    pub name: String,
+    /// Oldest first, including deleted messages.
+    pub messages: Vec<ChatMessage>,
+}
+
+impl ChatExport {
+    pub fn load(archive: &Archive, chat_id: i64) -> rusqlite::Result<Self> {
+        let mut messages = archive.chat_messages(chat_id)?;
+        archive.attach_reply_previews(&mut messages)?;
+        Ok(Self {
+            id: chat_id,
+            name: archive.chat_name(chat_id)?.unwrap_or_else(|| format!("Chat {}", chat_id)),
+            messages,
+        })
+    }
+
+    pub fn render(&self, format: ExportFormat) -> String {
+        match format {
+            ExportFormat::Json => self.render_json(),
+            ExportFormat::Html => self.render_html(),
+            ExportFormat::Markdown => self.render_markdown(),
+            ExportFormat::Text => self.render_text(),
+        }
+    }
+
+    fn render_json(&self) -> String {
+        let export = TdChat {
+            name: &self.name,
+            kind: td_chat_type(self.id),
+            id: self.id,
+            messages: self.messages.iter().map(TdMessage::new).collect(),
+        };
+        serde_json::to_string_pretty(&export).expect("export serializes to JSON")
+    }
+
+    fn render_html(&self) -> String {
+        let name = self.name.clone();
+        let meta = format!(
+            "{} messages · exported {} UTC",
+            self.messages.len(),
+            format_timestamp(chrono::Utc::now().timestamp()),
+        );
+        let messages = self.messages.clone();
+
+        let title = view! { <title>{name.clone()}</title> }.to_html();
+        let body = view! {
+            <div class="export">
+                <header class="export-header">
+                    <h1>{name}</h1>
+                    <div class="export-meta">{meta}</div>
+                </header>
+                <div class="messages">
+                    {messages.into_iter().map(|message| view! { <ExportedMessage message=message /> }).collect::<Vec<_>>()}
+                </div>
+            </div>
+        }.to_html()
+            // Hydration markers mean nothing in a static page
+            .replace("<!>", "");
+
+        format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+             {}\n<style>\n{}</style>\n</head>\n<body>\n{}\n</body>\n</html>\n",
+            title, EXPORT_CSS, body,
+        )
+    }
+
+    fn render_markdown(&self) -> String {
+        let mut blocks = vec![format!("# {}", escape_markdown(&self.name))];
+        for message in &self.messages {
+            blocks.push(format!(
+                "### {} · {}{}",
+                escape_markdown(&message.sender),
+                format_timestamp(message.timestamp),
+                status_suffix(message),
+            ));
+            if let Some(forward) = forward_line(message) {
+                blocks.push(format!("> {}", escape_markdown(&forward)));
+            }
+            if let Some(reply) = reply_line(message) {
+                blocks.push(format!("> {}", escape_markdown(&reply)));
+            }
+            if let Some(media) = media_summary(message) {
+                blocks.push(escape_markdown(&media));
+            }
+            if let Some(poll) = &message.poll {
+                let mut lines = vec![format!("**{}** _({})_", escape_markdown(&poll.question), poll.kind_label())];
+                lines.extend(poll.result_lines().iter().map(|line| format!("- {}", escape_markdown(line))));
+                blocks.push(lines.join("\n"));
+            }
+            let (text, entities) = body(message);
+            if !text.is_empty() {
+                let text = markdown(&span_tree(text, entities));
+                if message.message_type == MessageType::System {
+                    blocks.push(format!("_{}_", text.trim()));
+                } else {
+                    blocks.push(text.trim().to_string());
+                }
+            }
+        }
+        blocks.join("\n\n") + "\n"
+    }
+
+    fn render_text(&self) -> String {
+        let mut out = format!("{}\n", self.name);
+        for message in &self.messages {
+            out.push_str(&format!(
+                "\n[{}] {}{}:\n",
+                format_timestamp(message.timestamp),
+                message.sender,
+                status_suffix(message),
+            ));
+            if let Some(forward) = forward_line(message) {
+                out.push_str(&format!("{}\n", forward));
+            }
+            if let Some(reply) = reply_line(message) {
+                out.push_str(&format!("{}\n", reply));
+            }
+            if let Some(media) = media_summary(message) {
+                out.push_str(&format!("{}\n", media));
+            }
+            if let Some(poll) = &message.poll {
+                out.push_str(&format!("📊 {} ({})\n", poll.question, poll.kind_label()));
+                for line in poll.result_lines() {
+                    out.push_str(&format!("  - {}\n", line));
+                }
+            }
+            let (text, _) = body(message);
+            if !text.is_empty() {
+                out.push_str(&format!("{}\n", text));
+            }
+        }
+        out
+    }
+}
+
+/// `GET /export/:chat_id?format=json|html|markdown|text` downloads an
+/// archived chat; JSON is the default.
+pub async fn export_chat(
+    State(state): State<AppState>,
+    Path(chat_id): Path<i64>,
+    Query(params): Query<ExportParams>,
+) -> Response {
+    let format = match params.format.as_deref().map(str::parse::<ExportFormat>).transpose() {
+        Ok(format) => format.unwrap_or(ExportFormat::Json),
+        Err(_) => return (StatusCode::BAD_REQUEST, "format must be json, html, markdown or text").into_response(),
+    };
+    if !state.chats.contains(&chat_id) {
+        return (StatusCode::NOT_FOUND, format!("Chat {} is not logged", chat_id)).into_response();
+    }
+
+    match ChatExport::load(&state.archive, chat_id) {
+        Ok(export) => (
+            [
+                (header::CONTENT_TYPE, format.content_type().to_string()),
+                (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", format.file_name())),
+            ],
+            export.render(format),
+        ).into_response(),
+        Err(e) => {
+            eprintln!("Failed to export chat {}: {}", chat_id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read the archive").into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ExportParams {
+    format: Option<String>,
+}
+
+/// Runs `tg-log-new export <chat> [--format <format>] [--output <file>]`,
+/// writing the archived chat to the format's usual file name in the current
+/// directory, to `file`, or to stdout for `-`.
+///
+/// Works offline: chats given by username or link must have been resolved
+/// by the server before.
+pub fn run_export_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut chat = None;
+    let mut format = ExportFormat::Json;
+    let mut output = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" | "-f" => {
+                format = args.next().ok_or(USAGE)?.parse().map_err(|_| "format must be json, html, markdown or text")?;
+            }
+            "--output" | "-o" => output = Some(args.next().ok_or(USAGE)?.as_str()),
+            _ if chat.is_none() && !arg.starts_with('-') => chat = Some(arg.parse::<ChatRef>()?),
+            _ => return Err(USAGE.into()),
+        }
+    }
+
+    let chat = chat.ok_or(USAGE)?;
+    let chat_id = cached_chat_id(&chat).ok_or_else(|| {
+        format!("{} has not been resolved yet; start the server once or pass the numeric chat id", chat)
+    })?;
+
+    let archive = Archive::open(&config().archive_path)?;
+    let export = ChatExport::load(&archive, chat_id)?;
+    if export.messages.is_empty() {
+        return Err(format!("No archived messages for chat {}", chat).into());
+    }
+
+    let rendered = export.render(format);
+    match output.unwrap_or(format.file_name()) {
+        "-" => std::io::stdout().write_all(rendered.as_bytes())?,
+        path => {
+            std::fs::write(path, rendered)?;
+            eprintln!("Exported {} messages of {} to {}", export.messages.len(), export.name, path);
+        }
+    }
+    Ok(())
+}
+
+/// A message as shown on the chat page, without the interactive parts.
+#[component]
+fn ExportedMessage(message: ChatMessage) -> AnyView {
+    let mut class = format!("message message-type-{}", message.message_type);
+    if message.deleted_at.is_some() {
+        class.push_str(" message-deleted");
+    }
+    let revision_count = message.revisions.len();
+    let link_preview = message.message_type == MessageType::WebPage;
+
+    if message.service.is_some() {
+        let pinned = message.reply_preview.clone().filter(|_| {
+            matches!(message.service, Some(ServiceAction::MessagePinned))
+        });
+        return view! {
+            <div class="service-message" id=format!("message-{}", message.id)>
+                <span class="service-text">{message.text.clone()}</span>
+                {pinned.map(|preview| view! {
+                    <a class="service-pinned" href=format!("#message-{}", preview.id)>{preview.snippet}</a>
+                })}
+                <span class="timestamp">{format_timestamp(message.timestamp)}</span>
+            </div>
+        }
+        .into_any();
+    }
+
+    view! {
+        <div class=class id=format!("message-{}", message.id)>
+            {message.reply_to.map(|reply_id| view! {
+                <a class="reply-indicator" href=format!("#message-{}", reply_id)>
+                    {match message.reply_preview.clone() {
+                        Some(preview) => view! {
+                            <span class="reply-sender">{preview.sender}</span>
+                            <span class="reply-snippet">{preview.snippet}</span>
+                        }.into_any(),
+                        None => format!("↳ Reply to message #{}", reply_id).into_any(),
+                    }}
+                </a>
+            })}
+            {message.forward_source().map(|source| view! {
+                <div class="forward-indicator">
+                    "↪ Forwarded from " <span class="forward-source">{source}</span>
+                    {message.forward.as_ref().map(|forward| view! {
+                        <span class="forward-date">" · sent " {format_timestamp(forward.date)}</span>
+                    })}
+                </div>
+            })}
+            <div class="message-header">
+                <span class="sender">{message.sender.clone()}</span>
+                <div class="message-meta">
+                    {message.deleted_at.map(|at| view! {
+                        <span class="deleted-badge" title=format!("Deleted at {}", format_timestamp(at))>"deleted"</span>
+                    })}
+                    {message.edited_at.map(|at| view! {
+                        <span class="edited-badge" title=format!("Edited at {}", format_timestamp(at))>"edited"</span>
+                    })}
+                    <span class="message-type-badge">{message.message_type.clone().get_emoji()}</span>
+                    <span class="timestamp">{format_timestamp(message.timestamp)}</span>
+                </div>
+            </div>
+            // Link previews go under the text, as in the app
+            {message.media_info.as_ref().filter(|_| !link_preview).map(render_media)}
+            {match &message.poll {
+                Some(poll) => render_poll(poll),
+                None => view! {
+                    <div class="message-text">
+                        {render_formatted_text(&message.text, &message.formatted_text)}
+                    </div>
+                }.into_any(),
+            }}
+            {message.media_info.as_ref().filter(|_| link_preview).map(render_media)}
+            {(revision_count > 0).then(|| view! {
+                <details class="revisions">
+                    <summary>{format!("{} earlier version(s)", revision_count)}</summary>
+                    <ol class="revision-list">
+                        {message.revisions.iter().rev().map(|revision| view! {
+                            <li class="revision">
+                                <span class="revision-time">{format_timestamp(revision.timestamp)}</span>
+                                <div class="message-text">
+                                    {render_formatted_text(&revision.text, &revision.formatted_text)}
+                                </div>
+                            </li>
+                        }).collect::<Vec<_>>()}
+                    </ol>
+                </details>
+            })}
+        </div>
+    }
+    .into_any()
+}
+
+fn render_media(media: &MediaInfo) -> AnyView {
+    if let Some(details) = &media.details {
+        return render_details(details);
+    }
+    if let Some(src) = sticker_data_url(media) {
+        let sticker = if media.mime_type.as_deref() == Some("video/webm") {
+            view! { <video class="sticker-image" src=src autoplay loop muted playsinline></video> }.into_any()
+        } else {
+            view! { <img class="sticker-image" src=src alt="Sticker" /> }.into_any()
+        };
+        return view! { <div class="sticker-container">{sticker}</div> }.into_any();
+    }
+    if let Some(src) = thumbnail_data_url(media) {
+        return view! {
+            <div class="photo-container">
+                <img class="photo-thumbnail" src=src width=media.width height=media.height alt="Photo" />
+            </div>
+        }.into_any();
+    }
+
+    view! {
+        <div class="media-info">
+            {media.file_name.clone().map(|name| view! { <div class="file-name">"📎 " {name}</div> })}
+            {media.file_size.map(|size| view! { <div class="file-size">"Size: " {format_file_size(size)}</div> })}
+            {media.mime_type.clone().map(|mime| view! { <div class="mime-type">"Type: " {mime}</div> })}
+        </div>
+    }.into_any()
+}
+
+/// Media that is not a file, as a line linking to the map or page when
+/// there is one.
+fn render_details(details: &MediaDetails) -> AnyView {
+    let link = match details {
+        MediaDetails::Location { point } => Some(point.map_url()),
+        MediaDetails::Venue { point, .. } | MediaDetails::LiveLocation { point, .. } => {
+            point.as_ref().map(GeoPoint::map_url)
+        }
+        MediaDetails::WebPage(page) => safe_link(&page.url),
+        _ => None,
+    };
+    let summary = details.summary();
+    let description = match details {
+        MediaDetails::Game { description, .. } | MediaDetails::Invoice { description, .. } => {
+            Some(description.clone()).filter(|description| !description.is_empty())
+        }
+        MediaDetails::WebPage(page) => page.description.clone(),
+        _ => None,
+    };
+    let image = match details {
+        MediaDetails::WebPage(page) => page.image.as_deref().and_then(photo_data_url),
+        _ => None,
+    };
+    view! {
+        <div class="media-details">
+            {match link {
+                Some(link) => view! { <a class="details-title" href=link>{summary}</a> }.into_any(),
+                None => view! { <span class="details-title">{summary}</span> }.into_any(),
+            }}
+            {description.map(|description| view! { <div class="details-text">{description}</div> })}
+            {image.map(|src| view! { <img class="link-image" src=src alt="" /> })}
+        </div>
+    }
+    .into_any()
+}
+
+/// A downloaded sticker as a `data:` URL, so the page needs no other files.
+/// Animated (TGS) stickers would need a player and are left out.
+fn sticker_data_url(media: &MediaInfo) -> Option<String> {
+    let name = media.file_name.as_deref()
+        .filter(|name| name.starts_with("sticker_") && !name.contains(['/', '\\']))
+        .filter(|_| media.mime_type.as_deref() != Some(TGS_MIME_TYPE))?;
+    data_url(&config().sticker_dir().join(name), media.mime_type.as_deref().unwrap_or("image/webp"))
+}
+
+/// The thumbnail of a downloaded photo as a `data:` URL, falling back to the
+/// photo itself for imported ones, which have no thumbnail.
+fn thumbnail_data_url(media: &MediaInfo) -> Option<String> {
+    photo_data_url(media.cached_photo()?)
+}
+
+/// The cached photo `name`, preferably its thumbnail, as a `data:` URL.
+fn photo_data_url(name: &str) -> Option<String> {
+    if name.contains(['/', '\\']) {
+        return None;
+    }
+    let photo_dir = config().photo_dir();
+    data_url(&photo_dir.join(thumbnail_file_name(name)), "image/jpeg")
+        .or_else(|| data_url(&photo_dir.join(name), "image/jpeg"))
+}
+
+fn data_url(path: &std::path::Path, mime_type: &str) -> Option<String> {
+    let data = std::fs::read(path).ok()?;
+    Some(format!("data:{};base64,{}", mime_type, base64::engine::general_purpose::STANDARD.encode(data)))
+}
+
+/// `result.json` as Telegram Desktop writes it for a single chat.
+#[derive(Serialize)]
+struct TdChat<'a> {
+    name: &'a str,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    id: i64,
+    messages: Vec<TdMessage<'a>>,
+}
+
+#[derive(Serialize)]
+struct TdMessage<'a> {
+    id: i32,
+    /// `message`, or `service` for chat events.
+    #[serde(rename = "type")]
+    kind: &'static str,
+    date: String,
+    date_unixtime: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    edited: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    edited_unixtime: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    from: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    actor: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    action: Option<&'static str>,
+    /// What the action was about, under Telegram Desktop's field names.
+    #[serde(flatten)]
+    action_details: serde_json::Map<String, serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    forwarded_from: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to_message_id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    photo: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    photo_file_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file_name: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    media_type: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mime_type: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    height: Option<u32>,
+    /// Places, games and invoices, under Telegram Desktop's field names.
+    #[serde(flatten)]
+    media_details: serde_json::Map<String, serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    poll: Option<TdPoll<'a>>,
+    text: TdText<'a>,
+    text_entities: Vec<TdEntity<'a>>,
+}
+
+#[derive(Serialize)]
+struct TdPoll<'a> {
+    question: &'a str,
+    closed: bool,
+    total_voters: i32,
+    answers: Vec<TdPollAnswer<'a>>,
+}
+
+#[derive(Serialize)]
+struct TdPollAnswer<'a> {
+    text: &'a str,
+    voters: i32,
+    chosen: bool,
+}
+
+impl<'a> TdPoll<'a> {
+    fn new(poll: &'a Poll) -> Self {
+        Self {
+            question: &poll.question,
+            closed: poll.closed,
+            total_voters: poll.total_voters.unwrap_or(0),
+            answers: poll
+                .answers
+                .iter()
+                .map(|answer| TdPollAnswer {
+                    text: &answer.text,
+                    voters: answer.voters.unwrap_or(0),
+                    chosen: answer.chosen,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A plain string, or a list of plain strings and entities when the text is
+/// formatted.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum TdText<'a> {
+    Plain(&'a str),
+    Formatted(Vec<TdText<'a>>),
+    Entity(TdEntity<'a>),
+}
+
+#[derive(Clone, Serialize)]
+struct TdEntity<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    text: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    href: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    document_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    language: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    collapsed: Option<bool>,
+}
+
+impl<'a> TdMessage<'a> {
+    fn new(message: &'a ChatMessage) -> Self {
+        let service = message.message_type == MessageType::System;
+        let (action, action_details) = message
+            .service
+            .as_ref()
+            .and_then(|action| td_action(action, message))
+            .unzip();
+        let action_details = action_details.unwrap_or_default();
+        // Pins name the pinned message in their details rather than replying to it
+        let pinned = matches!(message.service, Some(ServiceAction::MessagePinned));
+        let media = message.media_info.as_ref();
+        let photo = message.message_type == MessageType::Photo;
+        // Telegram Desktop keeps the size of photos apart and never names them
+        let file = media.filter(|media| !photo && media.details.is_none());
+        let (text, entities) = body(message);
+        let text_entities: Vec<TdEntity> = segments(text, entities)
+            .into_iter()
+            .map(|(text, entity)| TdEntity {
+                kind: entity.map_or("plain", |entity| td_entity_type(&entity.entity_type)),
+                text,
+                href: entity
+                    .filter(|entity| entity.entity_type == EntityType::TextLink)
+                    .and_then(|entity| entity.url.as_deref()),
+                document_id: entity.and_then(|entity| match entity.entity_type {
+                    EntityType::CustomEmoji { document_id } => Some(document_id.to_string()),
+                    _ => None,
+                }),
+                language: entity.and_then(|entity| entity.language.as_deref()),
+                user_id: entity.and_then(|entity| entity.user_id),
+                collapsed: entity
+                    .filter(|entity| entity.entity_type == EntityType::ExpandableBlockquote)
+                    .map(|_| true),
+            })
+            .collect();
+        let text = match text_entities.as_slice() {
+            [] => TdText::Plain(""),
+            [only] if only.kind == "plain" => TdText::Plain(only.text),
+            parts => TdText::Formatted(
+                parts
+                    .iter()
+                    .map(|part| match part.kind {
+                        "plain" => TdText::Plain(part.text),
+                        _ => TdText::Entity(part.clone()),
+                    })
+                    .collect(),
+            ),
+        };
+
+        Self {
+            id: message.id,
+            kind: if service { "service" } else { "message" },
+            date: td_date(message.timestamp),
+            date_unixtime: message.timestamp.to_string(),
+            edited: message.edited_at.map(td_date),
+            edited_unixtime: message.edited_at.map(|at| at.to_string()),
+            from: (!service).then_some(message.sender.as_str()),
+            actor: service.then_some(message.sender.as_str()),
+            action,
+            action_details,
+            media_details: media
+                .and_then(|media| media.details.as_ref())
+                .map(td_media)
+                .unwrap_or_default(),
+            poll: message.poll.as_ref().map(TdPoll::new),
+            forwarded_from: message.forwarded_from.as_deref(),
+            reply_to_message_id: message.reply_to.filter(|_| !pinned),
+            photo: photo.then_some(FILE_NOT_INCLUDED),
+            photo_file_size: media.filter(|_| photo).and_then(|media| media.file_size),
+            file: file.map(|_| FILE_NOT_INCLUDED),
+            file_name: file.and_then(|media| media.file_name.as_deref()),
+            file_size: file.and_then(|media| media.file_size),
+            media_type: match message.message_type {
+                MessageType::Sticker => Some("sticker"),
+                MessageType::Video => Some("video_file"),
+                MessageType::Audio => Some("audio_file"),
+                MessageType::Voice => Some("voice_message"),
+                _ => None,
+            },
+            mime_type: file.and_then(|media| media.mime_type.as_deref()),
+            width: media.and_then(|media| media.width),
+            height: media.and_then(|media| media.height),
+            text,
+            text_entities,
+        }
+    }
+}
+
+/// Telegram Desktop's name for service action `action` of `message`, with
+/// the fields it exports for it. `None` for actions it has no name for.
+fn td_action(
+    action: &ServiceAction,
+    message: &ChatMessage,
+) -> Option<(&'static str, serde_json::Map<String, serde_json::Value>)> {
+    use serde_json::json;
+
+    let names = |members: &[ServiceUser]| {
+        json!(members.iter().map(|member| member.name.as_deref()).collect::<Vec<_>>())
+    };
+    let (name, details) = match action {
+        ServiceAction::GroupCreated { title } => ("create_group", json!({ "title": title, "members": [] })),
+        ServiceAction::ChannelCreated { title } => ("create_channel", json!({ "title": title })),
+        ServiceAction::Joined => ("invite_members", json!({ "members": [message.sender] })),
+        ServiceAction::JoinedByLink => ("join_group_by_link", json!({})),
+        ServiceAction::JoinedByRequest => ("join_group_by_request", json!({})),
+        ServiceAction::MembersAdded { members } => ("invite_members", json!({ "members": names(members) })),
+        ServiceAction::Left => ("remove_members", json!({ "members": [message.sender] })),
+        ServiceAction::MemberRemoved { member } => {
+            ("remove_members", json!({ "members": names(std::slice::from_ref(member)) }))
+        }
+        ServiceAction::TitleChanged { title } => ("edit_group_title", json!({ "title": title })),
+        ServiceAction::PhotoChanged => ("edit_group_photo", json!({})),
+        ServiceAction::PhotoRemoved => ("delete_group_photo", json!({})),
+        ServiceAction::MessagePinned => ("pin_message", json!({ "message_id": message.reply_to })),
+        ServiceAction::HistoryCleared => ("clear_history", json!({})),
+        ServiceAction::MigratedToSupergroup { .. } => ("migrate_to_supergroup", json!({})),
+        ServiceAction::MigratedFromGroup { title, .. } => ("migrate_from_group", json!({ "title": title })),
+        ServiceAction::Call { missed, duration, .. } => (
+            "phone_call",
+            json!({ "duration_seconds": duration, "discard_reason": if *missed { "missed" } else { "hangup" } }),
+        ),
+        ServiceAction::VideoChatStarted => ("group_call", json!({})),
+        ServiceAction::VideoChatEnded { duration } => ("group_call", json!({ "duration": duration })),
+        ServiceAction::VideoChatScheduled { date } => ("group_call_scheduled", json!({ "schedule_date": td_date(*date) })),
+        ServiceAction::VideoChatInvite { members } => ("invite_to_group_call", json!({ "members": names(members) })),
+        ServiceAction::TopicCreated { title, .. } => ("topic_created", json!({ "title": title })),
+        ServiceAction::TopicEdited { title, .. } => ("topic_edit", json!({ "new_title": title })),
+        ServiceAction::ScreenshotTaken => ("take_screenshot", json!({})),
+        ServiceAction::GameScore { score } => ("score_in_game", json!({ "score": score })),
+        ServiceAction::PaymentSent { currency, total_amount } => {
+            ("send_payment", json!({ "amount": total_amount, "currency": currency }))
+        }
+        ServiceAction::PremiumGifted { months } => ("send_premium_gift", json!({ "months": months })),
+        ServiceAction::ContactSignedUp => ("joined_telegram", json!({})),
+        ServiceAction::AutoDeleteChanged { period } => ("set_messages_ttl", json!({ "period": period })),
+        ServiceAction::ThemeChanged { emoticon } => ("edit_chat_theme", json!({ "emoticon": emoticon })),
+        ServiceAction::BotAllowed => ("allow_sending_messages", json!({})),
+        ServiceAction::Custom { .. } | ServiceAction::Other { .. } => return None,
+    };
+    match details {
+        serde_json::Value::Object(details) => Some((name, details)),
+        _ => Some((name, serde_json::Map::new())),
+    }
+}
+
+/// The fields Telegram Desktop exports for media that is not a file. It has
+/// none for dice or link previews.
+fn td_media(details: &MediaDetails) -> serde_json::Map<String, serde_json::Value> {
+    use serde_json::json;
+
+    let location = |point: &Option<GeoPoint>| {
+        point.map(|point| json!({ "latitude": point.latitude, "longitude": point.longitude }))
+    };
+    let fields = match details {
+        MediaDetails::Location { point } => json!({ "location_information": location(&Some(*point)) }),
+        MediaDetails::Venue { point, title, address, .. } => {
+            json!({ "place_name": title, "address": address, "location_information": location(point) })
+        }
+        MediaDetails::LiveLocation { point, period, .. } => {
+            json!({ "location_information": location(point), "live_location_period_seconds": period })
+        }
+        MediaDetails::Game { title, description, .. } => {
+            json!({ "game_title": title, "game_description": description })
+        }
+        MediaDetails::Invoice { title, description, currency, total_amount, .. } => json!({
+            "invoice_information": {
+                "title": title,
+                "description": description,
+                "amount": total_amount,
+                "currency": currency,
+            }
+        }),
+        MediaDetails::Dice { .. } | MediaDetails::WebPage(_) => json!({}),
+    };
+    match fields {
+        serde_json::Value::Object(fields) => fields.into_iter().filter(|(_, value)| !value.is_null()).collect(),
+        _ => serde_json::Map::new(),
+    }
+}
+
+/// Telegram Desktop's name for the kind of chat, from the peer cache. Chats
+/// that were never resolved are assumed to be groups.
+fn td_chat_type(chat_id: i64) -> &'static str {
+    match PEER_CACHE.get(&ChatRef::Id(chat_id)).map(|packed| packed.ty) {
+        Some(PackedType::User) => "personal_chat",
+        Some(PackedType::Bot) => "bot_chat",
+        Some(PackedType::Megagroup) | Some(PackedType::Gigagroup) => "private_supergroup",
+        Some(PackedType::Broadcast) => "private_channel",
+        Some(PackedType::Chat) | None => "private_group",
+    }
+}
+
+fn td_entity_type(entity_type: &EntityType) -> &'static str {
+    match entity_type {
+        EntityType::Bold => "bold",
+        EntityType::Italic => "italic",
+        EntityType::Code => "code",
+        EntityType::Pre => "pre",
+        EntityType::Link => "link",
+        EntityType::TextLink => "text_link",
+        EntityType::Mention => "mention",
+        EntityType::Hashtag => "hashtag",
+        EntityType::BotCommand => "bot_command",
+        EntityType::Email => "email",
+        EntityType::Phone => "phone",
+        EntityType::Underline => "underline",
+        EntityType::Strikethrough => "strikethrough",
+        EntityType::Spoiler => "spoiler",
+        EntityType::CustomEmoji { .. } => "custom_emoji",
+        EntityType::MentionName => "mention_name",
+        EntityType::Cashtag => "cashtag",
+        EntityType::BankCard => "bank_card",
+        // Telegram Desktop marks expandable ones with `collapsed`
+        EntityType::Blockquote | EntityType::ExpandableBlockquote => "blockquote",
+    }
+}
+
+fn td_date(timestamp: i64) -> String {
+    chrono::DateTime::<chrono::Utc>::from_timestamp(timestamp, 0)
+        .unwrap_or_default()
+        .format("%Y-%m-%dT%H:%M:%S")
+        .to_string()
+}
+
+/// The text the message was sent with. For media without a caption the
+/// archive holds a placeholder such as "[Photo]", which is left out.
+fn body(message: &ChatMessage) -> (&str, &[TextEntity]) {
+    // The text of polls is a placeholder naming the question
+    if message.poll.is_some() {
+        return ("", &[]);
+    }
+    match &message.media_info {
+        Some(media) if media.caption.is_none() => ("", &[]),
+        _ => (&message.text, &message.formatted_text),
+    }
+}
+
+/// Splits `text` into runs that are either plain or covered by one entity.
+/// Entities that overlap an earlier one or do not fit the text are dropped.
+fn segments<'a>(text: &'a str, entities: &'a [TextEntity]) -> Vec<(&'a str, Option<&'a TextEntity>)> {
+    let mut sorted: Vec<&TextEntity> = entities.iter().collect();
+    sorted.sort_by_key(|entity| entity.offset);
+
+    let mut segments = Vec::new();
+    let mut pos = 0;
+    for entity in sorted {
+        let Some((start, end)) = byte_range(text, entity) else {
+            continue;
+        };
+        if start < pos {
+            continue;
+        }
+        if start > pos {
+            segments.push((&text[pos..start], None));
+        }
+        segments.push((&text[start..end], Some(entity)));
+        pos = end;
+    }
+    if pos < text.len() {
+        segments.push((&text[pos..], None));
+    }
+    segments
+}
+
+/// Markdown for formatted text, with nested entities nested in the markup.
+fn markdown(spans: &[Span]) -> String {
+    spans.iter().map(markdown_span).collect()
+}
+
+fn markdown_span(span: &Span) -> String {
+    let (entity, text, children) = match span {
+        Span::Text(text) => return escape_markdown(text),
+        Span::Entity { entity, text, children } => (entity, *text, children),
+    };
+    let inner = markdown(children);
+    match entity.entity_type {
+        EntityType::Bold => format!("**{}**", inner),
+        EntityType::Italic => format!("_{}_", inner),
+        EntityType::Underline => format!("<u>{}</u>", inner),
+        EntityType::Strikethrough => format!("~~{}~~", inner),
+        // Padded so backticks at either end are not taken for the fence
+        EntityType::Code if text.contains('`') => format!("{0} {1} {0}", backtick_fence(text, 1), text),
+        EntityType::Code => format!("`{}`", text),
+        EntityType::Pre => {
+            let fence = backtick_fence(text, 3);
+            format!("\n{0}{1}\n{2}\n{0}\n", fence, entity.language.as_deref().unwrap_or_default(), text)
+        }
+        EntityType::Blockquote | EntityType::ExpandableBlockquote => {
+            let quoted: Vec<String> = inner.lines().map(|line| format!("> {}", line)).collect();
+            format!("\n{}\n", quoted.join("\n"))
+        }
+        EntityType::MentionName => match entity.user_id {
+            Some(user_id) => format!("[{}](tg://user?id={})", inner, user_id),
+            None => inner,
+        },
+        EntityType::TextLink => match entity.url.as_deref().and_then(safe_link) {
+            Some(url) => format!("[{}]({})", inner, url.replace(' ', "%20").replace(')', "%29")),
+            None => inner,
+        },
+        EntityType::Link
+        | EntityType::Mention
+        | EntityType::Hashtag
+        | EntityType::BotCommand
+        | EntityType::Email
+        | EntityType::Phone
+        | EntityType::Spoiler
+        | EntityType::Cashtag
+        | EntityType::BankCard
+        | EntityType::CustomEmoji { .. } => inner,
+    }
+}
+
+/// Backticks enough to fence `text`: one more than its longest run of them,
+/// and at least `min`.
+fn backtick_fence(text: &str, min: usize) -> String {
+    let longest = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    "`".repeat((longest + 1).max(min))
+}
+
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#' | '|' | '~') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// "Forwarded from …" with the original sender and date, for the text
+/// formats.
+fn forward_line(message: &ChatMessage) -> Option<String> {
+    let source = message.forward_source()?;
+    Some(match &message.forward {
+        Some(forward) => format!("Forwarded from {}, sent {}", source, format_timestamp(forward.date)),
+        None => format!("Forwarded from {}", source),
+    })
+}
+
+/// "In reply to …" with the replied-to sender and text when archived.
+fn reply_line(message: &ChatMessage) -> Option<String> {
+    let reply_to = message.reply_to?;
+    Some(match &message.reply_preview {
+        Some(preview) => format!("In reply to {}: {}", preview.sender, preview.snippet),
+        None => format!("In reply to message #{}", reply_to),
+    })
+}
+
+/// " (edited)", " (deleted)" or both, for the header of a message.
+fn status_suffix(message: &ChatMessage) -> String {
+    let mut suffix = String::new();
+    if message.edited_at.is_some() {
+        suffix.push_str(" (edited)");
+    }
+    if message.deleted_at.is_some() {
+        suffix.push_str(" (deleted)");
+    }
+    suffix
+}
+
+/// One line describing the attached media, such as "📎 report.pdf (1.2 MB)".
+fn media_summary(message: &ChatMessage) -> Option<String> {
+    let media = message.media_info.as_ref()?;
+    let emoji = message.message_type.clone().get_emoji();
+    if let Some(details) = &media.details {
+        return Some(format!("{} {}", emoji, details.summary()));
+    }
+    let name = media.file_name.clone().unwrap_or_else(|| message.message_type.to_string());
+    Some(match media.file_size {
+        Some(size) => format!("{} {} ({})", emoji, name, format_file_size(size)),
+        None => format!("{} {}", emoji, name),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn message(id: i32, sender: &str, text: &str, formatted_text: serde_json::Value) -> ChatMessage {
+        serde_json::from_value(json!({
+            "id": id,
+            "text": text,
+            "formatted_text": formatted_text,
+            "timestamp": 1_709_294_400 + i64::from(id) * 60,
+            "sender": sender,
+            "chat_id": 42,
+            "message_type": "Text",
+            "media_info": null,
+            "reply_to": null,
+            "forwarded_from": null,
+        }))
+        .unwrap()
+    }
+
+    fn entity(offset: usize, length: usize, entity_type: EntityType) -> TextEntity {
+        TextEntity { offset, length, entity_type, url: None, language: None, user_id: None }
+    }
+
+    fn export(messages: Vec<ChatMessage>) -> ChatExport {
+        ChatExport { id: 42, name: "Test <chat> *1*".to_string(), messages }
+    }
+
+    #[test]
+    fn json_export_imports_back() {
+        let mut formatted = message(1, "Alice", "Hi 👋 wörld, see example", json!([]));
+        formatted.formatted_text = vec![
+            entity(6, 5, EntityType::Bold),
+            TextEntity { url: Some("https://example.com".to_string()), ..entity(17, 7, EntityType::TextLink) },
+        ];
+        formatted.edited_at = Some(1_709_300_000);
+        let mut reply = message(2, "Bob", "```not code```", json!([]));
+        reply.reply_to = Some(1);
+        reply.forwarded_from = Some("Carol".to_string());
+        let mut joined = message(3, "Dave", "Dave joined the group", json!([]));
+        joined.message_type = MessageType::System;
+        joined.service = Some(ServiceAction::Joined);
+        let original = vec![formatted, reply, joined];
+
+        // JSON exports look chat types up in the peer cache
+        crate::config::install_test_config();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("result.json");
+        std::fs::write(&path, export(original.clone()).render(ExportFormat::Json)).unwrap();
+
+        let archive = Archive::open(":memory:").unwrap();
+        let reports = crate::import::import_export(&archive, &path, None).unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].imported, 3);
+        assert!(reports[0].unmapped.is_empty(), "{:?}", reports[0].unmapped);
+        assert!(reports[0].dropped_entities.is_empty(), "{:?}", reports[0].dropped_entities);
+
+        let imported = archive.chat_messages(42).unwrap();
+        assert_eq!(imported.len(), original.len());
+        for (imported, original) in imported.iter().zip(&original) {
+            assert_eq!(imported.id, original.id);
+            assert_eq!(imported.text, original.text);
+            assert_eq!(imported.formatted_text, original.formatted_text);
+            assert_eq!(imported.timestamp, original.timestamp);
+            assert_eq!(imported.sender, original.sender);
+            assert_eq!(imported.message_type, original.message_type);
+            assert_eq!(imported.reply_to, original.reply_to);
+            assert_eq!(imported.forwarded_from, original.forwarded_from);
+            assert_eq!(imported.service, original.service);
+            assert_eq!(imported.edited_at, original.edited_at);
+            assert!(imported.imported);
+        }
+    }
+
+    #[test]
+    fn markdown_escapes_text_but_not_formatting() {
+        let mut bold = message(1, "a_b*c", "*not bold* [x](y) # ~z~ and bold", json!([]));
+        bold.formatted_text = vec![entity(28, 4, EntityType::Bold)];
+        let mut snippet = message(2, "Bob", "a `b` *c*", json!([]));
+        snippet.formatted_text = vec![entity(0, 9, EntityType::Code)];
+        let mut ticks = message(3, "Bob", "x `` y", json!([]));
+        ticks.formatted_text = vec![entity(0, 6, EntityType::Code)];
+        let mut block = message(4, "Bob", "```\nnot the end\n````", json!([]));
+        block.formatted_text = vec![TextEntity { language: Some("md".to_string()), ..entity(0, 20, EntityType::Pre) }];
+
+        let markdown = export(vec![bold, snippet, ticks, block]).render(ExportFormat::Markdown);
+        assert!(markdown.starts_with("# Test \\<chat\\> \\*1\\*\n"), "{}", markdown);
+        assert!(markdown.contains("### a\\_b\\*c · "), "{}", markdown);
+        assert!(markdown.contains("\\*not bold\\* \\[x\\](y) \\# \\~z\\~ and **bold**"), "{}", markdown);
+        assert!(markdown.contains("`` a `b` *c* ``"), "{}", markdown);
+        assert!(markdown.contains("``` x `` y ```"), "{}", markdown);
+        assert!(markdown.contains("`````md\n```\nnot the end\n````\n`````"), "{}", markdown);
+    }
+
+    #[test]
+    fn html_escapes_text_and_names() {
+        let mut link = message(1, "<b>Eve</b>", "<script>alert(1)</script> & \"quotes\"", json!([]));
+        link.formatted_text = vec![TextEntity {
+            url: Some("https://example.com/?a=1&b=\"2\"".to_string()),
+            ..entity(0, 8, EntityType::TextLink)
+        }];
+
+        let html = export(vec![link]).render(ExportFormat::Html);
+        assert!(html.contains("<title>Test &lt;chat&gt; *1*</title>"), "{}", html);
+        assert!(!html.contains("<script>"), "{}", html);
+        assert!(!html.contains("<b>Eve</b>"), "{}", html);
+        assert!(html.contains("&lt;b&gt;Eve&lt;/b&gt;"), "{}", html);
+        assert!(html.contains("alert(1)&lt;/script&gt; &amp; "), "{}", html);
+        assert!(html.contains("href=\"https://example.com/?a=1&amp;b=&quot;2&quot;\""), "{}", html);
+    }
+
+    #[test]
+    fn unsafe_links_are_not_exported() {
+        let mut link = message(1, "Eve", "click me", json!([]));
+        link.formatted_text = vec![TextEntity {
+            url: Some("javascript:alert(1)".to_string()),
+            ..entity(0, 5, EntityType::TextLink)
+        }];
+
+        let chat = export(vec![link]);
+        let html = chat.render(ExportFormat::Html);
+        assert!(!html.contains("javascript:"), "{}", html);
+        assert!(html.contains("<span class=\"message-link\"><span>click</span></span>"), "{}", html);
+        let markdown = chat.render(ExportFormat::Markdown);
+        assert!(!markdown.contains("javascript:"), "{}", markdown);
+    }
+}
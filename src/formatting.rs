@@ -0,0 +1,319 @@
+//! Turns a message's text and its Telegram entities into a tree of spans.
+//!
+//! Entity offsets and lengths count UTF-16 code units, as Telegram does, so
+//! they are converted to byte indices before the text is sliced. Entities may
+//! nest (bold inside a link) or cross each other; a crossing entity is split
+//! at the boundary of the one it crosses, so every part of the text keeps all
+//! the formatting that covers it.
+
+use crate::telegram::TextEntity;
+
+/// A run of a message's text, either plain or formatted by an entity.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Span<'a> {
+    Text(&'a str),
+    Entity {
+        entity: &'a TextEntity,
+        /// All of the text the entity covers here.
+        text: &'a str,
+        children: Vec<Span<'a>>,
+    },
+}
+
+/// Schemes a link in a message may use. Anything else, `javascript:` in
+/// particular, is shown as plain text.
+const LINK_SCHEMES: &[&str] = &["http", "https", "tg", "mailto", "tel"];
+
+/// `url` as it can go into an `href`: unchanged with one of `LINK_SCHEMES`,
+/// as `https` without a scheme like Telegram's own links (`t.me/...`), and
+/// `None` otherwise.
+pub fn safe_link(url: &str) -> Option<String> {
+    let scheme = url
+        .split_once(':')
+        .map(|(scheme, _)| scheme)
+        .filter(|scheme| {
+            scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+        });
+    match scheme {
+        Some(scheme) => LINK_SCHEMES
+            .iter()
+            .any(|allowed| scheme.eq_ignore_ascii_case(allowed))
+            .then(|| url.to_string()),
+        None if url.trim().is_empty() || url.starts_with(|c: char| c.is_whitespace() || c.is_control()) => None,
+        None => Some(format!("https://{}", url.trim_start_matches('/'))),
+    }
+}
+
+/// Byte index in `text` of `offset` UTF-16 code units, or `None` if that
+/// falls inside a character or past the end.
+pub fn utf16_to_byte_index(text: &str, offset: usize) -> Option<usize> {
+    let mut units = 0;
+    for (index, c) in text.char_indices() {
+        if units >= offset {
+            return (units == offset).then_some(index);
+        }
+        units += c.len_utf16();
+    }
+    (units == offset).then_some(text.len())
+}
+
+/// Byte range in `text` that `entity` covers. `None` if it is empty or does
+/// not line up with the characters of `text`.
+pub fn byte_range(text: &str, entity: &TextEntity) -> Option<(usize, usize)> {
+    let start = utf16_to_byte_index(text, entity.offset)?;
+    let end = utf16_to_byte_index(text, entity.offset.checked_add(entity.length)?)?;
+    (start < end).then_some((start, end))
+}
+
+/// Builds the span tree of `text` formatted with `entities`, in any order.
+/// Entities that do not fit the text are left out; the plain text of the
+/// tree is always all of `text`.
+pub fn span_tree<'a>(text: &'a str, entities: &'a [TextEntity]) -> Vec<Span<'a>> {
+    let ranges = entities
+        .iter()
+        .enumerate()
+        .filter_map(|(index, entity)| {
+            let (start, end) = byte_range(text, entity)?;
+            Some(Range { start, end, index, entity })
+        })
+        .collect();
+    build(text, 0, text.len(), ranges)
+}
+
+#[derive(Clone, Copy)]
+struct Range<'a> {
+    start: usize,
+    end: usize,
+    /// Position in the message's entities, so equal ranges nest in order.
+    index: usize,
+    entity: &'a TextEntity,
+}
+
+/// Spans of `text[start..end]`, given `ranges` that all lie inside it.
+fn build<'a>(text: &'a str, start: usize, end: usize, mut ranges: Vec<Range<'a>>) -> Vec<Span<'a>> {
+    let mut spans = Vec::new();
+    let mut pos = start;
+    while !ranges.is_empty() {
+        // The outermost entity is the longest one starting first
+        ranges.sort_by_key(|range| (range.start, std::cmp::Reverse(range.end), range.index));
+        let outer = ranges.remove(0);
+        if outer.start > pos {
+            spans.push(Span::Text(&text[pos..outer.start]));
+        }
+
+        // Whatever starts inside `outer` nests in it, up to its end; the
+        // rest carries on after it
+        let mut inside = Vec::new();
+        let mut after = Vec::new();
+        for range in ranges {
+            if range.start >= outer.end {
+                after.push(range);
+                continue;
+            }
+            inside.push(Range { end: range.end.min(outer.end), ..range });
+            if range.end > outer.end {
+                after.push(Range { start: outer.end, ..range });
+            }
+        }
+
+        spans.push(Span::Entity {
+            entity: outer.entity,
+            text: &text[outer.start..outer.end],
+            children: build(text, outer.start, outer.end, inside),
+        });
+        pos = outer.end;
+        ranges = after;
+    }
+    if pos < end {
+        spans.push(Span::Text(&text[pos..end]));
+    }
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telegram::EntityType;
+    use proptest::prelude::*;
+
+    #[test]
+    fn only_safe_links_are_kept() {
+        let cases = [
+            ("https://example.com/a?b=c", Some("https://example.com/a?b=c")),
+            ("HTTP://example.com", Some("HTTP://example.com")),
+            ("tg://user?id=1", Some("tg://user?id=1")),
+            ("mailto:a@example.com", Some("mailto:a@example.com")),
+            ("tel:+123", Some("tel:+123")),
+            ("t.me/tg_log", Some("https://t.me/tg_log")),
+            ("//example.com", Some("https://example.com")),
+            ("javascript:alert(1)", None),
+            ("JaVaScRiPt:alert(1)", None),
+            (" javascript:alert(1)", None),
+            ("java\tscript:alert(1)", Some("https://java\tscript:alert(1)")),
+            ("data:text/html,<script>", None),
+            ("vbscript:msgbox", None),
+            ("", None),
+        ];
+        for (url, expected) in cases {
+            assert_eq!(safe_link(url).as_deref(), expected, "{:?}", url);
+        }
+    }
+
+    fn entity(offset: usize, length: usize, entity_type: EntityType) -> TextEntity {
+        TextEntity { offset, length, entity_type, url: None, language: None, user_id: None }
+    }
+
+    /// The plain text under `spans`, in order.
+    fn plain_text(spans: &[Span]) -> String {
+        spans
+            .iter()
+            .map(|span| match span {
+                Span::Text(text) => text.to_string(),
+                Span::Entity { children, .. } => plain_text(children),
+            })
+            .collect()
+    }
+
+    /// For every byte of the text, the entities around it in the tree,
+    /// by their position in `entities`.
+    fn coverage(spans: &[Span], entities: &[TextEntity], around: &mut Vec<usize>, out: &mut Vec<Vec<usize>>) {
+        for span in spans {
+            match span {
+                Span::Text(text) => {
+                    let mut covering = around.clone();
+                    covering.sort();
+                    out.extend(std::iter::repeat_n(covering, text.len()));
+                }
+                Span::Entity { entity, children, .. } => {
+                    around.push(entities.iter().position(|e| std::ptr::eq(e, *entity)).unwrap());
+                    coverage(children, entities, around, out);
+                    around.pop();
+                }
+            }
+        }
+    }
+
+    fn any_entity_type() -> impl Strategy<Value = EntityType> {
+        prop_oneof![
+            Just(EntityType::Bold),
+            Just(EntityType::Italic),
+            Just(EntityType::Code),
+            Just(EntityType::TextLink),
+            Just(EntityType::Spoiler),
+            Just(EntityType::Blockquote),
+        ]
+    }
+
+    /// Text mixing ASCII, Cyrillic, CJK, emoji (two UTF-16 units each) and
+    /// combining marks, with entities that may overlap, nest, cross, repeat
+    /// or run past the end.
+    fn text_and_entities() -> impl Strategy<Value = (String, Vec<TextEntity>)> {
+        "[a-z !\\n]{0,4}([а-яё]{0,4}[😀-🙏🇦-🇿]{0,3}[中文字]{0,2}e\u{301}?[a-z ]{0,4}){0,4}".prop_flat_map(|text| {
+            let units = text.encode_utf16().count();
+            let entities = prop::collection::vec(
+                (0..=units + 2, 0..=units + 2, any_entity_type())
+                    .prop_map(|(offset, length, entity_type)| entity(offset, length, entity_type)),
+                0..6,
+            );
+            (Just(text), entities)
+        })
+    }
+
+    #[test]
+    fn offsets_count_utf16_units() {
+        let text = "a😀б";
+        assert_eq!(utf16_to_byte_index(text, 0), Some(0));
+        assert_eq!(utf16_to_byte_index(text, 1), Some(1));
+        // Inside the surrogate pair of the emoji
+        assert_eq!(utf16_to_byte_index(text, 2), None);
+        assert_eq!(utf16_to_byte_index(text, 3), Some(5));
+        assert_eq!(utf16_to_byte_index(text, 4), Some(7));
+        assert_eq!(utf16_to_byte_index(text, 5), None);
+    }
+
+    #[test]
+    fn bold_inside_link() {
+        let entities = [entity(0, 11, EntityType::TextLink), entity(7, 4, EntityType::Bold)];
+        let spans = span_tree("Привет мир!", &entities);
+        assert_eq!(
+            spans,
+            vec![
+                Span::Entity {
+                    entity: &entities[0],
+                    text: "Привет мир!",
+                    children: vec![
+                        Span::Text("Привет "),
+                        Span::Entity { entity: &entities[1], text: "мир!", children: vec![Span::Text("мир!")] },
+                    ],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn crossing_entities_are_split() {
+        let entities = [entity(0, 4, EntityType::Bold), entity(2, 4, EntityType::Italic)];
+        let spans = span_tree("abcdef", &entities);
+        assert_eq!(
+            spans,
+            vec![
+                Span::Entity {
+                    entity: &entities[0],
+                    text: "abcd",
+                    children: vec![
+                        Span::Text("ab"),
+                        Span::Entity { entity: &entities[1], text: "cd", children: vec![Span::Text("cd")] },
+                    ],
+                },
+                Span::Entity { entity: &entities[1], text: "ef", children: vec![Span::Text("ef")] },
+            ]
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn byte_index_matches_utf16_prefix(text in "\\PC{0,24}") {
+            for (index, _) in text.char_indices().chain([(text.len(), ' ')]) {
+                let units = text[..index].encode_utf16().count();
+                prop_assert_eq!(utf16_to_byte_index(&text, units), Some(index));
+            }
+        }
+
+        #[test]
+        fn tree_keeps_all_of_the_text((text, entities) in text_and_entities()) {
+            prop_assert_eq!(plain_text(&span_tree(&text, &entities)), text);
+        }
+
+        #[test]
+        fn entity_text_is_its_children((text, entities) in text_and_entities()) {
+            fn check(spans: &[Span]) -> Result<(), TestCaseError> {
+                for span in spans {
+                    if let Span::Entity { text, children, .. } = span {
+                        prop_assert!(!text.is_empty());
+                        prop_assert_eq!(plain_text(children), *text);
+                        check(children)?;
+                    }
+                }
+                Ok(())
+            }
+            check(&span_tree(&text, &entities))?;
+        }
+
+        #[test]
+        fn every_character_keeps_its_formatting((text, entities) in text_and_entities()) {
+            let mut expected = vec![Vec::new(); text.len()];
+            for (index, entity) in entities.iter().enumerate() {
+                if let Some((start, end)) = byte_range(&text, entity) {
+                    for covering in &mut expected[start..end] {
+                        covering.push(index);
+                    }
+                }
+            }
+
+            let mut actual = Vec::new();
+            coverage(&span_tree(&text, &entities), &entities, &mut Vec::new(), &mut actual);
+            prop_assert_eq!(actual, expected);
+        }
+    }
+}
@@ -0,0 +1,284 @@
+//! Syntax highlighting for code blocks sent with a language.
+//!
+//! This is a small tokenizer rather than a full grammar: it picks out
+//! comments, strings, numbers and keywords, which is what makes a snippet in
+//! a chat readable. It runs the same on the server, in the browser and in
+//! HTML exports, so highlighted blocks need no script to show.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenKind {
+    Plain,
+    Keyword,
+    String,
+    Number,
+    Comment,
+}
+
+impl TokenKind {
+    /// CSS class the token is drawn with; plain text has none.
+    pub fn class(self) -> Option<&'static str> {
+        match self {
+            TokenKind::Plain => None,
+            TokenKind::Keyword => Some("hl-keyword"),
+            TokenKind::String => Some("hl-string"),
+            TokenKind::Number => Some("hl-number"),
+            TokenKind::Comment => Some("hl-comment"),
+        }
+    }
+}
+
+struct Syntax {
+    keywords: &'static [&'static str],
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    quotes: &'static [char],
+    /// Whether keywords match in any case, as in SQL.
+    case_insensitive: bool,
+}
+
+const C_COMMENTS: &[&str] = &["//"];
+const HASH_COMMENTS: &[&str] = &["#"];
+
+const RUST: Syntax = Syntax {
+    keywords: &[
+        "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern", "false",
+        "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return",
+        "self", "Self", "static", "struct", "super", "trait", "true", "type", "unsafe", "use", "where", "while",
+    ],
+    line_comments: C_COMMENTS,
+    block_comment: Some(("/*", "*/")),
+    // Single quotes also start lifetimes, so only double quotes are strings
+    quotes: &['"'],
+    case_insensitive: false,
+};
+
+const PYTHON: Syntax = Syntax {
+    keywords: &[
+        "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del", "elif", "else",
+        "except", "False", "finally", "for", "from", "global", "if", "import", "in", "is", "lambda", "None",
+        "nonlocal", "not", "or", "pass", "raise", "return", "True", "try", "while", "with", "yield",
+    ],
+    line_comments: HASH_COMMENTS,
+    block_comment: None,
+    quotes: &['"', '\''],
+    case_insensitive: false,
+};
+
+const JAVASCRIPT: Syntax = Syntax {
+    keywords: &[
+        "async", "await", "break", "case", "catch", "class", "const", "continue", "default", "delete", "do",
+        "else", "export", "extends", "false", "finally", "for", "from", "function", "if", "import", "in",
+        "instanceof", "interface", "let", "new", "null", "return", "switch", "this", "throw", "true", "try",
+        "type", "typeof", "undefined", "var", "void", "while", "yield",
+    ],
+    line_comments: C_COMMENTS,
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"', '\'', '`'],
+    case_insensitive: false,
+};
+
+const GO: Syntax = Syntax {
+    keywords: &[
+        "break", "case", "chan", "const", "continue", "default", "defer", "else", "false", "for", "func", "go",
+        "goto", "if", "import", "interface", "map", "nil", "package", "range", "return", "select", "struct",
+        "switch", "true", "type", "var",
+    ],
+    line_comments: C_COMMENTS,
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"', '\'', '`'],
+    case_insensitive: false,
+};
+
+const C_FAMILY: Syntax = Syntax {
+    keywords: &[
+        "abstract", "auto", "bool", "break", "case", "catch", "char", "class", "const", "continue", "default",
+        "delete", "do", "double", "else", "enum", "extends", "false", "final", "float", "for", "fun", "if",
+        "implements", "import", "int", "interface", "long", "namespace", "new", "null", "nullptr", "override",
+        "package", "private", "protected", "public", "return", "short", "signed", "sizeof", "static", "struct",
+        "switch", "template", "this", "throw", "true", "try", "typedef", "unsigned", "using", "val", "var",
+        "virtual", "void", "volatile", "when", "while",
+    ],
+    line_comments: C_COMMENTS,
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"', '\''],
+    case_insensitive: false,
+};
+
+const SHELL: Syntax = Syntax {
+    keywords: &[
+        "case", "do", "done", "echo", "elif", "else", "esac", "exit", "export", "fi", "for", "function", "if",
+        "in", "local", "return", "then", "until", "while",
+    ],
+    line_comments: HASH_COMMENTS,
+    block_comment: None,
+    quotes: &['"', '\''],
+    case_insensitive: false,
+};
+
+const SQL: Syntax = Syntax {
+    keywords: &[
+        "and", "as", "asc", "by", "create", "delete", "desc", "distinct", "drop", "from", "group", "having",
+        "index", "insert", "into", "join", "left", "limit", "not", "null", "on", "or", "order", "primary",
+        "select", "set", "table", "update", "values", "where",
+    ],
+    line_comments: &["--"],
+    block_comment: Some(("/*", "*/")),
+    quotes: &['\'', '"'],
+    case_insensitive: true,
+};
+
+const RUBY: Syntax = Syntax {
+    keywords: &[
+        "begin", "class", "def", "do", "else", "elsif", "end", "ensure", "false", "if", "module", "nil", "puts",
+        "require", "rescue", "return", "self", "then", "true", "unless", "until", "while", "yield",
+    ],
+    line_comments: HASH_COMMENTS,
+    block_comment: None,
+    quotes: &['"', '\''],
+    case_insensitive: false,
+};
+
+const DATA: Syntax = Syntax {
+    keywords: &["false", "null", "true"],
+    line_comments: HASH_COMMENTS,
+    block_comment: None,
+    quotes: &['"', '\''],
+    case_insensitive: false,
+};
+
+/// The syntax for a language name as senders write it, such as `rs`,
+/// `Python` or `c++`.
+fn syntax(language: &str) -> Option<&'static Syntax> {
+    Some(match language.to_ascii_lowercase().as_str() {
+        "rust" | "rs" => &RUST,
+        "python" | "py" | "python3" => &PYTHON,
+        "javascript" | "js" | "jsx" | "typescript" | "ts" | "tsx" | "node" => &JAVASCRIPT,
+        "go" | "golang" => &GO,
+        "c" | "h" | "cpp" | "c++" | "cxx" | "hpp" | "java" | "kotlin" | "kt" | "csharp" | "cs" | "c#" | "swift"
+        | "dart" | "php" => &C_FAMILY,
+        "bash" | "sh" | "shell" | "zsh" | "console" => &SHELL,
+        "sql" | "sqlite" | "postgresql" | "mysql" => &SQL,
+        "ruby" | "rb" => &RUBY,
+        "json" | "yaml" | "yml" | "toml" => &DATA,
+        _ => return None,
+    })
+}
+
+/// Splits `code` into tokens for `language`. Languages without a known
+/// syntax come back as a single plain token.
+pub fn highlight<'a>(code: &'a str, language: &str) -> Vec<(TokenKind, &'a str)> {
+    let Some(syntax) = syntax(language) else {
+        return vec![(TokenKind::Plain, code)];
+    };
+    let mut tokens: Vec<(TokenKind, &str)> = Vec::new();
+    let mut pos = 0;
+    while pos < code.len() {
+        let rest = &code[pos..];
+        let c = rest.chars().next().unwrap_or_default();
+        let (kind, len) = if syntax.line_comments.iter().any(|start| rest.starts_with(start)) {
+            (TokenKind::Comment, rest.find('\n').unwrap_or(rest.len()))
+        } else if let Some((start, end)) = syntax.block_comment.filter(|(start, _)| rest.starts_with(start)) {
+            let len = rest[start.len()..].find(end).map_or(rest.len(), |at| start.len() + at + end.len());
+            (TokenKind::Comment, len)
+        } else if syntax.quotes.contains(&c) {
+            (TokenKind::String, quoted_len(rest, c))
+        } else if c.is_ascii_digit() {
+            let len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.')).unwrap_or(rest.len());
+            (TokenKind::Number, len)
+        } else if c.is_alphabetic() || c == '_' {
+            let len = rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            let word = &rest[..len];
+            let keyword = if syntax.case_insensitive {
+                syntax.keywords.iter().any(|keyword| keyword.eq_ignore_ascii_case(word))
+            } else {
+                syntax.keywords.contains(&word)
+            };
+            (if keyword { TokenKind::Keyword } else { TokenKind::Plain }, len)
+        } else {
+            (TokenKind::Plain, c.len_utf8())
+        };
+
+        let text = &rest[..len];
+        match tokens.last_mut() {
+            // Keep runs of plain text together rather than one token per character
+            Some((TokenKind::Plain, last)) if kind == TokenKind::Plain => {
+                *last = &code[pos - last.len()..pos + len];
+            }
+            _ => tokens.push((kind, text)),
+        }
+        pos += len;
+    }
+    tokens
+}
+
+/// Length of the string literal at the start of `rest`, which opens with
+/// `quote`, including both quotes. An unterminated string runs to the end.
+fn quoted_len(rest: &str, quote: char) -> usize {
+    let mut escaped = false;
+    for (index, c) in rest.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            _ if c == quote => return index + c.len_utf8(),
+            _ => {}
+        }
+    }
+    rest.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use TokenKind::*;
+
+    #[test]
+    fn unknown_languages_stay_plain() {
+        assert_eq!(highlight("let x = 1;", "brainfuck"), vec![(Plain, "let x = 1;")]);
+    }
+
+    #[test]
+    fn unterminated_strings_run_to_the_end() {
+        assert_eq!(highlight("x = \"abc", "py"), vec![(Plain, "x = "), (String, "\"abc")]);
+        assert_eq!(highlight("'a\\'", "py"), vec![(String, "'a\\'")]);
+        assert_eq!(highlight("s = \"é\\\"\" + 1", "js"), vec![
+            (Plain, "s = "),
+            (String, "\"é\\\"\""),
+            (Plain, " + "),
+            (Number, "1"),
+        ]);
+    }
+
+    #[test]
+    fn unterminated_comments_run_to_the_end() {
+        assert_eq!(highlight("a /* b\nc", "c"), vec![(Plain, "a "), (Comment, "/* b\nc")]);
+        assert_eq!(highlight("/**/x", "c"), vec![(Comment, "/**/"), (Plain, "x")]);
+        assert_eq!(highlight("# note", "sh"), vec![(Comment, "# note")]);
+        assert_eq!(highlight("x // y\nz", "rust"), vec![(Plain, "x "), (Comment, "// y"), (Plain, "\nz")]);
+    }
+
+    #[test]
+    fn non_ascii_identifiers_are_words() {
+        assert_eq!(highlight("let größe = ñ;", "rust"), vec![(Keyword, "let"), (Plain, " größe = ñ;")]);
+        assert_eq!(highlight("fnμ fn", "rust"), vec![(Plain, "fnμ "), (Keyword, "fn")]);
+        assert_eq!(highlight("名前 in x", "py"), vec![(Plain, "名前 "), (Keyword, "in"), (Plain, " x")]);
+    }
+
+    #[test]
+    fn plain_runs_are_merged() {
+        assert_eq!(highlight("a + b_c * (d)", "go"), vec![(Plain, "a + b_c * (d)")]);
+        assert_eq!(highlight("x 😀 y", "go"), vec![(Plain, "x 😀 y")]);
+        assert_eq!(highlight("SELECT a FROM t", "sql"), vec![
+            (Keyword, "SELECT"),
+            (Plain, " a "),
+            (Keyword, "FROM"),
+            (Plain, " t"),
+        ]);
+    }
+
+    #[test]
+    fn tokens_cover_the_code() {
+        let code = "fn main() { let s = \"hi\"; /* c */ 42 } // end";
+        let tokens = highlight(code, "rust");
+        assert_eq!(tokens.iter().map(|(_, text)| *text).collect::<std::string::String>(), code);
+    }
+}
@@ -0,0 +1,866 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::path::Path;
+use serde::Deserialize;
+use serde_json::Value;
+use crate::archive::Archive;
+use crate::config::{config, ChatRef};
+use crate::formatting::safe_link;
+use crate::peers::cached_chat_id;
+use crate::service::{ServiceAction, ServiceUser};
+use crate::telegram::{
+    ChatMessage, EntityType, GeoPoint, MediaDetails, MediaInfo, MessageType, Poll, PollAnswer, TextEntity,
+};
+
+const USAGE: &str = "usage: tg-log-new import <result.json> [--chat <chat>]";
+
+/// Fields of an exported message that announce media with no `MessageType`
+/// of its own, and the kind reported for each.
+const UNMAPPED_MEDIA: &[(&str, &str)] = &[
+    ("todo_list", "checklist"),
+    ("giveaway_information", "giveaway"),
+];
+
+/// What importing one exported chat did.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub chat_id: i64,
+    pub chat_name: String,
+    pub imported: usize,
+    /// Messages left alone because the archive already had them.
+    pub duplicates: usize,
+    /// Kinds of messages the archive has no equivalent for, with how often
+    /// each occurred. Those with a date are imported with placeholder text.
+    pub unmapped: BTreeMap<String, usize>,
+    /// Text formatting that was dropped, by export entity type.
+    pub dropped_entities: BTreeMap<String, usize>,
+}
+
+impl ImportReport {
+    fn note_unmapped(&mut self, kind: impl Into<String>) {
+        *self.unmapped.entry(kind.into()).or_default() += 1;
+    }
+}
+
+impl std::fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} ({}): imported {} messages, {} already archived",
+            self.chat_name, self.chat_id, self.imported, self.duplicates,
+        )?;
+        if !self.unmapped.is_empty() {
+            writeln!(f, "  Unmapped message kinds:")?;
+            for (kind, count) in &self.unmapped {
+                writeln!(f, "    {}: {}", kind, count)?;
+            }
+        }
+        if !self.dropped_entities.is_empty() {
+            writeln!(f, "  Formatting kept as plain text:")?;
+            for (kind, count) in &self.dropped_entities {
+                writeln!(f, "    {}: {}", kind, count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// One chat of a Telegram Desktop export.
+#[derive(Deserialize)]
+struct TdChat {
+    name: Option<String>,
+    id: i64,
+    messages: Vec<TdMessage>,
+}
+
+/// The chat list of a whole-account export.
+#[derive(Default, Deserialize)]
+struct TdChatList {
+    list: Vec<TdChat>,
+}
+
+#[derive(Deserialize)]
+struct TdMessage {
+    id: i32,
+    /// `message`, or `service` for chat events.
+    #[serde(rename = "type")]
+    kind: String,
+    date: Option<String>,
+    date_unixtime: Option<String>,
+    edited: Option<String>,
+    edited_unixtime: Option<String>,
+    from: Option<String>,
+    actor: Option<String>,
+    action: Option<String>,
+    reply_to_message_id: Option<i32>,
+    forwarded_from: Option<String>,
+    photo: Option<String>,
+    photo_file_size: Option<u64>,
+    width: Option<u32>,
+    height: Option<u32>,
+    file: Option<String>,
+    file_name: Option<String>,
+    file_size: Option<u64>,
+    media_type: Option<String>,
+    mime_type: Option<String>,
+    sticker_emoji: Option<String>,
+    location_information: Option<TdLocation>,
+    contact_information: Option<Value>,
+    poll: Option<Value>,
+    #[serde(default)]
+    text: TdText,
+    /// The text split into runs; missing from older exports, which only
+    /// have `text`.
+    #[serde(default)]
+    text_entities: Vec<TdEntity>,
+    #[serde(flatten)]
+    other: HashMap<String, Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TdText {
+    Plain(String),
+    Formatted(Vec<TdTextPart>),
+}
+
+impl Default for TdText {
+    fn default() -> Self {
+        TdText::Plain(String::new())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TdTextPart {
+    Plain(String),
+    Entity(TdEntity),
+}
+
+#[derive(Clone, Copy, Deserialize)]
+struct TdLocation {
+    latitude: f64,
+    longitude: f64,
+}
+
+impl From<TdLocation> for GeoPoint {
+    fn from(location: TdLocation) -> Self {
+        GeoPoint { latitude: location.latitude, longitude: location.longitude, accuracy_radius: None }
+    }
+}
+
+#[derive(Deserialize)]
+struct TdPoll {
+    question: String,
+    #[serde(default)]
+    closed: bool,
+    #[serde(default)]
+    total_voters: i32,
+    #[serde(default)]
+    answers: Vec<TdPollAnswer>,
+}
+
+#[derive(Deserialize)]
+struct TdPollAnswer {
+    text: String,
+    #[serde(default)]
+    voters: i32,
+    #[serde(default)]
+    chosen: bool,
+}
+
+#[derive(Deserialize)]
+struct TdInvoice {
+    title: String,
+    #[serde(default)]
+    description: String,
+    amount: i64,
+    currency: String,
+}
+
+#[derive(Clone, Deserialize)]
+struct TdEntity {
+    #[serde(rename = "type")]
+    kind: String,
+    text: String,
+    href: Option<String>,
+    document_id: Option<String>,
+    language: Option<String>,
+    user_id: Option<i64>,
+    #[serde(default)]
+    collapsed: bool,
+}
+
+/// Imports every chat in the Telegram Desktop export at `path`, either a
+/// single chat's `result.json` or a whole account's. Messages whose id is
+/// already archived are skipped. `chat_id` replaces the exported chat id and
+/// only works for single-chat exports.
+pub fn import_export(archive: &Archive, path: &Path, chat_id: Option<i64>) -> Result<Vec<ImportReport>, Box<dyn Error>> {
+    let data = std::fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    let mut export: Value = serde_json::from_str(&data)?;
+
+    let chats = match export.get_mut("chats") {
+        Some(chats) => {
+            let mut chats = TdChatList::deserialize(chats.take())?.list;
+            if let Some(left) = export.get_mut("left_chats") {
+                chats.extend(TdChatList::deserialize(left.take())?.list);
+            }
+            if chat_id.is_some() {
+                return Err("--chat only works with single-chat exports".into());
+            }
+            chats
+        }
+        None => vec![TdChat::deserialize(export)?],
+    };
+
+    let export_dir = path.parent().unwrap_or(Path::new("."));
+    chats
+        .into_iter()
+        .map(|chat| {
+            let chat_id = chat_id.unwrap_or(chat.id);
+            import_chat(archive, export_dir, chat, chat_id)
+        })
+        .collect()
+}
+
+fn import_chat(archive: &Archive, export_dir: &Path, chat: TdChat, chat_id: i64) -> Result<ImportReport, Box<dyn Error>> {
+    let mut report = ImportReport {
+        chat_id,
+        chat_name: chat.name.clone().unwrap_or_else(|| format!("Chat {}", chat_id)),
+        ..Default::default()
+    };
+
+    let mut known = archive.message_ids(chat_id)?;
+    let mut messages = Vec::new();
+    for message in chat.messages {
+        if !known.insert(message.id) {
+            report.duplicates += 1;
+            continue;
+        }
+        if let Some(message) = convert_message(export_dir, chat_id, message, &mut report) {
+            messages.push(message);
+        }
+    }
+    report.imported = archive.store_messages(&messages)?.len();
+
+    if let (Some(name), None) = (&chat.name, archive.chat_name(chat_id)?) {
+        archive.store_chat_name(chat_id, name)?;
+    }
+    Ok(report)
+}
+
+fn convert_message(export_dir: &Path, chat_id: i64, message: TdMessage, report: &mut ImportReport) -> Option<ChatMessage> {
+    let Some(timestamp) = parse_date(message.date_unixtime.as_deref(), message.date.as_deref()) else {
+        report.note_unmapped("message without a date");
+        return None;
+    };
+    let (text, formatted_text) = message.text(report);
+
+    let sender = message.from.clone().or(message.actor.clone()).unwrap_or_else(|| "Unknown".to_string());
+    let mut service = None;
+    let (message_type, media_info, text, formatted_text) = match message.kind.as_str() {
+        "message" => {
+            let (message_type, media_info, shown) = classify_message(export_dir, chat_id, &message, &text, report);
+            let formatted_text = if shown == text { formatted_text } else { Vec::new() };
+            (message_type, media_info, shown, formatted_text)
+        }
+        "service" => {
+            let name = message.action.as_deref().unwrap_or("unknown");
+            let action = service_action(name, &sender, &message).unwrap_or_else(|| {
+                report.note_unmapped(format!("service: {}", name));
+                ServiceAction::Other { name: name.replace('_', " ") }
+            });
+            let text = action.describe(&sender);
+            service = Some(action);
+            (MessageType::System, None, text, Vec::new())
+        }
+        other => {
+            report.note_unmapped(format!("{} message", other));
+            return None;
+        }
+    };
+
+    let poll = message.poll();
+    // Pins name the pinned message instead of replying to it
+    let reply_to = match service {
+        Some(ServiceAction::MessagePinned) => field(&message, "message_id"),
+        _ => message.reply_to_message_id,
+    };
+
+    Some(ChatMessage {
+        id: message.id,
+        text,
+        formatted_text,
+        timestamp,
+        sender,
+        chat_id,
+        message_type,
+        media_info,
+        reply_to,
+        forwarded_from: message.forwarded_from,
+        // Exports name the original sender but keep neither their id nor
+        // the original date
+        forward: None,
+        reply_preview: None,
+        poll,
+        service,
+        topic_id: None,
+        thread_id: None,
+        edited_at: parse_date(message.edited_unixtime.as_deref(), message.edited.as_deref()),
+        deleted_at: None,
+        revisions: Vec::new(),
+        imported: true,
+    })
+}
+
+/// Decodes service message `action` of an export, done by `actor`. `None`
+/// for actions with no `ServiceAction`.
+fn service_action(action: &str, actor: &str, message: &TdMessage) -> Option<ServiceAction> {
+    let text = |name: &str| field::<String>(message, name).unwrap_or_default();
+    // Exports name members but keep no ids
+    let members = field::<Vec<Option<String>>>(message, "members")
+        .unwrap_or_default()
+        .into_iter()
+        .map(|name| ServiceUser { id: None, name })
+        .collect::<Vec<_>>();
+    let only_actor = matches!(members.as_slice(), [only] if only.name.as_deref() == Some(actor));
+
+    Some(match action {
+        "create_group" => ServiceAction::GroupCreated { title: text("title") },
+        "create_channel" => ServiceAction::ChannelCreated { title: text("title") },
+        "invite_members" if only_actor => ServiceAction::Joined,
+        "invite_members" => ServiceAction::MembersAdded { members },
+        "join_group_by_link" => ServiceAction::JoinedByLink,
+        "join_group_by_request" => ServiceAction::JoinedByRequest,
+        "remove_members" if only_actor => ServiceAction::Left,
+        "remove_members" => ServiceAction::MemberRemoved { member: members.into_iter().next()? },
+        "edit_group_title" => ServiceAction::TitleChanged { title: text("title") },
+        "edit_group_photo" => ServiceAction::PhotoChanged,
+        "delete_group_photo" => ServiceAction::PhotoRemoved,
+        "pin_message" => ServiceAction::MessagePinned,
+        "clear_history" => ServiceAction::HistoryCleared,
+        "migrate_to_supergroup" => ServiceAction::MigratedToSupergroup { channel_id: 0 },
+        "migrate_from_group" => ServiceAction::MigratedFromGroup { title: text("title"), chat_id: 0 },
+        "phone_call" => ServiceAction::Call {
+            video: false,
+            missed: matches!(field::<String>(message, "discard_reason").as_deref(), Some("missed" | "busy")),
+            duration: field(message, "duration_seconds"),
+        },
+        "group_call" => match field(message, "duration") {
+            Some(duration) => ServiceAction::VideoChatEnded { duration },
+            None => ServiceAction::VideoChatStarted,
+        },
+        "group_call_scheduled" => ServiceAction::VideoChatScheduled {
+            date: field::<String>(message, "schedule_date").and_then(|date| parse_date(None, Some(&date)))?,
+        },
+        "invite_to_group_call" => ServiceAction::VideoChatInvite { members },
+        "topic_created" => ServiceAction::TopicCreated { title: text("title"), icon_color: 0 },
+        "topic_edit" => ServiceAction::TopicEdited {
+            title: field(message, "new_title"),
+            closed: None,
+            hidden: None,
+        },
+        "take_screenshot" => ServiceAction::ScreenshotTaken,
+        "score_in_game" => ServiceAction::GameScore { score: field(message, "score")? },
+        "send_payment" => ServiceAction::PaymentSent {
+            currency: text("currency"),
+            total_amount: field(message, "amount")?,
+        },
+        "send_premium_gift" => ServiceAction::PremiumGifted { months: field(message, "months")? },
+        "joined_telegram" => ServiceAction::ContactSignedUp,
+        "set_messages_ttl" => ServiceAction::AutoDeleteChanged { period: field(message, "period")? },
+        "edit_chat_theme" => ServiceAction::ThemeChanged { emoticon: text("emoticon") },
+        "allow_sending_messages" => ServiceAction::BotAllowed,
+        _ => return None,
+    })
+}
+
+/// Field `name` of an exported message that has no field of its own in
+/// `TdMessage`.
+fn field<T: serde::de::DeserializeOwned>(message: &TdMessage, name: &str) -> Option<T> {
+    T::deserialize(message.other.get(name)?).ok()
+}
+
+/// Works out the type and media of a regular message, along with the text to
+/// show: `text` itself, or a placeholder like the ones used for fetched
+/// messages when media has no caption.
+fn classify_message(
+    export_dir: &Path,
+    chat_id: i64,
+    message: &TdMessage,
+    text: &str,
+    report: &mut ImportReport,
+) -> (MessageType, Option<MediaInfo>, String) {
+    let caption = (!text.is_empty()).then(|| text.to_string());
+    let shown = |placeholder: String| caption.clone().unwrap_or(placeholder);
+
+    if let Some(photo) = &message.photo {
+        let file_name = copy_media(export_dir, photo, &config().photo_dir(), "photo", chat_id, message.id)
+            .or_else(|| included_file_name(photo));
+        let media_info = MediaInfo {
+            file_name,
+            file_size: message.photo_file_size,
+            mime_type: Some("image/jpeg".to_string()),
+            caption: caption.clone(),
+            width: message.width,
+            height: message.height,
+            document_id: None,
+            details: None,
+        };
+        return (MessageType::Photo, Some(media_info), shown("[Photo]".to_string()));
+    }
+
+    if let Some(file) = &message.file {
+        let mime_type = message.mime_type.clone();
+        let message_type = match message.media_type.as_deref() {
+            Some("sticker") => MessageType::Sticker,
+            Some("video_file" | "video_message" | "animation") => MessageType::Video,
+            Some("audio_file") => MessageType::Audio,
+            Some("voice_message") => MessageType::Voice,
+            Some(other) => {
+                report.note_unmapped(format!("media: {}", other));
+                MessageType::Document
+            }
+            None => match mime_type.as_deref() {
+                Some(mime) if mime.starts_with("video/") => MessageType::Video,
+                Some(mime) if mime.starts_with("audio/") => MessageType::Audio,
+                _ => MessageType::Document,
+            },
+        };
+
+        let file_name = if message_type == MessageType::Sticker {
+            copy_media(export_dir, file, &config().sticker_dir(), "sticker", chat_id, message.id)
+                .or_else(|| message.file_name.clone())
+        } else {
+            message.file_name.clone().or_else(|| included_file_name(file))
+        };
+        let placeholder = match message_type {
+            MessageType::Sticker => format!("[{} Sticker]", message.sticker_emoji.as_deref().unwrap_or("🎭")),
+            MessageType::Video => "[Video]".to_string(),
+            MessageType::Audio => "[Audio]".to_string(),
+            MessageType::Voice => "[Voice]".to_string(),
+            _ => format!("[Document: {}]", file_name.as_deref().unwrap_or("file")),
+        };
+        let media_info = MediaInfo {
+            file_name,
+            file_size: message.file_size,
+            mime_type,
+            caption: caption.clone(),
+            width: message.width,
+            height: message.height,
+            document_id: None,
+            details: None,
+        };
+        return (message_type, Some(media_info), shown(placeholder));
+    }
+
+    // Venues and live locations are locations with a name or a period
+    let place_name = field::<String>(message, "place_name");
+    let live_period = field::<i32>(message, "live_location_period_seconds");
+    if message.location_information.is_some() || place_name.is_some() {
+        let point = message.location_information.map(GeoPoint::from);
+        let (message_type, details, placeholder) = match (place_name, live_period, point) {
+            (Some(title), _, _) => {
+                let placeholder = format!("[Venue: {}]", title);
+                let details = MediaDetails::Venue {
+                    point,
+                    title,
+                    address: field(message, "address").unwrap_or_default(),
+                    provider: None,
+                    venue_id: None,
+                };
+                (MessageType::Venue, details, placeholder)
+            }
+            (None, Some(period), _) => {
+                let details = MediaDetails::LiveLocation { point, period, heading: None };
+                (MessageType::LiveLocation, details, "[Live Location]".to_string())
+            }
+            (None, None, Some(point)) => (MessageType::Location, MediaDetails::Location { point }, "[Location]".to_string()),
+            (None, None, None) => return (MessageType::Location, None, shown("[Location]".to_string())),
+        };
+        return (message_type, Some(MediaInfo::from_details(details, caption.clone())), shown(placeholder));
+    }
+    if let Some(title) = field::<String>(message, "game_title") {
+        let placeholder = format!("[Game: {}]", title);
+        // The link is `https://t.me/<bot>?game=<short name>`
+        let short_name = field::<String>(message, "game_link")
+            .and_then(|link| link.rsplit_once("game=").map(|(_, name)| name.to_string()))
+            .unwrap_or_default();
+        let details = MediaDetails::Game {
+            title,
+            description: field(message, "game_description").unwrap_or_default(),
+            short_name,
+        };
+        return (MessageType::Game, Some(MediaInfo::from_details(details, caption.clone())), shown(placeholder));
+    }
+    if let Some(invoice) = field::<TdInvoice>(message, "invoice_information") {
+        let placeholder = format!("[Invoice: {}]", invoice.title);
+        let details = MediaDetails::Invoice {
+            title: invoice.title,
+            description: invoice.description,
+            currency: invoice.currency,
+            total_amount: invoice.amount,
+            test: false,
+        };
+        return (MessageType::Invoice, Some(MediaInfo::from_details(details, caption.clone())), shown(placeholder));
+    }
+    if message.contact_information.is_some() {
+        return (MessageType::Contact, None, shown("[Contact]".to_string()));
+    }
+    if message.poll.is_some() {
+        let placeholder = match message.poll() {
+            Some(poll) => format!("[Poll: {}]", poll.question),
+            None => "[Poll]".to_string(),
+        };
+        return (MessageType::Poll, None, shown(placeholder));
+    }
+    if let Some((_, kind)) = UNMAPPED_MEDIA.iter().find(|(field, _)| message.other.contains_key(*field)) {
+        report.note_unmapped(*kind);
+        return (MessageType::Text, None, shown(format!("[{}]", kind)));
+    }
+    (MessageType::Text, None, text.to_string())
+}
+
+impl TdMessage {
+    /// The poll, which exports keep without its id or whether it is a quiz.
+    fn poll(&self) -> Option<Poll> {
+        let poll = TdPoll::deserialize(self.poll.as_ref()?).ok()?;
+        Some(Poll {
+            id: 0,
+            question: poll.question,
+            answers: poll
+                .answers
+                .into_iter()
+                .map(|answer| PollAnswer {
+                    text: answer.text,
+                    option: Vec::new(),
+                    voters: Some(answer.voters),
+                    correct: false,
+                    chosen: answer.chosen,
+                })
+                .collect(),
+            quiz: false,
+            multiple_choice: false,
+            anonymous: true,
+            closed: poll.closed,
+            total_voters: Some(poll.total_voters),
+            solution: None,
+        })
+    }
+
+    /// The text and its formatting, with offsets in UTF-16 code units as
+    /// Telegram sends them. Formatting without an `EntityType` is counted in
+    /// `report` and kept as plain text.
+    fn text(&self, report: &mut ImportReport) -> (String, Vec<TextEntity>) {
+        let parts = if !self.text_entities.is_empty() {
+            self.text_entities.clone()
+        } else {
+            match &self.text {
+                TdText::Plain(text) => vec![TdEntity { kind: "plain".to_string(), text: text.clone(), href: None, document_id: None, language: None, user_id: None, collapsed: false }],
+                TdText::Formatted(parts) => parts
+                    .iter()
+                    .map(|part| match part {
+                        TdTextPart::Plain(text) => TdEntity { kind: "plain".to_string(), text: text.clone(), href: None, document_id: None, language: None, user_id: None, collapsed: false },
+                        TdTextPart::Entity(entity) => entity.clone(),
+                    })
+                    .collect(),
+            }
+        };
+
+        let mut text = String::new();
+        let mut entities = Vec::new();
+        let mut offset = 0;
+        for part in parts {
+            let length = part.text.encode_utf16().count();
+            // Links that could run script, like `javascript:`, keep only their text
+            let url = part.href.as_deref().filter(|_| part.kind == "text_link").and_then(|href| {
+                let url = safe_link(href);
+                if url.is_none() {
+                    *report.dropped_entities.entry("text_link with an unsafe URL".to_string()).or_default() += 1;
+                }
+                url
+            });
+            match entity_type(&part) {
+                Some(entity_type) => entities.push(TextEntity {
+                    offset,
+                    length,
+                    url,
+                    language: part.language.filter(|language| entity_type == EntityType::Pre && !language.is_empty()),
+                    user_id: part.user_id.filter(|_| entity_type == EntityType::MentionName),
+                    entity_type,
+                }),
+                None if part.kind == "plain" => {}
+                None => *report.dropped_entities.entry(part.kind).or_default() += 1,
+            }
+            text.push_str(&part.text);
+            offset += length;
+        }
+        (text, entities)
+    }
+}
+
+/// The `EntityType` for an export entity type; the reverse of the mapping
+/// used when exporting.
+fn entity_type(entity: &TdEntity) -> Option<EntityType> {
+    Some(match entity.kind.as_str() {
+        "bold" => EntityType::Bold,
+        "italic" => EntityType::Italic,
+        "code" => EntityType::Code,
+        "pre" => EntityType::Pre,
+        "link" => EntityType::Link,
+        "text_link" => EntityType::TextLink,
+        "mention" => EntityType::Mention,
+        "hashtag" => EntityType::Hashtag,
+        "bot_command" => EntityType::BotCommand,
+        "email" => EntityType::Email,
+        "phone" => EntityType::Phone,
+        "underline" => EntityType::Underline,
+        "strikethrough" => EntityType::Strikethrough,
+        "spoiler" => EntityType::Spoiler,
+        "custom_emoji" => EntityType::CustomEmoji { document_id: entity.document_id.as_deref()?.parse().ok()? },
+        "mention_name" => EntityType::MentionName,
+        "cashtag" => EntityType::Cashtag,
+        "bank_card" => EntityType::BankCard,
+        "blockquote" if entity.collapsed => EntityType::ExpandableBlockquote,
+        "blockquote" => EntityType::Blockquote,
+        _ => return None,
+    })
+}
+
+/// Reads `unixtime`, or failing that `date`, which older exports write in
+/// local time without a zone and is taken as UTC.
+fn parse_date(unixtime: Option<&str>, date: Option<&str>) -> Option<i64> {
+    unixtime.and_then(|unixtime| unixtime.parse().ok()).or_else(|| {
+        let date = chrono::NaiveDateTime::parse_from_str(date?, "%Y-%m-%dT%H:%M:%S").ok()?;
+        Some(date.and_utc().timestamp())
+    })
+}
+
+/// The file name of an exported file, unless the export left it out.
+fn included_file_name(path: &str) -> Option<String> {
+    if path.starts_with('(') {
+        // "(File not included. Change data exporting settings to download.)"
+        return None;
+    }
+    Path::new(path).file_name().map(|name| name.to_string_lossy().into_owned())
+}
+
+/// Copies an exported sticker or photo into `cache_dir` so it shows like a
+/// fetched one, returning its new file name. Paths are resolved, symlinks
+/// included, and only files inside the export are copied, so a crafted
+/// export cannot publish other files.
+fn copy_media(export_dir: &Path, file: &str, cache_dir: &Path, kind: &str, chat_id: i64, message_id: i32) -> Option<String> {
+    let source = export_dir.join(file).canonicalize().ok()?;
+    let inside_export = export_dir.canonicalize().is_ok_and(|export_dir| source.starts_with(export_dir));
+    if !inside_export {
+        eprintln!("Not copying {} {}: outside the export", kind, file);
+        return None;
+    }
+    if !source.is_file() {
+        return None;
+    }
+    let extension = source.extension()?.to_str()?;
+    let name = format!("{}_import_{}_{}.{}", kind, chat_id, message_id, extension);
+    std::fs::create_dir_all(cache_dir)
+        .and_then(|_| std::fs::copy(&source, cache_dir.join(&name)))
+        .map_err(|e| eprintln!("Failed to copy {} {}: {}", kind, source.display(), e))
+        .ok()?;
+    Some(name)
+}
+
+/// Runs `tg-log-new import <result.json> [--chat <chat>]` and prints what
+/// was imported.
+pub fn run_import_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut path = None;
+    let mut chat = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--chat" | "-c" => chat = Some(args.next().ok_or(USAGE)?.parse::<ChatRef>()?),
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(Path::new(arg)),
+            _ => return Err(USAGE.into()),
+        }
+    }
+    let path = path.ok_or(USAGE)?;
+    let chat_id = chat
+        .map(|chat| {
+            cached_chat_id(&chat).ok_or_else(|| {
+                format!("{} has not been resolved yet; start the server once or pass the numeric chat id", chat)
+            })
+        })
+        .transpose()?;
+
+    let archive = Archive::open(&config().archive_path)?;
+    let logged: Vec<i64> = config().chats.iter().filter_map(cached_chat_id).collect();
+    for report in import_export(&archive, path, chat_id)? {
+        print!("{}", report);
+        if !logged.contains(&report.chat_id) {
+            println!("  Not in the configured chats, so the web interface does not list it.");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A chat as Telegram Desktop exports it, trimmed to what the tests read.
+    const FIXTURE: &str = r#"{
+        "name": "Fixture",
+        "id": 42,
+        "messages": [
+            {
+                "id": 1, "type": "message",
+                "date": "2024-03-01T12:00:00", "date_unixtime": "1709294400",
+                "from": "Alice",
+                "text": ["Hi 👋 ", {"type": "bold", "text": "wörld"}, " ", {"type": "text_link", "text": "x", "href": "https://example.com"}],
+                "text_entities": [
+                    {"type": "plain", "text": "Hi 👋 "},
+                    {"type": "bold", "text": "wörld"},
+                    {"type": "plain", "text": " "},
+                    {"type": "text_link", "text": "x", "href": "https://example.com"},
+                    {"type": "plain", "text": " "},
+                    {"type": "fancy_new_thing", "text": "𝒴"},
+                    {"type": "pre", "text": "fn main() {}", "language": "rust"}
+                ]
+            },
+            {
+                "id": 2, "type": "message", "date": "2024-03-01T12:01:00", "from": "Bob",
+                "text": ["🎉", {"type": "italic", "text": "party"}]
+            },
+            {
+                "id": 3, "type": "service", "date_unixtime": "1709294500",
+                "actor": "Alice", "action": "invite_members", "members": ["Alice"]
+            },
+            {
+                "id": 4, "type": "service", "date_unixtime": "1709294600",
+                "actor": "Alice", "action": "invite_members", "members": ["Bob", null]
+            },
+            {
+                "id": 5, "type": "service", "date_unixtime": "1709294700",
+                "actor": "Bob", "action": "remove_members", "members": ["Carol"]
+            },
+            {
+                "id": 6, "type": "service", "date_unixtime": "1709294800",
+                "actor": "Alice", "action": "phone_call", "discard_reason": "missed"
+            },
+            {
+                "id": 7, "type": "service", "date_unixtime": "1709294900",
+                "actor": "Alice", "action": "group_call_scheduled", "schedule_date": "tomorrow"
+            },
+            {
+                "id": 8, "type": "service", "date_unixtime": "1709295000",
+                "actor": "Alice", "action": "boost_apply"
+            },
+            {
+                "id": 9, "type": "message", "date_unixtime": "1709295100", "from": "Eve",
+                "text_entities": [
+                    {"type": "text_link", "text": "click", "href": "JavaScript:alert(1)"},
+                    {"type": "text_link", "text": "me", "href": "t.me/tg_log"}
+                ]
+            }
+        ]
+    }"#;
+
+    fn fixture() -> Vec<TdMessage> {
+        serde_json::from_str::<TdChat>(FIXTURE).unwrap().messages
+    }
+
+    fn entity(offset: usize, length: usize, entity_type: EntityType) -> TextEntity {
+        TextEntity { offset, length, entity_type, url: None, language: None, user_id: None }
+    }
+
+    #[test]
+    fn text_offsets_count_utf16_code_units() {
+        let messages = fixture();
+        let mut report = ImportReport::default();
+        let (text, entities) = messages[0].text(&mut report);
+
+        assert_eq!(text, "Hi 👋 wörld x 𝒴fn main() {}");
+        assert_eq!(
+            entities,
+            vec![
+                entity(6, 5, EntityType::Bold),
+                TextEntity { url: Some("https://example.com".to_string()), ..entity(12, 1, EntityType::TextLink) },
+                TextEntity { language: Some("rust".to_string()), ..entity(16, 12, EntityType::Pre) },
+            ]
+        );
+        assert_eq!(report.dropped_entities.get("fancy_new_thing"), Some(&1));
+    }
+
+    #[test]
+    fn text_without_entities_reads_the_text_parts() {
+        let messages = fixture();
+        let (text, entities) = messages[1].text(&mut ImportReport::default());
+        assert_eq!(text, "🎉party");
+        assert_eq!(entities, vec![entity(2, 5, EntityType::Italic)]);
+    }
+
+    #[test]
+    fn unsafe_links_keep_only_their_text() {
+        let messages = fixture();
+        let mut report = ImportReport::default();
+        let (text, entities) = messages[8].text(&mut report);
+        assert_eq!(text, "clickme");
+        assert_eq!(
+            entities,
+            vec![
+                entity(0, 5, EntityType::TextLink),
+                TextEntity { url: Some("https://t.me/tg_log".to_string()), ..entity(5, 2, EntityType::TextLink) },
+            ]
+        );
+        assert_eq!(report.dropped_entities.get("text_link with an unsafe URL"), Some(&1));
+    }
+
+    #[test]
+    fn dates_prefer_unixtime() {
+        assert_eq!(parse_date(Some("1709294400"), Some("2000-01-01T00:00:00")), Some(1709294400));
+        assert_eq!(parse_date(None, Some("2024-03-01T12:00:00")), Some(1709294400));
+        assert_eq!(parse_date(Some("soon"), Some("2024-03-01T12:00:00")), Some(1709294400));
+        assert_eq!(parse_date(None, Some("2024-03-01 12:00")), None);
+        assert_eq!(parse_date(None, None), None);
+    }
+
+    #[test]
+    fn service_actions() {
+        let messages = fixture();
+        let action = |message: &TdMessage| {
+            service_action(message.action.as_deref().unwrap(), message.actor.as_deref().unwrap(), message)
+        };
+        let user = |name: &str| ServiceUser { id: None, name: Some(name.to_string()) };
+
+        assert_eq!(action(&messages[2]), Some(ServiceAction::Joined));
+        assert_eq!(
+            action(&messages[3]),
+            Some(ServiceAction::MembersAdded { members: vec![user("Bob"), ServiceUser { id: None, name: None }] })
+        );
+        assert_eq!(action(&messages[4]), Some(ServiceAction::MemberRemoved { member: user("Carol") }));
+        assert_eq!(action(&messages[5]), Some(ServiceAction::Call { video: false, missed: true, duration: None }));
+        assert_eq!(action(&messages[6]), None);
+        assert_eq!(action(&messages[7]), None);
+    }
+
+    #[test]
+    fn media_outside_the_export_is_not_copied() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        let export_dir = root.join("export");
+        let cache_dir = root.join("cache");
+        std::fs::create_dir_all(export_dir.join("photos")).unwrap();
+        std::fs::write(export_dir.join("photos/inside.jpg"), b"jpeg").unwrap();
+        std::fs::write(root.join("outside.jpg"), b"jpeg").unwrap();
+
+        let copy = |file: &str| copy_media(&export_dir, file, &cache_dir, "photo", 42, 1);
+        assert_eq!(copy("photos/inside.jpg").as_deref(), Some("photo_import_42_1.jpg"));
+        assert_eq!(copy("../outside.jpg"), None);
+        assert_eq!(copy("photos/../../outside.jpg"), None);
+        assert_eq!(copy(root.join("outside.jpg").to_str().unwrap()), None);
+
+        std::os::unix::fs::symlink(root.join("outside.jpg"), export_dir.join("photos/link.jpg")).unwrap();
+        std::os::unix::fs::symlink(export_dir.join("photos/inside.jpg"), export_dir.join("photos/alias.jpg")).unwrap();
+        assert_eq!(copy("photos/link.jpg"), None);
+        assert_eq!(copy("photos/alias.jpg").as_deref(), Some("photo_import_42_1.jpg"));
+    }
+}
@@ -1,7 +1,31 @@
 pub mod app;
+#[cfg(feature = "ssr")]
 pub mod config;
 pub mod telegram;
 pub mod chat;
+pub mod formatting;
+pub mod highlight;
+pub mod search;
+pub mod service;
+pub mod sidebar;
+#[cfg(feature = "ssr")]
+pub mod archive;
+#[cfg(feature = "ssr")]
+pub mod backfill;
+#[cfg(feature = "ssr")]
+pub mod downloads;
+#[cfg(feature = "ssr")]
+pub mod export;
+#[cfg(feature = "ssr")]
+pub mod import;
+#[cfg(feature = "ssr")]
+pub mod live;
+#[cfg(feature = "ssr")]
+pub mod media;
+#[cfg(feature = "ssr")]
+pub mod peers;
+#[cfg(feature = "ssr")]
+pub mod state;
 
 #[cfg(feature = "hydrate")]
 #[wasm_bindgen::prelude::wasm_bindgen]
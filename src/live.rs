@@ -0,0 +1,199 @@
+use std::collections::hash_map::{Entry, HashMap};
+use std::convert::Infallible;
+use std::time::Duration;
+use axum::extract::{Path, State};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use futures::Stream;
+use grammers_client::Update;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::task::JoinHandle;
+use crate::archive::Archive;
+use crate::downloads::attach_custom_emoji;
+use crate::state::AppState;
+use crate::telegram::{convert_message, fetch_raw_details, find_chat, ChatMessage, Poll, TelegramService};
+
+/// How many events a slow browser may fall behind before it starts
+/// missing some.
+const LIVE_BUFFER: usize = 256;
+
+/// A change to one of the logged chats, as pushed to browsers.
+#[derive(Clone, Debug)]
+pub enum LiveEvent {
+    /// A message that was just sent.
+    New(ChatMessage),
+    /// An already known message that was edited or deleted, in its archived
+    /// form including revisions.
+    Updated(ChatMessage),
+}
+
+/// Fan-out of chat changes from the Telegram update loop to every connected
+/// browser.
+#[derive(Clone)]
+pub struct LiveFeed {
+    sender: broadcast::Sender<LiveEvent>,
+}
+
+impl Default for LiveFeed {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(LIVE_BUFFER);
+        Self { sender }
+    }
+}
+
+impl LiveFeed {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn publish(&self, event: LiveEvent) {
+        // No subscribers just means nobody has the page open.
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<LiveEvent> {
+        self.sender.subscribe()
+    }
+}
+
+/// Consumes updates from the shared client forever, archiving new, edited
+/// and deleted messages and poll results of the logged `chat_ids` and
+/// publishing them to `feed`. Like the backfill, the task can be aborted at
+/// any await.
+pub fn spawn_update_loop(telegram: TelegramService, feed: LiveFeed, archive: Archive, chat_ids: Vec<i64>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut backoff = Duration::from_secs(1);
+        // Deletions outside channels don't say which chat they belong to, so
+        // they apply to every logged chat that is not a channel.
+        let mut is_channel = HashMap::new();
+        loop {
+            match telegram.next_update().await {
+                Ok(Some(update)) => {
+                    if let Err(e) = handle_update(&telegram, &feed, &archive, &chat_ids, &mut is_channel, update).await {
+                        eprintln!("Failed to handle update: {}", e);
+                    }
+                    backoff = Duration::from_secs(1);
+                }
+                Ok(None) => backoff = Duration::from_secs(1),
+                Err(e) => {
+                    eprintln!("Update loop error, retrying in {:?}: {}", backoff, e);
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(Duration::from_secs(60));
+                }
+            }
+        }
+    })
+}
+
+async fn handle_update(
+    telegram: &TelegramService,
+    feed: &LiveFeed,
+    archive: &Archive,
+    chat_ids: &[i64],
+    is_channel: &mut HashMap<i64, bool>,
+    update: Update,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    match update {
+        Update::NewMessage(message) | Update::MessageEdited(message) if chat_ids.contains(&message.chat().id()) => {
+            let chat_id = message.chat().id();
+            is_channel.insert(chat_id, message.chat().pack().is_channel());
+            let client = telegram.client().await?;
+            let mut converted = [convert_message(&client, &message, chat_id)];
+            if let Err(e) = fetch_raw_details(&client, message.chat().pack(), &mut converted).await {
+                eprintln!("Failed to fetch the details of message {}: {}", message.id(), e);
+            }
+            let edited = converted[0].edited_at.is_some();
+            for mut stored in archive.store_messages(&converted)? {
+                archive.attach_reply_previews(std::slice::from_mut(&mut stored))?;
+                feed.publish(if edited { LiveEvent::Updated(stored) } else { LiveEvent::New(stored) });
+            }
+        }
+        Update::MessageDeleted(deletion) => {
+            let mut affected = Vec::new();
+            match deletion.channel_id() {
+                Some(channel_id) if chat_ids.contains(&channel_id) => affected.push(channel_id),
+                Some(_) => {}
+                None => {
+                    for &chat_id in chat_ids {
+                        if let Entry::Vacant(entry) = is_channel.entry(chat_id) {
+                            // One chat that cannot be resolved must not hold up the others
+                            match telegram.run(|client| async move { find_chat(&client, chat_id).await }).await {
+                                Ok(chat) => entry.insert(chat.is_channel()),
+                                Err(e) => {
+                                    eprintln!("Cannot tell whether chat {} is a channel: {}", chat_id, e);
+                                    continue;
+                                }
+                            };
+                        }
+                        if is_channel.get(&chat_id) == Some(&false) {
+                            affected.push(chat_id);
+                        }
+                    }
+                }
+            }
+
+            let now = chrono::Utc::now().timestamp();
+            for chat_id in affected {
+                for mut stored in archive.mark_deleted(chat_id, deletion.messages(), now)? {
+                    archive.attach_reply_previews(std::slice::from_mut(&mut stored))?;
+                    feed.publish(LiveEvent::Updated(stored));
+                }
+            }
+        }
+        // Votes and closing, which only name the poll
+        Update::Raw(grammers_tl_types::enums::Update::MessagePoll(update)) => {
+            let mut changed = archive.poll_messages(update.poll_id)?;
+            changed.retain(|message| chat_ids.contains(&message.chat_id));
+            for message in &mut changed {
+                let Some(poll) = &mut message.poll else {
+                    continue;
+                };
+                if let Some(fresh) = update.poll.clone() {
+                    *poll = Poll::from_raw(fresh, update.results.clone());
+                } else {
+                    poll.apply_results(update.results.clone());
+                }
+            }
+            for mut stored in archive.store_messages(&changed)? {
+                archive.attach_reply_previews(std::slice::from_mut(&mut stored))?;
+                feed.publish(LiveEvent::Updated(stored));
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// `GET /live/{chat_id}`: server-sent events carrying each new (`message`)
+/// or changed (`updated`) message of the chat as JSON.
+pub async fn live_events(
+    State(state): State<AppState>,
+    Path(chat_id): Path<i64>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = futures::stream::unfold(state.live.subscribe(), move |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => {
+                    let (name, mut message) = match event {
+                        LiveEvent::New(message) => ("message", message),
+                        LiveEvent::Updated(message) => ("updated", message),
+                    };
+                    if message.chat_id != chat_id {
+                        continue;
+                    }
+                    attach_custom_emoji(std::slice::from_mut(&mut message));
+                    let event = Event::default()
+                        .event(name)
+                        .json_data(&message)
+                        .unwrap_or_else(|_| Event::default().comment("unserializable message"));
+                    return Some((Ok(event), receiver));
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    eprintln!("Live subscriber lagged, skipped {} events", skipped);
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
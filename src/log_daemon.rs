@@ -0,0 +1,100 @@
+//! Archives the configured chats without serving the web interface: brings
+//! each chat's archive up to date, then follows new, edited and deleted
+//! messages until stopped with SIGTERM or Ctrl-C. Backfills that hit rate
+//! limits or lose the connection wait and resume on their own.
+
+use tg_log_new::archive::Archive;
+use tg_log_new::backfill::spawn_backfill;
+use tg_log_new::config::{Config, ConfigError};
+use tg_log_new::live::{spawn_update_loop, LiveFeed};
+use tg_log_new::peers::resolve_chat_ids;
+use tg_log_new::telegram::{TelegramError, TelegramService};
+
+#[tokio::main]
+async fn main() {
+    let config = match Config::load() {
+        Ok(config) if config.chats.is_empty() => Err(ConfigError {
+            problems: vec!["no chats to log; list them in chats (TELEGRAM_CHATS)".to_string()],
+        }),
+        result => result,
+    };
+    let config = match config {
+        Ok(config) => config.install(),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let archive = match Archive::open(&config.archive_path) {
+        Ok(archive) => archive,
+        Err(e) => {
+            eprintln!("Cannot open archive {}: {}", config.archive_path.display(), e);
+            std::process::exit(1);
+        }
+    };
+
+    // Unlike the web server, there is nobody to show a login error to later
+    let telegram = TelegramService::new();
+    if let Err(e) = telegram.client().await {
+        eprintln!("Cannot connect to Telegram: {}", e);
+        if e == TelegramError::NotAuthorized {
+            eprintln!("Log in with `cargo run --bin cli-auth` first.");
+        }
+        std::process::exit(1);
+    }
+
+    let chats = resolve_chat_ids(&telegram, &config.chats).await;
+    if chats.is_empty() {
+        eprintln!("None of the configured chats could be resolved");
+        std::process::exit(1);
+    }
+    eprintln!("Logging {} chat(s) to {}", chats.len(), config.archive_path.display());
+
+    let updates = spawn_update_loop(telegram.clone(), LiveFeed::new(), archive.clone(), chats.clone());
+    let backfill = spawn_backfill(telegram.clone(), archive, chats);
+
+    shutdown_signal().await;
+    eprintln!("Shutting down");
+
+    // Progress is saved after every batch, so the next start resumes here
+    updates.abort();
+    backfill.abort();
+    let _ = updates.await;
+    let _ = backfill.await;
+
+    if let Err(e) = telegram.save_session().await {
+        eprintln!("{}", e);
+    }
+    telegram.disconnect().await;
+}
+
+/// Resolves on Ctrl-C or, on Unix, SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            eprintln!("Cannot listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                eprintln!("Cannot listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
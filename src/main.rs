@@ -7,25 +7,98 @@ async fn main() {
     use leptos::prelude::*;
     use leptos_axum::{generate_route_list, LeptosRoutes};
     use tg_log_new::app::*;
+    use tg_log_new::archive::Archive;
+    use tg_log_new::backfill::spawn_backfill;
+    use tg_log_new::config::{Config, ConfigError};
+    use tg_log_new::export::{export_chat, run_export_command};
+    use tg_log_new::import::run_import_command;
+    use tg_log_new::live::{live_events, spawn_update_loop, LiveFeed};
+    use tg_log_new::media::{serve_lottie, serve_media};
+    use tg_log_new::peers::resolve_chat_ids;
+    use tg_log_new::state::AppState;
+    use tg_log_new::telegram::TelegramService;
+
+    // `tg-log-new export|import ...` work on the archive instead of serving
+    let mut args = std::env::args().skip(1);
+    let command = match args.next() {
+        Some(name) if name == "export" || name == "import" => Some((name, args.collect::<Vec<_>>())),
+        _ => None,
+    };
+
+    let config = match Config::load() {
+        Ok(config) if config.chats.is_empty() && command.is_none() => Err(ConfigError {
+            problems: vec!["no chats to log; list them in chats (TELEGRAM_CHATS)".to_string()],
+        }),
+        result => result,
+    };
+    let config = match config {
+        Ok(config) => config.install(),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some((name, args)) = command {
+        let result = match name.as_str() {
+            "export" => run_export_command(&args),
+            _ => run_import_command(&args),
+        };
+        if let Err(e) = result {
+            eprintln!("{} failed: {}", name, e);
+            std::process::exit(1);
+        }
+        return;
+    }
 
     let conf = get_configuration(None).unwrap();
-    let addr = conf.leptos_options.site_addr;
+    let addr = config.bind_address.unwrap_or(conf.leptos_options.site_addr);
     let leptos_options = conf.leptos_options;
+
+    let telegram = TelegramService::new();
+    let chats = resolve_chat_ids(&telegram, &config.chats).await;
+    let state = AppState {
+        leptos_options: leptos_options.clone(),
+        telegram,
+        live: LiveFeed::new(),
+        archive: Archive::open(&config.archive_path).expect("failed to open message archive"),
+        chats,
+    };
+
+    spawn_update_loop(state.telegram.clone(), state.live.clone(), state.archive.clone(), state.chats.clone());
+    spawn_backfill(state.telegram.clone(), state.archive.clone(), state.chats.clone());
     
     // Generate the list of routes in your Leptos App
     let routes = generate_route_list(App);
 
     let app = Router::new()
-        .leptos_routes(&leptos_options, routes, {
+        .leptos_routes(&state, routes, {
             let leptos_options = leptos_options.clone();
             move || shell(leptos_options.clone())
         })
+        .route("/live/{chat_id}", axum::routing::get(live_events))
+        .route("/export/{chat_id}", axum::routing::get(export_chat))
+        .route("/media/{document_id}", axum::routing::get(serve_media))
+        .route("/lottie/{file_name}", axum::routing::get(serve_lottie))
+        .route("/health", axum::routing::get(
+            |axum::extract::State(state): axum::extract::State<AppState>| async move {
+                axum::Json(state.telegram.health())
+            }
+        ))
         .nest_service("/stickers", axum::routing::get_service(
             tower::ServiceBuilder::new()
-                .service(tower_http::services::ServeDir::new("target/site/stickers"))
+                .service(tower_http::services::ServeDir::new(config.sticker_dir()))
+        ))
+        .nest_service("/avatars", axum::routing::get_service(
+            tower::ServiceBuilder::new()
+                .service(tower_http::services::ServeDir::new(config.avatar_dir()))
+        ))
+        .nest_service("/photos", axum::routing::get_service(
+            tower::ServiceBuilder::new()
+                .service(tower_http::services::ServeDir::new(config.photo_dir()))
         ))
-        .fallback(leptos_axum::file_and_error_handler(shell))
-        .with_state(leptos_options);
+        .fallback(leptos_axum::file_and_error_handler::<AppState, _>(shell))
+        .with_state(state);
 
     // run our app with hyper
     // `axum::Server` is a re-export of `hyper::Server`
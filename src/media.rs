@@ -0,0 +1,367 @@
+//! On-demand cache of documents, videos, audio and voice messages, and the
+//! decoding of animated stickers for the browser.
+//!
+//! Files are stored in `Config::file_dir`, named by their Telegram document
+//! id, and only fetched from Telegram the first time someone opens them.
+//! Files over `media_max_file_mb` are never fetched, and once the cache
+//! outgrows `media_cache_mb` the least recently served files are removed.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+use axum::body::Body;
+use axum::extract::{Path as UrlPath, State};
+use axum::http::{header, HeaderValue, Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use grammers_client::types::{Downloadable, Media};
+use grammers_client::Client;
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
+use crate::config::config;
+use crate::state::AppState;
+use crate::telegram::{find_chat, MediaInfo, TelegramError};
+
+const MEGABYTE: u64 = 1024 * 1024;
+
+/// Numbers temporary files, so concurrent first requests for the same file
+/// download side by side instead of writing into each other.
+static DOWNLOADS: AtomicU64 = AtomicU64::new(0);
+
+/// `GET /media/:document_id` serves an archived document, downloading it
+/// from Telegram first if it is not cached. Range requests are supported so
+/// players can seek.
+pub async fn serve_media(
+    State(state): State<AppState>,
+    UrlPath(document_id): UrlPath<i64>,
+    request: Request<Body>,
+) -> Response {
+    let message = match state.archive.document_message(document_id) {
+        Ok(Some(message)) => message,
+        Ok(None) => return (StatusCode::NOT_FOUND, "No archived message has this file").into_response(),
+        Err(e) => {
+            eprintln!("Failed to look up document {}: {}", document_id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read the archive").into_response();
+        }
+    };
+    let Some(media) = message.media_info else {
+        return (StatusCode::NOT_FOUND, "No archived message has this file").into_response();
+    };
+
+    let path = cached_path(document_id);
+    if path.exists() {
+        mark_used(&path);
+    } else {
+        let max_file_mb = config().media_max_file_mb;
+        if media.file_size.is_some_and(|size| size > max_file_mb * MEGABYTE) {
+            return (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Files over {} MB are not downloaded (media_max_file_mb)", max_file_mb),
+            ).into_response();
+        }
+
+        let result = state.telegram
+            .run(|client| {
+                let path = path.clone();
+                async move { download_document(&client, message.chat_id, message.id, document_id, &path).await }
+            })
+            .await;
+        if let Err(e) = result {
+            eprintln!("Failed to download document {}: {}", document_id, e);
+            return download_error(e);
+        }
+        evict_least_recently_used(&config().file_dir(), config().media_cache_mb * MEGABYTE, &path);
+    }
+
+    match ServeFile::new(&path).oneshot(request).await {
+        Ok(response) => {
+            let mut response = response.map(Body::new);
+            add_media_headers(&mut response, &media);
+            response
+        }
+        Err(e) => {
+            eprintln!("Failed to serve {}: {}", path.display(), e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read the cached file").into_response()
+        }
+    }
+}
+
+/// `GET /lottie/:file_name` serves a cached animated (TGS) sticker as the
+/// plain Lottie JSON players expect; on disk it is gzipped.
+pub async fn serve_lottie(UrlPath(file_name): UrlPath<String>) -> Response {
+    use std::io::Read;
+
+    if !file_name.starts_with("sticker_") || !file_name.ends_with(".tgs") || file_name.contains(['/', '\\']) {
+        return (StatusCode::NOT_FOUND, "Not an animated sticker").into_response();
+    }
+    let compressed = match tokio::fs::read(config().sticker_dir().join(&file_name)).await {
+        Ok(compressed) => compressed,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return (StatusCode::NOT_FOUND, "Sticker not downloaded yet").into_response();
+        }
+        Err(e) => {
+            eprintln!("Failed to read sticker {}: {}", file_name, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read the sticker").into_response();
+        }
+    };
+
+    let mut json = String::new();
+    if let Err(e) = flate2::read::GzDecoder::new(compressed.as_slice()).read_to_string(&mut json) {
+        eprintln!("Sticker {} is not gzipped Lottie JSON: {}", file_name, e);
+        return (StatusCode::UNPROCESSABLE_ENTITY, "Sticker is not gzipped Lottie JSON").into_response();
+    }
+    ([(header::CONTENT_TYPE, "application/json")], json).into_response()
+}
+
+fn cached_path(document_id: i64) -> PathBuf {
+    config().file_dir().join(document_id.to_string())
+}
+
+/// Fetches message `message_id` of `chat_id` again, since file references
+/// expire, and downloads its document to `path`.
+async fn download_document(
+    client: &Client,
+    chat_id: i64,
+    message_id: i32,
+    document_id: i64,
+    path: &Path,
+) -> Result<(), TelegramError> {
+    let failed = |reason: String| TelegramError::MediaDownloadFailed {
+        media: format!("document {}", document_id),
+        reason,
+    };
+
+    let chat = find_chat(client, chat_id).await?;
+    let message = client.get_messages_by_id(chat, &[message_id]).await?.pop().flatten();
+    let document = match message.and_then(|message| message.media()) {
+        Some(Media::Document(document)) if document.id() == document_id => document,
+        _ => return Err(failed("the message no longer has it on Telegram".to_string())),
+    };
+
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await
+            .map_err(|e| failed(format!("creating directory: {}", e)))?;
+    }
+    let partial = path.with_extension(format!("{}.part", DOWNLOADS.fetch_add(1, Ordering::Relaxed)));
+    eprintln!("Downloading document {} from Telegram", document_id);
+    let downloaded = client.download_media(&Downloadable::Media(Media::Document(document)), &partial).await;
+    if let Err(e) = downloaded {
+        let _ = tokio::fs::remove_file(&partial).await;
+        return Err(failed(format!("downloading: {}", e)));
+    }
+    tokio::fs::rename(&partial, path).await.map_err(|e| failed(format!("saving: {}", e)))
+}
+
+/// Records that `path` was just served; eviction goes by modification time.
+fn mark_used(path: &Path) {
+    let touched = std::fs::File::options()
+        .write(true)
+        .open(path)
+        .and_then(|file| file.set_modified(SystemTime::now()));
+    if let Err(e) = touched {
+        eprintln!("Failed to update {}: {}", path.display(), e);
+    }
+}
+
+/// Removes the least recently served files in `dir` until they take at most
+/// `limit` bytes, keeping `keep`, the file just downloaded.
+fn evict_least_recently_used(dir: &Path, limit: u64, keep: &Path) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Failed to list the media cache: {}", e);
+            return;
+        }
+    };
+
+    let mut files: Vec<(SystemTime, u64, PathBuf)> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let metadata = entry.metadata().ok()?;
+            // Leave downloads in progress alone
+            let is_partial = entry.path().extension().is_some_and(|extension| extension == "part");
+            (metadata.is_file() && !is_partial)
+                .then(|| (metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH), metadata.len(), entry.path()))
+        })
+        .collect();
+    let mut total: u64 = files.iter().map(|(_, size, _)| size).sum();
+    files.sort();
+
+    for (_, size, path) in files {
+        if total <= limit {
+            break;
+        }
+        if path == keep {
+            continue;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(()) => total -= size,
+            Err(e) => eprintln!("Failed to evict {}: {}", path.display(), e),
+        }
+    }
+}
+
+/// Cached files have no extension, so the type and name come from the
+/// archived message. Only audio, video and raster images are shown inline;
+/// anything else someone posted, HTML or SVG included, is downloaded so it
+/// cannot run script on this origin.
+fn add_media_headers(response: &mut Response, media: &MediaInfo) {
+    let headers = response.headers_mut();
+    headers.insert(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    headers.insert(header::CONTENT_SECURITY_POLICY, HeaderValue::from_static("sandbox"));
+    if let Some(mime_type) = media.mime_type.as_deref().and_then(|mime| HeaderValue::from_str(mime).ok()) {
+        headers.insert(header::CONTENT_TYPE, mime_type);
+    }
+
+    let disposition = if media.mime_type.as_deref().is_some_and(is_inline_type) { "inline" } else { "attachment" };
+    let disposition = match &media.file_name {
+        Some(file_name) => format!("{}; filename*=UTF-8''{}", disposition, percent_encode(file_name)),
+        None => disposition.to_string(),
+    };
+    if let Ok(disposition) = HeaderValue::from_str(&disposition) {
+        headers.insert(header::CONTENT_DISPOSITION, disposition);
+    }
+}
+
+/// Whether a file of this MIME type is safe to show in the browser.
+fn is_inline_type(mime_type: &str) -> bool {
+    let mime_type = mime_type.to_ascii_lowercase();
+    let essence = mime_type.split(';').next().unwrap_or_default().trim();
+    essence.starts_with("video/")
+        || essence.starts_with("audio/")
+        || (essence.starts_with("image/") && !essence.starts_with("image/svg"))
+}
+
+/// Encodes `value` for an RFC 5987 header parameter.
+fn percent_encode(value: &str) -> String {
+    value.bytes().fold(String::new(), |mut encoded, byte| {
+        if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+        encoded
+    })
+}
+
+fn download_error(e: TelegramError) -> Response {
+    let status = match &e {
+        TelegramError::NotAuthorized | TelegramError::Network(_) => StatusCode::SERVICE_UNAVAILABLE,
+        TelegramError::FloodWait { seconds } => {
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, seconds.to_string())],
+                e.to_string(),
+            ).into_response();
+        }
+        TelegramError::ChatNotFound { .. } | TelegramError::NoAccess { .. } => StatusCode::NOT_FOUND,
+        TelegramError::MediaDownloadFailed { .. } | TelegramError::Rpc(_) => StatusCode::BAD_GATEWAY,
+        TelegramError::Server(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, e.to_string()).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn only_playable_media_is_inline() {
+        let cases = [
+            ("video/mp4", true),
+            ("audio/ogg; codecs=opus", true),
+            ("image/jpeg", true),
+            ("IMAGE/PNG", true),
+            (" image/webp ", true),
+            ("image/svg+xml", false),
+            ("Image/SVG+XML; charset=utf-8", false),
+            ("text/html", false),
+            ("application/xhtml+xml", false),
+            ("application/pdf", false),
+            ("text/plain; image/png", false),
+            ("", false),
+        ];
+        for (mime_type, inline) in cases {
+            assert_eq!(is_inline_type(mime_type), inline, "{:?}", mime_type);
+        }
+    }
+
+    #[test]
+    fn file_names_are_percent_encoded() {
+        let cases = [
+            ("report.pdf", "report.pdf"),
+            ("my report.pdf", "my%20report.pdf"),
+            ("a\"b\".txt", "a%22b%22.txt"),
+            ("semi;colon,comma.txt", "semi%3Bcolon%2Ccomma.txt"),
+            ("100%.txt", "100%25.txt"),
+            ("line\r\nbreak", "line%0D%0Abreak"),
+            ("../etc/passwd", "..%2Fetc%2Fpasswd"),
+            ("отчёт.pdf", "%D0%BE%D1%82%D1%87%D1%91%D1%82.pdf"),
+            ("!#$&+-.^_`|~", "!#$&+-.^_`|~"),
+            ("", ""),
+        ];
+        for (file_name, expected) in cases {
+            let encoded = percent_encode(file_name);
+            assert_eq!(encoded, expected, "{:?}", file_name);
+            assert!(HeaderValue::from_str(&format!("attachment; filename*=UTF-8''{}", encoded)).is_ok());
+        }
+    }
+
+    /// Writes a file of `size` bytes last used `age` seconds ago.
+    fn cached_file(dir: &Path, name: &str, size: usize, age: u64) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, vec![0; size]).unwrap();
+        let used = SystemTime::now() - Duration::from_secs(age);
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(used).unwrap();
+        path
+    }
+
+    fn remaining(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn least_recently_used_files_are_evicted_first() {
+        let dir = tempfile::tempdir().unwrap();
+        cached_file(dir.path(), "1", 100, 40);
+        cached_file(dir.path(), "2", 100, 30);
+        cached_file(dir.path(), "3", 100, 20);
+        let keep = cached_file(dir.path(), "4", 100, 10);
+        cached_file(dir.path(), "5.0.part", 1000, 50);
+
+        evict_least_recently_used(dir.path(), 250, &keep);
+        assert_eq!(remaining(dir.path()), ["3", "4", "5.0.part"]);
+
+        // Nothing more to do once the files fit
+        evict_least_recently_used(dir.path(), 250, &keep);
+        assert_eq!(remaining(dir.path()), ["3", "4", "5.0.part"]);
+    }
+
+    #[test]
+    fn the_kept_file_is_never_evicted() {
+        let dir = tempfile::tempdir().unwrap();
+        // Oldest, and larger than the whole cache on its own
+        let keep = cached_file(dir.path(), "1", 500, 40);
+        cached_file(dir.path(), "2", 100, 30);
+        cached_file(dir.path(), "3", 100, 20);
+
+        evict_least_recently_used(dir.path(), 250, &keep);
+        assert_eq!(remaining(dir.path()), ["1"]);
+
+        evict_least_recently_used(dir.path(), 0, &keep);
+        assert_eq!(remaining(dir.path()), ["1"]);
+    }
+
+    #[test]
+    fn a_missing_cache_is_left_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("files");
+        evict_least_recently_used(&missing, 0, &missing.join("1"));
+        assert!(!missing.exists());
+    }
+}
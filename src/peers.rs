@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use grammers_client::client::messages::InvocationError;
+use grammers_client::Client;
+use grammers_session::{PackedChat, PackedType};
+use grammers_tl_types as tl;
+use once_cell::sync::Lazy;
+use crate::config::{config, ChatRef};
+use crate::telegram::TelegramService;
+
+/// Resolved chats, kept next to the session file.
+pub static PEER_CACHE: Lazy<PeerCache> = Lazy::new(|| PeerCache::load(config().peer_cache_path()));
+
+/// Why a configured chat could not be resolved.
+#[derive(Debug)]
+pub enum ResolveError {
+    /// Nothing exists under this id, username or invite link.
+    NotFound(ChatRef),
+    /// The chat exists, but this account is not a member or was banned.
+    NoAccess(ChatRef),
+    /// Telegram could not be asked.
+    Telegram(InvocationError),
+}
+
+impl std::fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResolveError::NotFound(chat) => write!(f, "Chat {} not found", chat),
+            ResolveError::NoAccess(chat) => write!(f, "No access to chat {}", chat),
+            ResolveError::Telegram(e) => write!(f, "Failed to resolve chat: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ResolveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ResolveError::Telegram(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// On-disk map from configured chat references to packed chats, so that
+/// resolving a chat costs at most one lookup on Telegram.
+pub struct PeerCache {
+    path: PathBuf,
+    entries: Mutex<HashMap<String, String>>,
+}
+
+impl PeerCache {
+    /// Reads the cache at `path`, starting empty if it is missing or broken.
+    pub fn load<P: Into<PathBuf>>(path: P) -> Self {
+        let path = path.into();
+        let entries = match std::fs::read_to_string(&path) {
+            Ok(data) => serde_json::from_str(&data).unwrap_or_else(|e| {
+                eprintln!("Ignoring unreadable peer cache {}: {}", path.display(), e);
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        Self {
+            path,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    /// Looks up `chat_ref`. A numeric id also matches chats that were cached
+    /// under their username or invite link.
+    pub fn get(&self, chat_ref: &ChatRef) -> Option<PackedChat> {
+        let entries = self.entries.lock().unwrap();
+        if let Some(packed) = entries.get(&chat_ref.to_string()).and_then(|hex| PackedChat::from_hex(hex).ok()) {
+            return Some(packed);
+        }
+        match chat_ref {
+            ChatRef::Id(id) => entries
+                .values()
+                .filter_map(|hex| PackedChat::from_hex(hex).ok())
+                .find(|packed| packed.id == *id),
+            _ => None,
+        }
+    }
+
+    /// Records `packed` under `chat_ref` and writes the cache back to disk.
+    pub fn insert(&self, chat_ref: &ChatRef, packed: PackedChat) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.insert(chat_ref.to_string(), packed.to_hex());
+        if let Err(e) = self.save(&entries) {
+            eprintln!("Failed to write peer cache {}: {}", self.path.display(), e);
+        }
+    }
+
+    fn save(&self, entries: &HashMap<String, String>) -> std::io::Result<()> {
+        // Write a sibling file first so a crash never leaves half a cache
+        let temp_path = self.path.with_extension("tmp");
+        std::fs::write(&temp_path, serde_json::to_vec_pretty(entries)?)?;
+        std::fs::rename(temp_path, &self.path)
+    }
+}
+
+/// Turns `chat_ref` into a packed chat, from the peer cache when possible.
+///
+/// Usernames and invite links take a single request; bare ids are looked up
+/// among the account's dialogs, since Telegram needs an access hash for them.
+pub async fn resolve_chat(client: &Client, chat_ref: &ChatRef) -> Result<PackedChat, ResolveError> {
+    if let Some(packed) = PEER_CACHE.get(chat_ref) {
+        return Ok(packed);
+    }
+
+    let resolved = match chat_ref {
+        ChatRef::Id(id) => find_in_dialogs(client, *id).await.map_err(|e| classify_error(chat_ref, e))?,
+        ChatRef::Username(username) => client
+            .resolve_username(username)
+            .await
+            .map_err(|e| classify_error(chat_ref, e))?
+            .map(|chat| chat.pack()),
+        ChatRef::Invite(hash) => check_invite(client, chat_ref, hash).await?,
+    };
+    let packed = resolved.ok_or_else(|| ResolveError::NotFound(chat_ref.clone()))?;
+
+    PEER_CACHE.insert(chat_ref, packed);
+    Ok(packed)
+}
+
+/// The id of `chat_ref` without asking Telegram: ids as given, usernames
+/// and links only if they have been resolved before.
+pub fn cached_chat_id(chat_ref: &ChatRef) -> Option<i64> {
+    match chat_ref {
+        ChatRef::Id(id) => Some(*id),
+        other => PEER_CACHE.get(other).map(|packed| packed.id),
+    }
+}
+
+async fn find_in_dialogs(client: &Client, chat_id: i64) -> Result<Option<PackedChat>, InvocationError> {
+    let mut dialogs = client.iter_dialogs();
+    while let Some(dialog) = dialogs.next().await? {
+        let chat = dialog.chat();
+        if chat.id() == chat_id {
+            return Ok(Some(chat.pack()));
+        }
+    }
+    Ok(None)
+}
+
+/// Resolves an invite link hash, which only yields a usable chat when the
+/// account has already joined it.
+async fn check_invite(client: &Client, chat_ref: &ChatRef, hash: &str) -> Result<Option<PackedChat>, ResolveError> {
+    let invite = client
+        .invoke(&tl::functions::messages::CheckChatInvite { hash: hash.to_string() })
+        .await;
+    match invite {
+        Ok(tl::enums::ChatInvite::Already(invite)) => pack_raw_chat(invite.chat)
+            .map(Some)
+            .ok_or_else(|| ResolveError::NoAccess(chat_ref.clone())),
+        Ok(_) => Err(ResolveError::NoAccess(chat_ref.clone())),
+        Err(e) if e.is("INVITE_HASH_EXPIRED") || e.is("INVITE_HASH_INVALID") => Ok(None),
+        Err(e) => Err(classify_error(chat_ref, e)),
+    }
+}
+
+/// Maps the RPC errors that say a chat is missing or off-limits onto the
+/// matching `ResolveError`.
+fn classify_error(chat_ref: &ChatRef, error: InvocationError) -> ResolveError {
+    if error.is("CHANNEL_PRIVATE") || error.is("CHAT_FORBIDDEN") || error.is("USER_BANNED_IN_CHANNEL") {
+        ResolveError::NoAccess(chat_ref.clone())
+    } else if error.is("USERNAME_INVALID") || error.is("CHANNEL_INVALID") || error.is("PEER_ID_INVALID") {
+        ResolveError::NotFound(chat_ref.clone())
+    } else {
+        ResolveError::Telegram(error)
+    }
+}
+
+fn pack_raw_chat(chat: tl::enums::Chat) -> Option<PackedChat> {
+    match chat {
+        tl::enums::Chat::Chat(chat) => Some(PackedChat {
+            ty: PackedType::Chat,
+            id: chat.id,
+            access_hash: None,
+        }),
+        tl::enums::Chat::Channel(channel) => Some(PackedChat {
+            ty: if channel.megagroup {
+                PackedType::Megagroup
+            } else if channel.gigagroup {
+                PackedType::Gigagroup
+            } else {
+                PackedType::Broadcast
+            },
+            id: channel.id,
+            access_hash: channel.access_hash,
+        }),
+        tl::enums::Chat::Empty(_) | tl::enums::Chat::Forbidden(_) | tl::enums::Chat::ChannelForbidden(_) => None,
+    }
+}
+
+/// Resolves the configured chats to their ids, leaving out (and logging) the
+/// ones that cannot be resolved. Numeric ids are taken as they are.
+pub async fn resolve_chat_ids(telegram: &TelegramService, chats: &[ChatRef]) -> Vec<i64> {
+    let mut chat_ids = Vec::with_capacity(chats.len());
+    for chat_ref in chats {
+        let resolved = match chat_ref {
+            ChatRef::Id(id) => Ok(*id),
+            _ => telegram
+                .run(|client| async move { Ok(resolve_chat(&client, chat_ref).await?.id) })
+                .await,
+        };
+        match resolved {
+            Ok(id) if !chat_ids.contains(&id) => chat_ids.push(id),
+            Ok(_) => {}
+            Err(e) => eprintln!("Not logging {}: {}", chat_ref, e),
+        }
+    }
+    chat_ids
+}
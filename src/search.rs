@@ -0,0 +1,210 @@
+use leptos::prelude::*;
+use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
+use crate::telegram::{ChatMessage, MessageType, TelegramError};
+
+/// Marks the start of a matched term in `SearchHit::snippet`.
+pub const SNIPPET_START: &str = "\u{2}";
+/// Marks the end of a matched term in `SearchHit::snippet`.
+pub const SNIPPET_END: &str = "\u{3}";
+
+/// Most hits returned by one search.
+#[cfg(feature = "ssr")]
+const SEARCH_LIMIT: i32 = 100;
+/// Messages shown on each side of a hit when jumping to it.
+#[cfg(feature = "ssr")]
+const CONTEXT_RADIUS: i32 = 25;
+
+/// A search over the archive. Empty text with filters set lists every
+/// message that passes the filters.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SearchQuery {
+    /// Words to find in text, captions, file names and sender names; each
+    /// matches as a prefix.
+    pub text: String,
+    pub chat_id: Option<i64>,
+    /// Part of the sender's name.
+    pub sender: Option<String>,
+    pub message_type: Option<MessageType>,
+    /// Inclusive lower bound on the send time, in Unix seconds.
+    pub from: Option<i64>,
+    /// Exclusive upper bound on the send time, in Unix seconds.
+    pub until: Option<i64>,
+    pub has_link: bool,
+    pub has_media: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub message: ChatMessage,
+    /// Excerpt around the match, with hits between `SNIPPET_START` and
+    /// `SNIPPET_END`. `None` when the query had no text.
+    pub snippet: Option<String>,
+}
+
+#[server]
+pub async fn search_messages(query: SearchQuery) -> Result<Vec<SearchHit>, TelegramError> {
+    use crate::state::AppState;
+
+    let state = expect_context::<AppState>();
+    if let Some(chat_id) = query.chat_id.filter(|chat_id| !state.chats.contains(chat_id)) {
+        return Err(TelegramError::ChatNotFound { chat: chat_id.to_string() });
+    }
+    Ok(state.archive.search(&query, SEARCH_LIMIT)?)
+}
+
+/// Loads the archived messages around `message_id`, oldest first.
+#[server]
+pub async fn get_message_context(chat_id: i64, message_id: i32) -> Result<Vec<ChatMessage>, TelegramError> {
+    use crate::state::AppState;
+
+    let state = expect_context::<AppState>();
+    if !state.chats.contains(&chat_id) {
+        return Err(TelegramError::ChatNotFound { chat: chat_id.to_string() });
+    }
+    let mut messages = state.archive.messages_before(chat_id, None, Some(message_id + 1), CONTEXT_RADIUS + 1)?;
+    messages.extend(state.archive.messages_after(chat_id, message_id, CONTEXT_RADIUS)?);
+    crate::downloads::attach_custom_emoji(&mut messages);
+    state.archive.attach_reply_previews(&mut messages)?;
+    Ok(messages)
+}
+
+/// Search form and result list for one chat. Clicking a hit passes its
+/// message to `on_select`.
+#[component]
+pub fn SearchPanel(chat_id: i64, #[prop(into)] on_select: Callback<ChatMessage>) -> impl IntoView {
+    let text = RwSignal::new(String::new());
+    let sender = RwSignal::new(String::new());
+    let message_type = RwSignal::new(String::new());
+    let from = RwSignal::new(String::new());
+    let until = RwSignal::new(String::new());
+    let has_link = RwSignal::new(false);
+    let has_media = RwSignal::new(false);
+
+    let searching = RwSignal::new(false);
+    let results = RwSignal::new(None::<Result<Vec<SearchHit>, TelegramError>>);
+
+    let run_search = move || {
+        let query = SearchQuery {
+            text: text.get_untracked(),
+            chat_id: Some(chat_id),
+            sender: Some(sender.get_untracked()).filter(|sender| !sender.trim().is_empty()),
+            message_type: message_type.get_untracked().parse().ok(),
+            from: parse_date(&from.get_untracked()),
+            // Include the whole end day
+            until: parse_date(&until.get_untracked()).map(|until| until + 24 * 60 * 60),
+            has_link: has_link.get_untracked(),
+            has_media: has_media.get_untracked(),
+        };
+        searching.set(true);
+        leptos::task::spawn_local(async move {
+            results.set(Some(search_messages(query).await));
+            searching.set(false);
+        });
+    };
+
+    view! {
+        <div class="search-panel">
+            <form
+                class="search-form"
+                on:submit=move |event| {
+                    event.prevent_default();
+                    run_search();
+                }
+            >
+                <input
+                    type="search"
+                    class="search-text"
+                    placeholder="Search messages, captions, files..."
+                    prop:value=move || text.get()
+                    on:input=move |event| text.set(event_target_value(&event))
+                />
+                <div class="search-filters">
+                    <input
+                        type="text"
+                        placeholder="Sender"
+                        prop:value=move || sender.get()
+                        on:input=move |event| sender.set(event_target_value(&event))
+                    />
+                    <select on:change=move |event| message_type.set(event_target_value(&event))>
+                        <option value="">"Any type"</option>
+                        {MessageType::iter().map(|message_type| view! {
+                            <option value=message_type.to_string()>{message_type.to_string()}</option>
+                        }).collect::<Vec<_>>()}
+                    </select>
+                    <label>
+                        "From "
+                        <input type="date" on:change=move |event| from.set(event_target_value(&event)) />
+                    </label>
+                    <label>
+                        "Until "
+                        <input type="date" on:change=move |event| until.set(event_target_value(&event)) />
+                    </label>
+                    <label>
+                        <input type="checkbox" on:change=move |event| has_link.set(event_target_checked(&event)) />
+                        " Has link"
+                    </label>
+                    <label>
+                        <input type="checkbox" on:change=move |event| has_media.set(event_target_checked(&event)) />
+                        " Has media"
+                    </label>
+                    <button type="submit" disabled=move || searching.get()>"Search"</button>
+                </div>
+            </form>
+
+            {move || match results.get() {
+                None => view! { <div></div> }.into_any(),
+                Some(Err(error)) => view! {
+                    <crate::chat::ErrorNotice error=error on_retry=move || run_search() />
+                }.into_any(),
+                Some(Ok(hits)) if hits.is_empty() => view! {
+                    <div class="search-empty">"No matching messages."</div>
+                }.into_any(),
+                Some(Ok(hits)) => view! {
+                    <ul class="search-results">
+                        {hits.into_iter().map(|hit| view! { <SearchResult hit=hit on_select=on_select /> }).collect::<Vec<_>>()}
+                    </ul>
+                }.into_any(),
+            }}
+        </div>
+    }.into_any()
+}
+
+#[component]
+fn SearchResult(hit: SearchHit, on_select: Callback<ChatMessage>) -> impl IntoView {
+    let SearchHit { message, snippet } = hit;
+    let excerpt = snippet.unwrap_or_else(|| {
+        let text = message.media_info.as_ref()
+            .and_then(|media| media.file_name.clone())
+            .filter(|_| message.text.is_empty())
+            .unwrap_or_else(|| message.text.clone());
+        text.chars().take(120).collect()
+    });
+    let header = format!("{} · {}", message.sender, crate::chat::format_timestamp(message.timestamp));
+
+    view! {
+        <li class="search-result" on:click=move |_| on_select.run(message.clone())>
+            <div class="search-result-header">{header}</div>
+            <div class="search-result-snippet">{highlight_snippet(&excerpt)}</div>
+        </li>
+    }
+}
+
+/// Renders `snippet` with the terms between `SNIPPET_START` and
+/// `SNIPPET_END` wrapped in `<mark>`.
+pub fn highlight_snippet(snippet: &str) -> Vec<AnyView> {
+    let mut parts = snippet.split(SNIPPET_START);
+    let mut views = vec![parts.next().unwrap_or_default().to_string().into_any()];
+    for part in parts {
+        let (marked, rest) = part.split_once(SNIPPET_END).unwrap_or((part, ""));
+        views.push(view! { <mark>{marked.to_string()}</mark> }.into_any());
+        views.push(rest.to_string().into_any());
+    }
+    views
+}
+
+/// Parses a `YYYY-MM-DD` date input into midnight UTC.
+fn parse_date(date: &str) -> Option<i64> {
+    let date = chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+    Some(date.and_hms_opt(0, 0, 0)?.and_utc().timestamp())
+}
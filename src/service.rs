@@ -0,0 +1,420 @@
+//! Service messages: the joins, pins, renames, calls and other events
+//! Telegram records in a chat, decoded from its message actions.
+//!
+//! Each action is archived in typed form and also described in words as the
+//! message's text, so search, exports and the sidebar preview read like the
+//! Telegram apps. Users other than the one acting are only known by id when
+//! the message arrives; their names are looked up afterwards and the text
+//! is described again.
+
+use serde::{Deserialize, Serialize};
+use crate::chat::format_timestamp;
+use crate::telegram::format_amount;
+
+/// Someone a service message is about besides its sender.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ServiceUser {
+    /// Missing for users known from an imported export, which only names them.
+    pub id: Option<i64>,
+    pub name: Option<String>,
+}
+
+impl ServiceUser {
+    fn display_name(&self) -> &str {
+        self.name.as_deref().filter(|name| !name.is_empty()).unwrap_or("a user")
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ServiceAction {
+    GroupCreated { title: String },
+    ChannelCreated { title: String },
+    /// The sender added themselves, e.g. by joining a public group.
+    Joined,
+    JoinedByLink,
+    JoinedByRequest,
+    MembersAdded { members: Vec<ServiceUser> },
+    Left,
+    MemberRemoved { member: ServiceUser },
+    TitleChanged { title: String },
+    PhotoChanged,
+    PhotoRemoved,
+    /// The pinned message is the one the service message replies to.
+    MessagePinned,
+    HistoryCleared,
+    MigratedToSupergroup { channel_id: i64 },
+    MigratedFromGroup { title: String, chat_id: i64 },
+    Call { video: bool, missed: bool, duration: Option<i32> },
+    VideoChatStarted,
+    VideoChatEnded { duration: i32 },
+    VideoChatScheduled { date: i64 },
+    VideoChatInvite { members: Vec<ServiceUser> },
+    TopicCreated { title: String, icon_color: i32 },
+    /// Only what changed is set.
+    TopicEdited { title: Option<String>, closed: Option<bool>, hidden: Option<bool> },
+    ScreenshotTaken,
+    GameScore { score: i32 },
+    /// `total_amount` is in the smallest unit of `currency`.
+    PaymentSent { currency: String, total_amount: i64 },
+    PremiumGifted { months: i32 },
+    ContactSignedUp,
+    /// Seconds after which new messages are deleted; 0 turns it off.
+    AutoDeleteChanged { period: i32 },
+    /// An empty emoticon means the theme was turned off.
+    ThemeChanged { emoticon: String },
+    BotAllowed,
+    /// Text set by a bot or Telegram itself.
+    Custom { text: String },
+    /// An action not decoded here, by its Telegram name.
+    Other { name: String },
+}
+
+impl ServiceAction {
+    /// The action in words, as done by `actor`, the message's sender.
+    pub fn describe(&self, actor: &str) -> String {
+        match self {
+            ServiceAction::GroupCreated { title } => format!("{} created the group «{}»", actor, title),
+            ServiceAction::ChannelCreated { title } => format!("Channel «{}» created", title),
+            ServiceAction::Joined => format!("{} joined the group", actor),
+            ServiceAction::JoinedByLink => format!("{} joined the group via invite link", actor),
+            ServiceAction::JoinedByRequest => format!("{} was accepted into the group", actor),
+            ServiceAction::MembersAdded { members } => format!("{} added {}", actor, list_names(members)),
+            ServiceAction::Left => format!("{} left the group", actor),
+            ServiceAction::MemberRemoved { member } => format!("{} removed {}", actor, member.display_name()),
+            ServiceAction::TitleChanged { title } => format!("{} changed the group name to «{}»", actor, title),
+            ServiceAction::PhotoChanged => format!("{} changed the group photo", actor),
+            ServiceAction::PhotoRemoved => format!("{} removed the group photo", actor),
+            ServiceAction::MessagePinned => format!("{} pinned a message", actor),
+            ServiceAction::HistoryCleared => "History was cleared".to_string(),
+            ServiceAction::MigratedToSupergroup { .. } => "The group was upgraded to a supergroup".to_string(),
+            ServiceAction::MigratedFromGroup { title, .. } => {
+                format!("The group «{}» was upgraded to this supergroup", title)
+            }
+            ServiceAction::Call { video, missed, duration } => {
+                let call = if *video { "video call" } else { "call" };
+                match (missed, duration) {
+                    (true, _) => format!("Missed {} from {}", call, actor),
+                    (false, Some(duration)) => {
+                        format!("{} from {} ({})", capitalize(call), actor, format_duration(*duration))
+                    }
+                    (false, None) => format!("Cancelled {} from {}", call, actor),
+                }
+            }
+            ServiceAction::VideoChatStarted => format!("{} started a video chat", actor),
+            ServiceAction::VideoChatEnded { duration } => {
+                format!("Video chat ended after {}", format_duration(*duration))
+            }
+            ServiceAction::VideoChatScheduled { date } => {
+                format!("{} scheduled a video chat for {}", actor, format_timestamp(*date))
+            }
+            ServiceAction::VideoChatInvite { members } => {
+                format!("{} invited {} to the video chat", actor, list_names(members))
+            }
+            ServiceAction::TopicCreated { title, .. } => format!("{} created the topic «{}»", actor, title),
+            ServiceAction::TopicEdited { title, closed, hidden } => match (title, closed, hidden) {
+                (Some(title), _, _) => format!("{} renamed the topic to «{}»", actor, title),
+                (None, Some(true), _) => format!("{} closed the topic", actor),
+                (None, Some(false), _) => format!("{} reopened the topic", actor),
+                (None, None, Some(true)) => format!("{} hid the topic", actor),
+                (None, None, Some(false)) => format!("{} unhid the topic", actor),
+                (None, None, None) => format!("{} changed the topic icon", actor),
+            },
+            ServiceAction::ScreenshotTaken => format!("{} took a screenshot", actor),
+            ServiceAction::GameScore { score } => format!("{} scored {}", actor, score),
+            ServiceAction::PaymentSent { currency, total_amount } => {
+                format!("{} paid {}", actor, format_amount(*total_amount, currency))
+            }
+            ServiceAction::PremiumGifted { months } => {
+                format!("{} gifted Telegram Premium for {} months", actor, months)
+            }
+            ServiceAction::ContactSignedUp => format!("{} joined Telegram", actor),
+            ServiceAction::AutoDeleteChanged { period: 0 } => format!("{} turned off auto-delete", actor),
+            ServiceAction::AutoDeleteChanged { period } => {
+                format!("{} set messages to auto-delete after {}", actor, format_period(*period))
+            }
+            ServiceAction::ThemeChanged { emoticon } if emoticon.is_empty() => {
+                format!("{} turned off the chat theme", actor)
+            }
+            ServiceAction::ThemeChanged { emoticon } => format!("{} changed the chat theme to {}", actor, emoticon),
+            ServiceAction::BotAllowed => format!("{} allowed the bot to message them", actor),
+            ServiceAction::Custom { text } => text.clone(),
+            ServiceAction::Other { name } => format!("{}: {}", actor, name),
+        }
+    }
+
+    /// Users the action is about, whose names may still have to be looked up.
+    pub fn members_mut(&mut self) -> &mut [ServiceUser] {
+        match self {
+            ServiceAction::MembersAdded { members } | ServiceAction::VideoChatInvite { members } => members,
+            ServiceAction::MemberRemoved { member } => std::slice::from_mut(member),
+            _ => &mut [],
+        }
+    }
+}
+
+/// "Alice", "Alice and Bob" or "Alice, Bob and 3 others".
+fn list_names(members: &[ServiceUser]) -> String {
+    let names: Vec<&str> = members.iter().map(ServiceUser::display_name).collect();
+    match names.as_slice() {
+        [] => "nobody".to_string(),
+        [only] => only.to_string(),
+        [first, second] => format!("{} and {}", first, second),
+        [first, second, third] => format!("{}, {} and {}", first, second, third),
+        [first, second, rest @ ..] => format!("{}, {} and {} others", first, second, rest.len()),
+    }
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    chars.next().map_or_else(String::new, |first| first.to_uppercase().chain(chars).collect())
+}
+
+/// `1:05` or `1:02:03`.
+fn format_duration(seconds: i32) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+/// Auto-delete periods, which Telegram offers in whole days, weeks or months.
+fn format_period(seconds: i32) -> String {
+    const DAY: i32 = 24 * 60 * 60;
+    let (count, unit) = match seconds {
+        _ if seconds >= 30 * DAY && seconds % (30 * DAY) == 0 => (seconds / (30 * DAY), "month"),
+        _ if seconds >= 7 * DAY && seconds % (7 * DAY) == 0 => (seconds / (7 * DAY), "week"),
+        _ if seconds >= DAY && seconds % DAY == 0 => (seconds / DAY, "day"),
+        _ if seconds >= 3600 && seconds % 3600 == 0 => (seconds / 3600, "hour"),
+        _ => (seconds, "second"),
+    };
+    format!("{} {}{}", count, unit, if count == 1 { "" } else { "s" })
+}
+
+/// Decodes the action of service message `message`. `None` for regular
+/// messages.
+#[cfg(feature = "ssr")]
+pub fn service_action(message: &grammers_client::types::Message) -> Option<ServiceAction> {
+    decode_action(message.action()?, message.sender().map(|sender| sender.id()))
+}
+
+/// Decodes `action`, sent by `sender_id`.
+#[cfg(feature = "ssr")]
+fn decode_action(action: &grammers_tl_types::enums::MessageAction, sender_id: Option<i64>) -> Option<ServiceAction> {
+    use grammers_tl_types::enums::{MessageAction, PhoneCallDiscardReason};
+
+    let user = |id: i64| ServiceUser { id: Some(id), name: None };
+    Some(match action {
+        MessageAction::Empty => return None,
+        MessageAction::ChatCreate(action) => ServiceAction::GroupCreated { title: action.title.clone() },
+        MessageAction::ChannelCreate(action) => ServiceAction::ChannelCreated { title: action.title.clone() },
+        MessageAction::ChatAddUser(action) if action.users.len() == 1 && Some(action.users[0]) == sender_id => {
+            ServiceAction::Joined
+        }
+        MessageAction::ChatAddUser(action) => ServiceAction::MembersAdded {
+            members: action.users.iter().copied().map(user).collect(),
+        },
+        MessageAction::ChatJoinedByLink(_) => ServiceAction::JoinedByLink,
+        MessageAction::ChatJoinedByRequest => ServiceAction::JoinedByRequest,
+        MessageAction::ChatDeleteUser(action) if Some(action.user_id) == sender_id => ServiceAction::Left,
+        MessageAction::ChatDeleteUser(action) => ServiceAction::MemberRemoved { member: user(action.user_id) },
+        MessageAction::ChatEditTitle(action) => ServiceAction::TitleChanged { title: action.title.clone() },
+        MessageAction::ChatEditPhoto(_) => ServiceAction::PhotoChanged,
+        MessageAction::ChatDeletePhoto => ServiceAction::PhotoRemoved,
+        MessageAction::PinMessage => ServiceAction::MessagePinned,
+        MessageAction::HistoryClear => ServiceAction::HistoryCleared,
+        MessageAction::ChatMigrateTo(action) => ServiceAction::MigratedToSupergroup { channel_id: action.channel_id },
+        MessageAction::ChannelMigrateFrom(action) => ServiceAction::MigratedFromGroup {
+            title: action.title.clone(),
+            chat_id: action.chat_id,
+        },
+        MessageAction::PhoneCall(action) => ServiceAction::Call {
+            video: action.video,
+            missed: matches!(action.reason, Some(PhoneCallDiscardReason::Missed | PhoneCallDiscardReason::Busy)),
+            duration: action.duration,
+        },
+        MessageAction::GroupCall(action) => match action.duration {
+            Some(duration) => ServiceAction::VideoChatEnded { duration },
+            None => ServiceAction::VideoChatStarted,
+        },
+        MessageAction::GroupCallScheduled(action) => ServiceAction::VideoChatScheduled {
+            date: action.schedule_date.into(),
+        },
+        MessageAction::InviteToGroupCall(action) => ServiceAction::VideoChatInvite {
+            members: action.users.iter().copied().map(user).collect(),
+        },
+        MessageAction::TopicCreate(action) => ServiceAction::TopicCreated {
+            title: action.title.clone(),
+            icon_color: action.icon_color,
+        },
+        MessageAction::TopicEdit(action) => ServiceAction::TopicEdited {
+            title: action.title.clone(),
+            closed: action.closed,
+            hidden: action.hidden,
+        },
+        MessageAction::ScreenshotTaken => ServiceAction::ScreenshotTaken,
+        MessageAction::GameScore(action) => ServiceAction::GameScore { score: action.score },
+        MessageAction::PaymentSent(action) => ServiceAction::PaymentSent {
+            currency: action.currency.clone(),
+            total_amount: action.total_amount,
+        },
+        MessageAction::GiftPremium(action) => ServiceAction::PremiumGifted { months: action.months },
+        MessageAction::ContactSignUp => ServiceAction::ContactSignedUp,
+        MessageAction::SetMessagesTtl(action) => ServiceAction::AutoDeleteChanged { period: action.period },
+        MessageAction::SetChatTheme(action) => ServiceAction::ThemeChanged { emoticon: action.emoticon.clone() },
+        MessageAction::BotAllowed(_) => ServiceAction::BotAllowed,
+        MessageAction::CustomAction(action) => ServiceAction::Custom { text: action.message.clone() },
+        other => {
+            // The variant name, without its fields
+            let name = format!("{:?}", other);
+            let name = name.split(['(', ' ']).next().unwrap_or_default().to_string();
+            ServiceAction::Other { name }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(name: Option<&str>) -> ServiceUser {
+        ServiceUser { id: Some(1), name: name.map(str::to_string) }
+    }
+
+    #[test]
+    fn actions_are_described() {
+        let cases = [
+            (ServiceAction::GroupCreated { title: "Book club".to_string() }, "Alice created the group «Book club»"),
+            (ServiceAction::Joined, "Alice joined the group"),
+            (ServiceAction::MembersAdded { members: vec![member(Some("Bob"))] }, "Alice added Bob"),
+            (ServiceAction::MembersAdded { members: vec![member(None)] }, "Alice added a user"),
+            (ServiceAction::MembersAdded { members: vec![member(Some(""))] }, "Alice added a user"),
+            (ServiceAction::MembersAdded { members: Vec::new() }, "Alice added nobody"),
+            (ServiceAction::MemberRemoved { member: member(None) }, "Alice removed a user"),
+            (
+                ServiceAction::VideoChatInvite { members: vec![member(Some("Bob")), member(None)] },
+                "Alice invited Bob and a user to the video chat",
+            ),
+            (ServiceAction::Call { video: false, missed: true, duration: None }, "Missed call from Alice"),
+            (ServiceAction::Call { video: true, missed: false, duration: Some(65) }, "Video call from Alice (1:05)"),
+            (ServiceAction::Call { video: false, missed: false, duration: None }, "Cancelled call from Alice"),
+            (ServiceAction::VideoChatEnded { duration: 3723 }, "Video chat ended after 1:02:03"),
+            (ServiceAction::TopicEdited { title: None, closed: Some(true), hidden: None }, "Alice closed the topic"),
+            (ServiceAction::TopicEdited { title: None, closed: None, hidden: None }, "Alice changed the topic icon"),
+            (ServiceAction::AutoDeleteChanged { period: 0 }, "Alice turned off auto-delete"),
+            (ServiceAction::AutoDeleteChanged { period: 86400 }, "Alice set messages to auto-delete after 1 day"),
+            (ServiceAction::ThemeChanged { emoticon: String::new() }, "Alice turned off the chat theme"),
+            (ServiceAction::Custom { text: "Set by a bot".to_string() }, "Set by a bot"),
+            (ServiceAction::Other { name: "GiftCode".to_string() }, "Alice: GiftCode"),
+        ];
+        for (action, expected) in cases {
+            assert_eq!(action.describe("Alice"), expected, "{:?}", action);
+        }
+    }
+
+    #[test]
+    fn members_are_listed_by_name() {
+        let cases: [(&[Option<&str>], &str); 8] = [
+            (&[], "nobody"),
+            (&[Some("Alice")], "Alice"),
+            (&[None], "a user"),
+            (&[Some("Alice"), None], "Alice and a user"),
+            (&[Some(""), Some("Bob")], "a user and Bob"),
+            (&[Some("Alice"), Some("Bob"), Some("Carol")], "Alice, Bob and Carol"),
+            (&[Some("Alice"), Some("Bob"), Some("Carol"), None], "Alice, Bob and 2 others"),
+            (&[None, None, None, None, None], "a user, a user and 3 others"),
+        ];
+        for (names, expected) in cases {
+            let members: Vec<ServiceUser> = names.iter().map(|&name| member(name)).collect();
+            assert_eq!(list_names(&members), expected, "{:?}", names);
+        }
+    }
+
+    #[test]
+    fn periods_use_the_largest_whole_unit() {
+        const DAY: i32 = 24 * 60 * 60;
+        let cases = [
+            (1, "1 second"),
+            (59, "59 seconds"),
+            (3599, "3599 seconds"),
+            (3600, "1 hour"),
+            (3601, "3601 seconds"),
+            (2 * 3600, "2 hours"),
+            (DAY - 3600, "23 hours"),
+            (DAY, "1 day"),
+            (DAY + 1, "86401 seconds"),
+            (6 * DAY, "6 days"),
+            (7 * DAY, "1 week"),
+            (8 * DAY, "8 days"),
+            (14 * DAY, "2 weeks"),
+            (29 * DAY, "29 days"),
+            (30 * DAY, "1 month"),
+            (31 * DAY, "31 days"),
+            (35 * DAY, "5 weeks"),
+            (365 * DAY, "365 days"),
+            (360 * DAY, "12 months"),
+        ];
+        for (seconds, expected) in cases {
+            assert_eq!(format_period(seconds), expected, "{}", seconds);
+        }
+    }
+
+    #[cfg(feature = "ssr")]
+    #[test]
+    fn telegram_actions_are_decoded() {
+        use grammers_tl_types as tl;
+        use tl::enums::{MessageAction, PhoneCallDiscardReason};
+
+        let user = |id: i64| ServiceUser { id: Some(id), name: None };
+        let cases = [
+            (MessageAction::ChatAddUser(tl::types::MessageActionChatAddUser { users: vec![7] }), Some(ServiceAction::Joined)),
+            (
+                MessageAction::ChatAddUser(tl::types::MessageActionChatAddUser { users: vec![8] }),
+                Some(ServiceAction::MembersAdded { members: vec![user(8)] }),
+            ),
+            (
+                MessageAction::ChatAddUser(tl::types::MessageActionChatAddUser { users: vec![7, 8] }),
+                Some(ServiceAction::MembersAdded { members: vec![user(7), user(8)] }),
+            ),
+            (MessageAction::ChatDeleteUser(tl::types::MessageActionChatDeleteUser { user_id: 7 }), Some(ServiceAction::Left)),
+            (
+                MessageAction::ChatDeleteUser(tl::types::MessageActionChatDeleteUser { user_id: 8 }),
+                Some(ServiceAction::MemberRemoved { member: user(8) }),
+            ),
+            (
+                MessageAction::PhoneCall(tl::types::MessageActionPhoneCall {
+                    video: false,
+                    call_id: 1,
+                    reason: Some(PhoneCallDiscardReason::Busy),
+                    duration: None,
+                }),
+                Some(ServiceAction::Call { video: false, missed: true, duration: None }),
+            ),
+            (
+                MessageAction::PhoneCall(tl::types::MessageActionPhoneCall {
+                    video: true,
+                    call_id: 1,
+                    reason: Some(PhoneCallDiscardReason::Hangup),
+                    duration: Some(65),
+                }),
+                Some(ServiceAction::Call { video: true, missed: false, duration: Some(65) }),
+            ),
+            (
+                MessageAction::SetMessagesTtl(tl::types::MessageActionSetMessagesTtl { period: 0, auto_setting_from: None }),
+                Some(ServiceAction::AutoDeleteChanged { period: 0 }),
+            ),
+            (MessageAction::ScreenshotTaken, Some(ServiceAction::ScreenshotTaken)),
+            (MessageAction::Empty, None),
+            (MessageAction::ChatJoinedByRequest, Some(ServiceAction::JoinedByRequest)),
+            (MessageAction::GiveawayLaunch, Some(ServiceAction::Other { name: "GiveawayLaunch".to_string() })),
+        ];
+        for (action, expected) in cases {
+            assert_eq!(decode_action(&action, Some(7)), expected, "{:?}", action);
+        }
+        // Without a known sender, nobody joined by themselves
+        let action = MessageAction::ChatAddUser(tl::types::MessageActionChatAddUser { users: vec![7] });
+        assert_eq!(decode_action(&action, None), Some(ServiceAction::MembersAdded { members: vec![user(7)] }));
+    }
+}
@@ -0,0 +1,112 @@
+use leptos::prelude::*;
+use crate::chat::ErrorNotice;
+use crate::telegram::{ChatMessage, ChatSummary, TelegramError};
+
+/// Lists the logged chats in configuration order.
+///
+/// Names, avatars and unread counts come from Telegram; when it cannot be
+/// reached the sidebar falls back to what the archive knows.
+#[server]
+pub async fn list_chats() -> Result<Vec<ChatSummary>, TelegramError> {
+    use crate::state::AppState;
+    use crate::telegram::get_chat_summaries;
+
+    let state = expect_context::<AppState>();
+
+    let fetched = state.telegram
+        .run(|client| {
+            let chats = state.chats.clone();
+            async move { get_chat_summaries(&client, &chats).await }
+        })
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("Listing chats from the archive only: {}", e);
+            Vec::new()
+        });
+
+    let mut summaries = Vec::with_capacity(state.chats.len());
+    for &chat_id in &state.chats {
+        let summary = match fetched.iter().find(|summary| summary.id == chat_id) {
+            Some(summary) => {
+                state.archive.store_chat_name(chat_id, &summary.name)?;
+                let mut summary = summary.clone();
+                if summary.last_message.is_none() {
+                    summary.last_message = state.archive.messages_before(chat_id, None, None, 1)?.pop();
+                }
+                summary
+            }
+            None => ChatSummary {
+                id: chat_id,
+                name: state.archive.chat_name(chat_id)?
+                    .unwrap_or_else(|| format!("Chat {}", chat_id)),
+                avatar_url: None,
+                unread_count: 0,
+                last_message: state.archive.messages_before(chat_id, None, None, 1)?.pop(),
+            },
+        };
+        summaries.push(summary);
+    }
+    Ok(summaries)
+}
+
+/// List of logged chats linking to `/chat/:id`, with `selected` highlighted.
+#[component]
+pub fn ChatSidebar(
+    chats: Resource<Result<Vec<ChatSummary>, TelegramError>>,
+    #[prop(into)] selected: Signal<Option<i64>>,
+) -> impl IntoView {
+    view! {
+        <nav class="chat-sidebar">
+            <Suspense fallback=move || view! { <div class="loading-indicator">"Loading chats..."</div> }>
+                {move || match chats.get() {
+                    None => view! { <div></div> }.into_any(),
+                    Some(Err(error)) => view! {
+                        <ErrorNotice error=error on_retry=move || chats.refetch() />
+                    }.into_any(),
+                    Some(Ok(chats)) => chats.into_iter().map(|chat| {
+                        let id = chat.id;
+                        view! { <ChatEntry chat=chat selected=Signal::derive(move || selected.get() == Some(id)) /> }
+                    }).collect::<Vec<_>>().into_any(),
+                }}
+            </Suspense>
+        </nav>
+    }
+}
+
+#[component]
+fn ChatEntry(chat: ChatSummary, #[prop(into)] selected: Signal<bool>) -> impl IntoView {
+    let initial = chat.name.chars().next().unwrap_or('?').to_uppercase().to_string();
+    let preview = chat.last_message.as_ref().map(message_preview);
+
+    view! {
+        <a
+            href=format!("/chat/{}", chat.id)
+            class="chat-entry"
+            class:selected=move || selected.get()
+        >
+            {match chat.avatar_url {
+                Some(url) => view! { <img class="chat-avatar" src=url alt="" /> }.into_any(),
+                None => view! { <div class="chat-avatar chat-avatar-placeholder">{initial}</div> }.into_any(),
+            }}
+            <div class="chat-entry-body">
+                <div class="chat-entry-title">
+                    <span class="chat-name">{chat.name}</span>
+                    {(chat.unread_count > 0).then(|| view! {
+                        <span class="unread-badge">{chat.unread_count}</span>
+                    })}
+                </div>
+                {preview.map(|preview| view! { <div class="chat-preview">{preview}</div> })}
+            </div>
+        </a>
+    }
+}
+
+/// One-line preview of `message` for the sidebar.
+fn message_preview(message: &ChatMessage) -> String {
+    let text = if message.text.is_empty() {
+        format!("[{}]", message.message_type)
+    } else {
+        message.text.lines().next().unwrap_or_default().to_string()
+    };
+    format!("{}: {}", message.sender, text)
+}
@@ -0,0 +1,23 @@
+use axum::extract::FromRef;
+use leptos::prelude::LeptosOptions;
+use crate::archive::Archive;
+use crate::live::LiveFeed;
+use crate::telegram::TelegramService;
+
+/// Shared server state, handed to axum handlers and provided as Leptos
+/// context to server functions.
+#[derive(Clone)]
+pub struct AppState {
+    pub leptos_options: LeptosOptions,
+    pub telegram: TelegramService,
+    pub live: LiveFeed,
+    pub archive: Archive,
+    /// Ids of the logged chats, in configuration order.
+    pub chats: Vec<i64>,
+}
+
+impl FromRef<AppState> for LeptosOptions {
+    fn from_ref(state: &AppState) -> Self {
+        state.leptos_options.clone()
+    }
+}
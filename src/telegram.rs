@@ -1,12 +1,23 @@
 #[cfg(feature = "ssr")]
-use grammers_client::{Client, Config};
+use grammers_client::client::messages::{AuthorizationError, InvocationError};
 #[cfg(feature = "ssr")]
-use grammers_session::Session;
+use grammers_client::{Client, Config, Update};
 #[cfg(feature = "ssr")]
-use crate::config::*;
+use grammers_session::{PackedChat, Session};
+#[cfg(feature = "ssr")]
+use crate::downloads::{sticker_file_name, PHOTO_DOWNLOADS, STICKER_DOWNLOADS};
+#[cfg(feature = "ssr")]
+use crate::peers::{resolve_chat, ResolveError, PEER_CACHE};
+#[cfg(feature = "ssr")]
+use crate::config::{config, ChatRef};
+#[cfg(feature = "ssr")]
+use crate::service::service_action;
+use crate::service::ServiceAction;
+use leptos::server_fn::codec::JsonEncoding;
+use leptos::server_fn::error::{FromServerFnError, ServerFnErrorErr};
 use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
-use strum::Display;
+use strum::{Display, EnumIter, EnumString};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ChatMessage {
@@ -19,18 +30,159 @@ pub struct ChatMessage {
     pub message_type: MessageType,
     pub media_info: Option<MediaInfo>,
     pub reply_to: Option<i32>,
+    /// Name of the original sender of a forwarded message.
     pub forwarded_from: Option<String>,
+    #[serde(default)]
+    pub forward: Option<ForwardInfo>,
+    /// The message replied to, filled in when messages are served rather
+    /// than archived.
+    #[serde(default)]
+    pub reply_preview: Option<ReplyPreview>,
+    /// What a service message records; its text describes it.
+    #[serde(default)]
+    pub service: Option<ServiceAction>,
+    #[serde(default)]
+    pub poll: Option<Poll>,
+    /// Forum topic the message was posted in, by the id of the message that
+    /// created the topic. `None` outside forums and in the General topic.
+    #[serde(default)]
+    pub topic_id: Option<i32>,
+    /// Reply thread the message is part of, by the id of the message that
+    /// started it.
+    #[serde(default)]
+    pub thread_id: Option<i32>,
+    #[serde(default)]
+    pub edited_at: Option<i64>,
+    #[serde(default)]
+    pub deleted_at: Option<i64>,
+    /// Earlier versions of the message, oldest first.
+    #[serde(default)]
+    pub revisions: Vec<MessageRevision>,
+    /// Read from a Telegram Desktop export rather than fetched; the first
+    /// fetched copy replaces it without recording a revision.
+    #[serde(default)]
+    pub imported: bool,
 }
 
+/// Where a forwarded message was first sent. Users who hide their account
+/// when forwarded have neither id; only their name is known.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ForwardInfo {
+    pub from_user_id: Option<i64>,
+    /// The channel, when forwarded from a channel post or an anonymous admin.
+    pub from_channel_id: Option<i64>,
+    /// When the original message was sent.
+    pub date: i64,
+    /// Id of the original post, when forwarded from a channel.
+    pub channel_post: Option<i32>,
+    /// Signature of the author of the original post.
+    pub post_author: Option<String>,
+}
+
+/// Enough of a replied-to message to show above the reply.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReplyPreview {
+    pub id: i32,
+    pub sender: String,
+    pub snippet: String,
+    pub deleted: bool,
+}
+
+/// Characters of the replied-to text shown in a `ReplyPreview`.
+const REPLY_SNIPPET_CHARS: usize = 100;
+
+impl ReplyPreview {
+    pub fn of(message: &ChatMessage) -> Self {
+        let text = message.text.split_whitespace().collect::<Vec<_>>().join(" ");
+        let mut snippet: String = text.chars().take(REPLY_SNIPPET_CHARS).collect();
+        if snippet.len() < text.len() {
+            snippet.push('…');
+        }
+        Self {
+            id: message.id,
+            sender: message.sender.clone(),
+            snippet,
+            deleted: message.deleted_at.is_some(),
+        }
+    }
+}
+
+/// A previous version of an edited message.
 #[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MessageRevision {
+    pub text: String,
+    pub formatted_text: Vec<TextEntity>,
+    /// When this version was written: the original send time or an earlier edit.
+    pub timestamp: i64,
+}
+
+impl ChatMessage {
+    /// Whether the visible content differs from `other`.
+    pub fn content_differs(&self, other: &ChatMessage) -> bool {
+        self.text != other.text || self.formatted_text != other.formatted_text
+    }
+
+    /// The forum topic the message is in. Messages of chats that are not
+    /// forums count as being in General.
+    pub fn topic(&self) -> i32 {
+        self.topic_id.unwrap_or(GENERAL_TOPIC_ID)
+    }
+
+    /// Who a forwarded message was first sent by, followed by the signature
+    /// of the post's author if it has one.
+    pub fn forward_source(&self) -> Option<String> {
+        let forward = self.forward.as_ref();
+        let name = match (&self.forwarded_from, forward) {
+            (Some(name), _) => name.clone(),
+            (None, Some(forward)) if forward.from_channel_id.is_some() => "a channel".to_string(),
+            (None, Some(_)) => "a user".to_string(),
+            (None, None) => return None,
+        };
+        Some(match forward.and_then(|forward| forward.post_author.as_deref()) {
+            Some(author) => format!("{} ({})", name, author),
+            None => name,
+        })
+    }
+
+    /// Whether the text contains a link, either written out or attached to
+    /// formatted text.
+    pub fn has_link(&self) -> bool {
+        self.formatted_text
+            .iter()
+            .any(|entity| matches!(entity.entity_type, EntityType::Link | EntityType::TextLink))
+            || self.text.contains("http://")
+            || self.text.contains("https://")
+    }
+
+    pub fn has_media(&self) -> bool {
+        self.media_info.is_some() || !matches!(self.message_type, MessageType::Text | MessageType::System)
+    }
+
+    /// Snapshot of the current content, for pushing onto `revisions`.
+    pub fn to_revision(&self) -> MessageRevision {
+        MessageRevision {
+            text: self.text.clone(),
+            formatted_text: self.formatted_text.clone(),
+            timestamp: self.edited_at.unwrap_or(self.timestamp),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct TextEntity {
     pub offset: usize,
     pub length: usize,
     pub entity_type: EntityType,
     pub url: Option<String>, // For links
+    /// Language of a `Pre` block, when the sender named one.
+    #[serde(default)]
+    pub language: Option<String>,
+    /// The user a `MentionName` points at.
+    #[serde(default)]
+    pub user_id: Option<i64>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum EntityType {
     Bold,
     Italic,
@@ -46,9 +198,19 @@ pub enum EntityType {
     Underline,
     Strikethrough,
     Spoiler,
+    /// A mention of a user without a username, linked by `user_id`.
+    MentionName,
+    Cashtag,
+    BankCard,
+    Blockquote,
+    /// A blockquote shown collapsed until the reader expands it.
+    ExpandableBlockquote,
+    /// A premium emoji drawn from the sticker document `document_id`; the
+    /// text it covers is the plain emoji shown where it cannot be drawn.
+    CustomEmoji { document_id: i64 },
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Display)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Display, EnumIter, EnumString)]
 #[strum(serialize_all = "snake_case")]
 pub enum MessageType {
     Text,
@@ -59,8 +221,14 @@ pub enum MessageType {
     Voice,
     Sticker,
     Location,
+    Venue,
+    LiveLocation,
     Contact,
     Poll,
+    Dice,
+    Game,
+    Invoice,
+    WebPage,
     System,
 }
 
@@ -75,19 +243,439 @@ impl MessageType {
             MessageType::Voice => "🎤",
             MessageType::Sticker => "😀",
             MessageType::Location => "📍",
+            MessageType::Venue => "🏛️",
+            MessageType::LiveLocation => "🛰️",
             MessageType::Contact => "👤",
             MessageType::Poll => "📊",
+            MessageType::Dice => "🎲",
+            MessageType::Game => "🎮",
+            MessageType::Invoice => "🧾",
+            MessageType::WebPage => "🔗",
             MessageType::System => "⚙️",
         }
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct MediaInfo {
     pub file_name: Option<String>,
     pub file_size: Option<u64>,
     pub mime_type: Option<String>,
     pub caption: Option<String>,
+    /// Pixel size of photos, when known.
+    #[serde(default)]
+    pub width: Option<u32>,
+    #[serde(default)]
+    pub height: Option<u32>,
+    /// Telegram id of documents, videos and audio, which are downloaded on
+    /// demand from `/media/{document_id}`.
+    #[serde(default)]
+    pub document_id: Option<i64>,
+    /// What media that is not a file shows: places, dice, games, invoices
+    /// and link previews.
+    #[serde(default)]
+    pub details: Option<MediaDetails>,
+}
+
+impl MediaInfo {
+    /// Media that is not a file, shown by `details`.
+    pub fn from_details(details: MediaDetails, caption: Option<String>) -> Self {
+        MediaInfo { caption, details: Some(details), ..MediaInfo::default() }
+    }
+
+    /// File name of a photo in the photo cache, if this is one.
+    pub fn cached_photo(&self) -> Option<&str> {
+        self.file_name.as_deref()
+            .filter(|name| name.starts_with("photo_") && !name.contains(['/', '\\']))
+    }
+
+    /// URL the full size photo is served under.
+    pub fn photo_url(&self) -> Option<String> {
+        self.cached_photo().map(|name| format!("/photos/{}", name))
+    }
+
+    /// URL the document is downloaded from when first requested.
+    pub fn media_url(&self) -> Option<String> {
+        self.document_id.map(|id| format!("/media/{}", id))
+    }
+
+    /// URL of the smaller copy of the photo shown inline.
+    pub fn thumbnail_url(&self) -> Option<String> {
+        self.cached_photo().map(|name| format!("/photos/{}", thumbnail_file_name(name)))
+    }
+}
+
+/// Media that is not a file, as Telegram describes it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum MediaDetails {
+    Location {
+        point: GeoPoint,
+    },
+    Venue {
+        point: Option<GeoPoint>,
+        title: String,
+        address: String,
+        /// Directory the venue comes from, like `foursquare`, with its id
+        /// there.
+        provider: Option<String>,
+        venue_id: Option<String>,
+    },
+    /// A location that is updated for `period` seconds after the message
+    /// was sent.
+    LiveLocation {
+        point: Option<GeoPoint>,
+        period: i32,
+        /// Direction of travel in degrees, when moving.
+        heading: Option<i32>,
+    },
+    Dice {
+        emoji: String,
+        value: i32,
+    },
+    Game {
+        title: String,
+        description: String,
+        short_name: String,
+    },
+    Invoice {
+        title: String,
+        description: String,
+        currency: String,
+        /// In the smallest units of `currency`.
+        total_amount: i64,
+        /// Sent by a bot in test mode, so not actually payable.
+        test: bool,
+    },
+    WebPage(WebPagePreview),
+}
+
+impl MediaDetails {
+    /// One line standing in for the media in plain text.
+    pub fn summary(&self) -> String {
+        match self {
+            MediaDetails::Location { point } => format!("Location {}", point),
+            MediaDetails::Venue { title, address, .. } if address.is_empty() => title.clone(),
+            MediaDetails::Venue { title, address, .. } => format!("{}, {}", title, address),
+            MediaDetails::LiveLocation { point: Some(point), .. } => format!("Live location {}", point),
+            MediaDetails::LiveLocation { point: None, .. } => "Live location".to_string(),
+            MediaDetails::Dice { emoji, value } => format!("{} {}", emoji, value),
+            MediaDetails::Game { title, .. } => format!("Game: {}", title),
+            MediaDetails::Invoice { title, currency, total_amount, .. } => {
+                format!("Invoice: {} ({})", title, format_amount(*total_amount, currency))
+            }
+            MediaDetails::WebPage(page) => page.title.clone().unwrap_or_else(|| page.url.clone()),
+        }
+    }
+}
+
+/// A point on the map, in degrees.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GeoPoint {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// How far off the point may be, in meters.
+    pub accuracy_radius: Option<i32>,
+}
+
+impl GeoPoint {
+    /// The point on OpenStreetMap.
+    pub fn map_url(&self) -> String {
+        format!(
+            "https://www.openstreetmap.org/?mlat={lat}&mlon={lon}#map=16/{lat}/{lon}",
+            lat = self.latitude,
+            lon = self.longitude,
+        )
+    }
+}
+
+impl std::fmt::Display for GeoPoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.5}, {:.5}", self.latitude, self.longitude)
+    }
+}
+
+/// The preview Telegram shows under a message with a link.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WebPagePreview {
+    pub url: String,
+    pub site_name: Option<String>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// Cached photo of the page, served like other photos.
+    pub image: Option<String>,
+}
+
+impl WebPagePreview {
+    /// A preview of `url` with nothing known about the page.
+    pub fn bare(url: String) -> Self {
+        WebPagePreview { url, site_name: None, title: None, description: None, image: None }
+    }
+}
+
+/// `total_amount` smallest units of `currency` as `12.50 EUR`. Currencies
+/// are taken to have two decimals, which all but a few do.
+pub fn format_amount(total_amount: i64, currency: &str) -> String {
+    format!("{}.{:02} {}", total_amount / 100, (total_amount % 100).abs(), currency)
+}
+
+/// A poll or quiz, with its results as last seen.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Poll {
+    /// Telegram's id of the poll, which updates to its results refer to.
+    pub id: i64,
+    pub question: String,
+    pub answers: Vec<PollAnswer>,
+    pub quiz: bool,
+    pub multiple_choice: bool,
+    /// Whether who voted for what stays hidden.
+    pub anonymous: bool,
+    pub closed: bool,
+    /// `None` while the results are hidden, until the logged-in user votes.
+    pub total_voters: Option<i32>,
+    /// What a quiz shows once answered to explain the correct answer.
+    pub solution: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PollAnswer {
+    pub text: String,
+    /// The bytes results use to name the answer.
+    pub option: Vec<u8>,
+    pub voters: Option<i32>,
+    /// The right answer of a quiz, which is only known after voting.
+    pub correct: bool,
+    /// Picked by the logged-in user.
+    pub chosen: bool,
+}
+
+impl Poll {
+    /// Share of the voters who picked `answer`, in whole percent.
+    pub fn percentage(&self, answer: &PollAnswer) -> Option<u32> {
+        let total = self.total_voters.filter(|&total| total > 0)?;
+        Some((answer.voters? as f64 * 100.0 / total as f64).round() as u32)
+    }
+
+    /// "Anonymous quiz", "Public poll, multiple answers" and the like.
+    pub fn kind_label(&self) -> String {
+        let mut label = format!(
+            "{} {}",
+            if self.anonymous { "Anonymous" } else { "Public" },
+            if self.quiz { "quiz" } else { "poll" },
+        );
+        if self.multiple_choice {
+            label.push_str(", multiple answers");
+        }
+        if self.closed {
+            label.push_str(", closed");
+        }
+        label
+    }
+
+    /// The answers as `Label — 12 votes (40%)` lines, for plain text.
+    pub fn result_lines(&self) -> Vec<String> {
+        self.answers
+            .iter()
+            .map(|answer| {
+                let mut line = answer.text.clone();
+                if answer.correct {
+                    line.push_str(" ✓");
+                }
+                if let Some(voters) = answer.voters {
+                    line.push_str(&format!(" — {}", votes(voters)));
+                }
+                if let Some(percentage) = self.percentage(answer) {
+                    line.push_str(&format!(" ({}%)", percentage));
+                }
+                line
+            })
+            .collect()
+    }
+}
+
+/// "1 vote" or "12 votes".
+pub fn votes(count: i32) -> String {
+    format!("{} vote{}", count, if count == 1 { "" } else { "s" })
+}
+
+#[cfg(feature = "ssr")]
+impl Poll {
+    pub fn from_raw(poll: grammers_tl_types::enums::Poll, results: grammers_tl_types::enums::PollResults) -> Self {
+        use grammers_tl_types::enums::{PollAnswer as RawAnswer, TextWithEntities};
+
+        let grammers_tl_types::enums::Poll::Poll(poll) = poll;
+        let TextWithEntities::Entities(question) = poll.question;
+        let mut converted = Poll {
+            id: poll.id,
+            question: question.text,
+            answers: poll
+                .answers
+                .into_iter()
+                .map(|RawAnswer::Answer(answer)| {
+                    let TextWithEntities::Entities(text) = answer.text;
+                    PollAnswer { text: text.text, option: answer.option, voters: None, correct: false, chosen: false }
+                })
+                .collect(),
+            quiz: poll.quiz,
+            multiple_choice: poll.multiple_choice,
+            anonymous: !poll.public_voters,
+            closed: poll.closed,
+            total_voters: None,
+            solution: None,
+        };
+        converted.apply_results(results);
+        converted
+    }
+
+    /// Takes in the newer `results`. Telegram leaves out what did not
+    /// change, and whether the logged-in user voted when the results are
+    /// not meant for them alone.
+    pub fn apply_results(&mut self, results: grammers_tl_types::enums::PollResults) {
+        use grammers_tl_types::enums::PollAnswerVoters;
+
+        let grammers_tl_types::enums::PollResults::Results(results) = results;
+        for PollAnswerVoters::Voters(voters) in results.results.into_iter().flatten() {
+            let Some(answer) = self.answers.iter_mut().find(|answer| answer.option == voters.option) else {
+                continue;
+            };
+            answer.voters = Some(voters.voters);
+            if !results.min {
+                answer.chosen = voters.chosen;
+                answer.correct = voters.correct;
+            }
+        }
+        self.total_voters = results.total_voters.or(self.total_voters);
+        self.solution = results.solution.or(self.solution.take());
+    }
+}
+
+/// Name under which the photo with id `photo_id` is cached.
+pub fn photo_file_name(photo_id: i64) -> String {
+    format!("photo_{}.jpg", photo_id)
+}
+
+/// Name under which the thumbnail of the cached photo `file_name` is kept.
+pub fn thumbnail_file_name(file_name: &str) -> String {
+    match file_name.rsplit_once('.') {
+        Some((stem, extension)) => format!("{}_thumb.{}", stem, extension),
+        None => format!("{}_thumb", file_name),
+    }
+}
+
+/// Id of the General topic of a forum, which holds every message posted
+/// outside the other topics.
+pub const GENERAL_TOPIC_ID: i32 = 1;
+
+/// A topic of a forum supergroup.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ForumTopic {
+    /// Id of the message that created the topic, or `GENERAL_TOPIC_ID`.
+    pub id: i32,
+    pub title: String,
+    /// RGB color of the topic's icon.
+    pub icon_color: i32,
+    pub closed: bool,
+    pub pinned: bool,
+    pub unread_count: i32,
+}
+
+/// Sidebar entry for one logged chat.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChatSummary {
+    pub id: i64,
+    pub name: String,
+    pub avatar_url: Option<String>,
+    pub unread_count: i32,
+    pub last_message: Option<ChatMessage>,
+}
+
+/// Why a Telegram operation failed, in a form that survives the trip from a
+/// server function to the browser so the UI can suggest a way out.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TelegramError {
+    /// There is no logged-in session; `cli-auth` has to be run.
+    NotAuthorized,
+    /// No chat exists under the configured id, username or link.
+    ChatNotFound { chat: String },
+    /// The chat exists but the account is not a member or was banned.
+    NoAccess { chat: String },
+    /// Telegram is rate limiting; retry after this many seconds.
+    FloodWait { seconds: u32 },
+    /// Telegram could not be reached.
+    Network(String),
+    MediaDownloadFailed { media: String, reason: String },
+    /// Telegram rejected the request for another reason.
+    Rpc(String),
+    /// The failure happened on our side, e.g. in the archive or while
+    /// calling the server function.
+    Server(String),
+}
+
+impl std::fmt::Display for TelegramError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TelegramError::NotAuthorized => write!(f, "Telegram session is not authorized"),
+            TelegramError::ChatNotFound { chat } => write!(f, "Chat {} not found", chat),
+            TelegramError::NoAccess { chat } => write!(f, "No access to chat {}", chat),
+            TelegramError::FloodWait { seconds } => write!(f, "Rate limited by Telegram for {} seconds", seconds),
+            TelegramError::Network(e) => write!(f, "Cannot reach Telegram: {}", e),
+            TelegramError::MediaDownloadFailed { media, reason } => write!(f, "Failed to download {}: {}", media, reason),
+            TelegramError::Rpc(e) => write!(f, "Telegram error: {}", e),
+            TelegramError::Server(e) => write!(f, "Server error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TelegramError {}
+
+impl FromServerFnError for TelegramError {
+    type Encoder = JsonEncoding;
+
+    fn from_server_fn_error(value: ServerFnErrorErr) -> Self {
+        TelegramError::Server(value.to_string())
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl From<InvocationError> for TelegramError {
+    fn from(error: InvocationError) -> Self {
+        match error {
+            InvocationError::Rpc(rpc) if rpc.name.starts_with("FLOOD") && rpc.name.ends_with("WAIT") => {
+                TelegramError::FloodWait { seconds: rpc.value.unwrap_or(0) }
+            }
+            InvocationError::Rpc(rpc) if rpc.code == 401 => TelegramError::NotAuthorized,
+            InvocationError::Rpc(rpc) => TelegramError::Rpc(rpc.to_string()),
+            InvocationError::Dropped | InvocationError::Read(_) => TelegramError::Network(error.to_string()),
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl From<AuthorizationError> for TelegramError {
+    fn from(error: AuthorizationError) -> Self {
+        match error {
+            AuthorizationError::Invoke(error) => error.into(),
+            AuthorizationError::Gen(error) => TelegramError::Network(error.to_string()),
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl From<ResolveError> for TelegramError {
+    fn from(error: ResolveError) -> Self {
+        match error {
+            ResolveError::NotFound(chat) => TelegramError::ChatNotFound { chat: chat.to_string() },
+            ResolveError::NoAccess(chat) => TelegramError::NoAccess { chat: chat.to_string() },
+            ResolveError::Telegram(error) => error.into(),
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl From<rusqlite::Error> for TelegramError {
+    fn from(error: rusqlite::Error) -> Self {
+        TelegramError::Server(format!("Archive error: {}", error))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -116,80 +704,348 @@ impl ChatHistory {
     }
 }
 
+/// Connection status of the shared Telegram client, as reported by `/health`.
 #[cfg(feature = "ssr")]
-pub async fn create_telegram_client() -> Result<Client, Box<dyn std::error::Error + Send + Sync>> {
-    let session = Session::load_file_or_create("session")?;
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ClientHealth {
+    pub connected: bool,
+    pub last_connected_at: Option<i64>,
+    pub last_success_at: Option<i64>,
+    pub last_error: Option<String>,
+    pub consecutive_failures: u32,
+    pub reconnects: u32,
+}
+
+/// Long-lived Telegram client owned by the server.
+///
+/// The connection is opened lazily on first use and reused by every request.
+/// When a request fails because the connection dropped, the client is thrown
+/// away and the request is retried once on a fresh connection.
+#[cfg(feature = "ssr")]
+#[derive(Clone, Default)]
+pub struct TelegramService {
+    client: std::sync::Arc<tokio::sync::Mutex<Option<Client>>>,
+    health: std::sync::Arc<std::sync::RwLock<ClientHealth>>,
+}
+
+#[cfg(feature = "ssr")]
+impl TelegramService {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the shared client, connecting first if there is none.
+    pub async fn client(&self) -> Result<Client, TelegramError> {
+        let mut slot = self.client.lock().await;
+        if let Some(client) = slot.as_ref() {
+            return Ok(client.clone());
+        }
+
+        match create_telegram_client().await {
+            Ok(client) => {
+                let now = chrono::Utc::now().timestamp();
+                let mut health = self.health.write().unwrap();
+                if health.last_connected_at.is_some() {
+                    health.reconnects += 1;
+                }
+                health.connected = true;
+                health.last_connected_at = Some(now);
+                *slot = Some(client.clone());
+                Ok(client)
+            }
+            Err(e) => {
+                self.record_failure(&e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Runs `op` against the shared client, reconnecting once if the
+    /// connection turns out to be dead.
+    pub async fn run<T, F, Fut>(&self, op: F) -> Result<T, TelegramError>
+    where
+        F: Fn(Client) -> Fut,
+        Fut: std::future::Future<Output = Result<T, TelegramError>>,
+    {
+        let client = self.client().await?;
+        let result = match op(client).await {
+            Err(e @ TelegramError::Network(_)) => {
+                eprintln!("Telegram connection lost, reconnecting: {}", e);
+                self.disconnect().await;
+                let client = self.client().await?;
+                op(client).await
+            }
+            result => result,
+        };
+
+        match &result {
+            Ok(_) => self.record_success(),
+            Err(e) => self.record_failure(e),
+        }
+        result
+    }
+
+    /// Waits for the next update on the shared connection.
+    ///
+    /// A failed read drops the connection, so calling this again reconnects.
+    pub async fn next_update(&self) -> Result<Option<Update>, TelegramError> {
+        let client = self.client().await?;
+        match client.next_update().await {
+            Ok(update) => {
+                if update.is_none() {
+                    self.disconnect().await;
+                }
+                Ok(update)
+            }
+            Err(e) => {
+                let e = TelegramError::from(e);
+                self.record_failure(&e);
+                self.disconnect().await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Writes the login and update state of the current connection back to
+    /// the session file.
+    pub async fn save_session(&self) -> Result<(), TelegramError> {
+        let Some(client) = self.client.lock().await.clone() else {
+            return Ok(());
+        };
+        let path = &config().session_path;
+        client.session().save_to_file(path).map_err(|e| {
+            TelegramError::Server(format!("Cannot save session {}: {}", path.display(), e))
+        })
+    }
+
+    /// Drops the current connection so the next call reconnects.
+    pub async fn disconnect(&self) {
+        self.client.lock().await.take();
+        self.health.write().unwrap().connected = false;
+    }
+
+    pub fn health(&self) -> ClientHealth {
+        self.health.read().unwrap().clone()
+    }
+
+    fn record_success(&self) {
+        let mut health = self.health.write().unwrap();
+        health.last_success_at = Some(chrono::Utc::now().timestamp());
+        health.consecutive_failures = 0;
+    }
+
+    fn record_failure(&self, error: &TelegramError) {
+        let mut health = self.health.write().unwrap();
+        health.last_error = Some(error.to_string());
+        health.consecutive_failures += 1;
+    }
+}
+
+#[cfg(feature = "ssr")]
+pub async fn create_telegram_client() -> Result<Client, TelegramError> {
+    let settings = config();
+    let session = Session::load_file_or_create(&settings.session_path).map_err(|e| {
+        TelegramError::Server(format!("Cannot open session {}: {}", settings.session_path.display(), e))
+    })?;
     
     let config = Config {
         session,
-        api_id: *API_ID,
-        api_hash: API_HASH.clone(),
+        api_id: settings.api_id,
+        api_hash: settings.api_hash.clone(),
         params: Default::default(),
     };
 
     let client = Client::connect(config).await?;
     
     if !client.is_authorized().await? {
-        return Err("Telegram client not authorized. Please run the CLI tool first to authenticate.".into());
+        return Err(TelegramError::NotAuthorized);
     }
     
     Ok(client)
 }
 
+/// Resolves `chat_id` to a packed chat usable in requests, going through the
+/// peer cache so the dialog list is only walked once per chat.
 #[cfg(feature = "ssr")]
-pub async fn get_chat_history(client: &Client, chat_id: i64, limit: i32) -> Result<Vec<ChatMessage>, Box<dyn std::error::Error + Send + Sync>> {
-    // First, let's try to get all dialogs to find our chat
-    let mut dialogs = client.iter_dialogs();
-    let mut target_chat = None;
-    let mut found_ids = Vec::new();
-    
-    while let Some(dialog) = dialogs.next().await? {
-        let dialog_chat = dialog.chat();
-        let dialog_id = dialog_chat.id();
-        found_ids.push(dialog_id);
-        
-        // Debug logging
-        eprintln!("Found chat: ID={}, Name={}", dialog_id, dialog_chat.name());
-        
-        // Check if this is our target chat
-        if dialog_id == chat_id {
-            target_chat = Some(dialog_chat.clone());
+pub async fn find_chat(client: &Client, chat_id: i64) -> Result<PackedChat, TelegramError> {
+    Ok(resolve_chat(client, &ChatRef::Id(chat_id)).await?)
+}
+
+/// Builds sidebar entries for `chat_ids` from the chats already in the peer
+/// cache, with one request for their dialogs. Chats that are not cached yet
+/// are left out.
+#[cfg(feature = "ssr")]
+pub async fn get_chat_summaries(client: &Client, chat_ids: &[i64]) -> Result<Vec<ChatSummary>, TelegramError> {
+    use grammers_client::types::ChatMap;
+    use grammers_tl_types as tl;
+
+    let peers: Vec<tl::enums::InputDialogPeer> = chat_ids
+        .iter()
+        .filter_map(|&chat_id| PEER_CACHE.get(&ChatRef::Id(chat_id)))
+        .map(|packed| tl::types::InputDialogPeer { peer: packed.to_input_peer() }.into())
+        .collect();
+    if peers.is_empty() {
+        return Ok(Vec::new());
+    }
+    let tl::enums::messages::PeerDialogs::Dialogs(response) =
+        client.invoke(&tl::functions::messages::GetPeerDialogs { peers }).await?;
+    let chats = ChatMap::new(response.users, response.chats);
+
+    let mut summaries = Vec::new();
+    for dialog in response.dialogs {
+        let tl::enums::Dialog::Dialog(dialog) = dialog else {
+            continue;
+        };
+        let Some(chat) = chats.get(&dialog.peer) else {
+            continue;
+        };
+
+        let avatar_url = match download_avatar(client, chat).await {
+            Ok(url) => url,
+            Err(e) => {
+                eprintln!("Failed to download avatar of chat {}: {}", chat.id(), e);
+                None
+            }
+        };
+        // The dialogs only carry raw messages, so the newest one is fetched
+        // again for grammers to read
+        let last_message = match client.get_messages_by_id(chat.pack(), &[dialog.top_message]).await {
+            Ok(mut messages) => messages.pop().flatten().map(|message| convert_message(client, &message, chat.id())),
+            Err(e) => {
+                eprintln!("Failed to fetch the last message of chat {}: {}", chat.id(), e);
+                None
+            }
+        };
+
+        summaries.push(ChatSummary {
+            id: chat.id(),
+            name: chat.name().to_string(),
+            avatar_url,
+            unread_count: dialog.unread_count,
+            last_message,
+        });
+    }
+    Ok(summaries)
+}
+
+/// Topics fetched per forum topics request, the most Telegram returns.
+#[cfg(feature = "ssr")]
+const FORUM_TOPICS_PAGE: i32 = 100;
+
+/// Lists the topics of `chat_id` in the order Telegram shows them: pinned
+/// topics first, then by latest activity. Chats that are not forums have
+/// none.
+#[cfg(feature = "ssr")]
+pub async fn get_forum_topics(client: &Client, chat_id: i64) -> Result<Vec<ForumTopic>, TelegramError> {
+    use grammers_tl_types as tl;
+
+    let chat = find_chat(client, chat_id).await?;
+    let Some(channel) = chat.try_to_input_channel() else {
+        return Ok(Vec::new());
+    };
+
+    let mut topics = Vec::new();
+    let (mut offset_date, mut offset_id, mut offset_topic) = (0, 0, 0);
+    loop {
+        let request = tl::functions::channels::GetForumTopics {
+            channel: channel.clone(),
+            q: None,
+            offset_date,
+            offset_id,
+            offset_topic,
+            limit: FORUM_TOPICS_PAGE,
+        };
+        let tl::enums::messages::ForumTopics::Topics(page) = match client.invoke(&request).await {
+            Ok(page) => page,
+            Err(InvocationError::Rpc(rpc)) if rpc.name == "CHANNEL_FORUM_MISSING" => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut last = None;
+        for topic in page.topics {
+            // Deleted topics only have an id left
+            let tl::enums::ForumTopic::Topic(topic) = topic else {
+                continue;
+            };
+            topics.push(ForumTopic {
+                id: topic.id,
+                title: topic.title,
+                icon_color: topic.icon_color,
+                closed: topic.closed,
+                pinned: topic.pinned,
+                unread_count: topic.unread_count,
+            });
+            last = Some((topic.id, topic.top_message));
+        }
+
+        // The next page continues after the latest message of the last topic
+        let Some((last_id, last_message)) = last else {
+            break;
+        };
+        if topics.len() as i32 >= page.count {
             break;
         }
+        offset_topic = last_id;
+        offset_id = last_message;
+        offset_date = page
+            .messages
+            .iter()
+            .find_map(|message| match message {
+                tl::enums::Message::Message(message) if message.id == last_message => Some(message.date),
+                tl::enums::Message::Service(message) if message.id == last_message => Some(message.date),
+                _ => None,
+            })
+            .unwrap_or_default();
     }
-    
-    let chat = match target_chat {
-        Some(chat) => chat,
-        None => {
-            eprintln!("Chat with ID {} not found. Available chat IDs: {:?}", chat_id, found_ids);
-            return Err(format!("Chat with ID {} not found in dialogs", chat_id).into());
-        }
+    Ok(topics)
+}
+
+/// Downloads the small profile photo of `chat` into the avatar cache unless
+/// it is already there, returning the URL it is served under. Files are
+/// named after the photo, so a new photo is fetched when the chat changes it.
+#[cfg(feature = "ssr")]
+async fn download_avatar(client: &Client, chat: &grammers_client::types::Chat) -> Result<Option<String>, TelegramError> {
+    use grammers_client::types::Downloadable;
+
+    let Some(photo) = chat.photo_downloadable(false) else {
+        return Ok(None);
     };
-    
-    eprintln!("Found chat, attempting to get messages from: {}", chat.name());
-    
+    let photo_id = match &photo {
+        Downloadable::UserProfilePhoto(profile) => profile.photo.photo_id,
+        Downloadable::ChatPhoto(chat_photo) => chat_photo.photo.photo_id,
+        _ => return Ok(None),
+    };
+    let file_name = format!("avatar_{}_{}.jpg", chat.id(), photo_id);
+    let avatar_dir = config().avatar_dir();
+    let file_path = avatar_dir.join(&file_name);
+    if !file_path.exists() {
+        let failed = |e: std::io::Error| TelegramError::MediaDownloadFailed {
+            media: format!("avatar of chat {}", chat.id()),
+            reason: e.to_string(),
+        };
+        tokio::fs::create_dir_all(&avatar_dir).await.map_err(failed)?;
+        client.download_media(&photo, &file_path).await.map_err(failed)?;
+    }
+    Ok(Some(format!("/avatars/{}", file_name)))
+}
+
+/// Fetches up to `limit` messages, oldest first. With `offset_id` set, only
+/// messages older than that id are returned.
+#[cfg(feature = "ssr")]
+pub async fn get_chat_history(client: &Client, chat_id: i64, limit: i32, offset_id: Option<i32>) -> Result<Vec<ChatMessage>, TelegramError> {
+    let chat = find_chat(client, chat_id).await?;
+
     let mut messages = Vec::new();
     let mut iter = client.iter_messages(chat);
+    if let Some(offset_id) = offset_id {
+        iter = iter.offset_id(offset_id);
+    }
     
     for i in 0..limit {
         if let Some(message) = iter.next().await? {
-            let (message_type, media_info, text) = classify_message(&client, &message);
-            let formatted_text = extract_text_entities(&message);
-            
-            eprintln!("Message {}: ID={}, Type={:?}, Text={}", i + 1, message.id(), message_type, text);
-            
-            messages.push(ChatMessage {
-                id: message.id(),
-                text,
-                formatted_text,
-                timestamp: message.date().timestamp(),
-                sender: message.sender().map(|s| s.name().to_string()).unwrap_or_else(|| "Unknown".to_string()),
-                chat_id,
-                message_type,
-                media_info,
-                reply_to: message.reply_to_message_id(),
-                forwarded_from: None, // TODO: Extract forward info properly
-            });
+            let message = convert_message(client, &message, chat_id);
+            eprintln!("Message {}: ID={}, Type={:?}, Text={}", i + 1, message.id, message.message_type, message.text);
+            messages.push(message);
         } else {
             eprintln!("No more messages after {} messages", i);
             break;
@@ -197,12 +1053,341 @@ pub async fn get_chat_history(client: &Client, chat_id: i64, limit: i32) -> Resu
     }
     
     eprintln!("Retrieved {} messages total", messages.len());
+    if let Err(e) = fetch_raw_details(client, chat, &mut messages).await {
+        eprintln!("Failed to fetch the details of messages: {}", e);
+    }
     
     // Reverse to show oldest first
     messages.reverse();
     Ok(messages)
 }
 
+/// Fetches up to `limit` messages of forum topic `topic_id`, oldest first,
+/// like `get_chat_history`. General has no thread of its own; its messages
+/// only come with the rest of the chat's history.
+#[cfg(feature = "ssr")]
+pub async fn get_topic_history(
+    client: &Client,
+    chat_id: i64,
+    topic_id: i32,
+    limit: i32,
+    offset_id: Option<i32>,
+) -> Result<Vec<ChatMessage>, TelegramError> {
+    use grammers_tl_types as tl;
+    use tl::enums::messages::Messages;
+
+    let chat = find_chat(client, chat_id).await?;
+    let response = client
+        .invoke(&tl::functions::messages::GetReplies {
+            peer: chat.to_input_peer(),
+            msg_id: topic_id,
+            offset_id: offset_id.unwrap_or(0),
+            offset_date: 0,
+            add_offset: 0,
+            limit,
+            max_id: 0,
+            min_id: 0,
+            hash: 0,
+        })
+        .await?;
+    let raw = match response {
+        Messages::Messages(response) => response.messages,
+        Messages::Slice(response) => response.messages,
+        Messages::ChannelMessages(response) => response.messages,
+        Messages::NotModified(_) => Vec::new(),
+    };
+
+    // Only the client can turn raw messages into the ones `convert_message`
+    // takes, so they are fetched again by id
+    let ids: Vec<i32> = raw
+        .iter()
+        .filter_map(|message| match message {
+            tl::enums::Message::Message(message) => Some(message.id),
+            tl::enums::Message::Service(message) => Some(message.id),
+            tl::enums::Message::Empty(_) => None,
+        })
+        .collect();
+    if ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut messages: Vec<ChatMessage> = client
+        .get_messages_by_id(chat, &ids)
+        .await?
+        .into_iter()
+        .flatten()
+        .map(|message| convert_message(client, &message, chat_id))
+        .collect();
+    if let Err(e) = fetch_raw_details(client, chat, &mut messages).await {
+        eprintln!("Failed to fetch the details of messages: {}", e);
+    }
+    messages.sort_by_key(|message| message.id);
+    Ok(messages)
+}
+
+/// Converts a grammers message into the `ChatMessage` shape the UI renders.
+#[cfg(feature = "ssr")]
+pub fn convert_message(client: &Client, message: &grammers_client::types::Message, chat_id: i64) -> ChatMessage {
+    let (message_type, media_info, text) = classify_message(client, message);
+    let formatted_text = extract_text_entities(message);
+    let custom_emoji: Vec<i64> = formatted_text
+        .iter()
+        .filter_map(|entity| match entity.entity_type {
+            EntityType::CustomEmoji { document_id } => Some(document_id),
+            _ => None,
+        })
+        .collect();
+    if !custom_emoji.is_empty() {
+        STICKER_DOWNLOADS.enqueue_custom_emoji(client, custom_emoji);
+    }
+
+    let (reply_to, topic_id, thread_id) = reply_context(message);
+    let (forward, forwarded_from) = forward_origin(message);
+    // Channel posts and some service messages have no sender but the chat
+    let sender = message.sender().unwrap_or_else(|| message.chat()).name().to_string();
+    let service = service_action(message);
+    let text = match &service {
+        Some(action) => action.describe(&sender),
+        None => text,
+    };
+
+    ChatMessage {
+        id: message.id(),
+        text,
+        formatted_text,
+        timestamp: message.date().timestamp(),
+        sender,
+        chat_id,
+        message_type,
+        media_info,
+        reply_to,
+        forwarded_from,
+        forward,
+        reply_preview: None,
+        service,
+        poll: None,
+        topic_id,
+        thread_id,
+        edited_at: message.edit_date().map(|date| date.timestamp()),
+        deleted_at: None,
+        revisions: Vec::new(),
+        imported: false,
+    }
+}
+
+/// The message replied to, the forum topic and the reply thread, from the
+/// reply header of `message`.
+#[cfg(feature = "ssr")]
+fn reply_context(message: &grammers_client::types::Message) -> (Option<i32>, Option<i32>, Option<i32>) {
+    use grammers_tl_types::enums::MessageReplyHeader;
+
+    // The message creating a topic is its first message
+    if let Some(grammers_tl_types::enums::MessageAction::TopicCreate(_)) = message.action() {
+        return (None, Some(message.id()), Some(message.id()));
+    }
+    let Some(MessageReplyHeader::Header(header)) = message.reply_header() else {
+        return (None, None, None);
+    };
+    let thread_id = header.reply_to_top_id.or(header.reply_to_msg_id);
+    let topic_id = if header.forum_topic { thread_id } else { None };
+    let reply_to = header
+        .reply_to_msg_id
+        // Posts in a topic point at the message that created it without
+        // replying to it
+        .filter(|_| !header.forum_topic || header.reply_to_top_id.is_some())
+        // Replies to messages of other chats cannot be looked up in this one
+        .filter(|_| header.reply_to_peer_id.is_none());
+    (reply_to, topic_id, thread_id)
+}
+
+/// Where `message` was forwarded from, and the original sender's name when
+/// the header carries it. Other names are looked up by
+/// `fetch_raw_details`.
+#[cfg(feature = "ssr")]
+fn forward_origin(message: &grammers_client::types::Message) -> (Option<ForwardInfo>, Option<String>) {
+    use grammers_tl_types::enums::{MessageFwdHeader, Peer};
+
+    let Some(MessageFwdHeader::Header(header)) = message.forward_header() else {
+        return (None, None);
+    };
+    let (from_user_id, from_channel_id) = match &header.from_id {
+        Some(Peer::User(user)) => (Some(user.user_id), None),
+        Some(Peer::Channel(channel)) => (None, Some(channel.channel_id)),
+        Some(Peer::Chat(_)) | None => (None, None),
+    };
+    let forward = ForwardInfo {
+        from_user_id,
+        from_channel_id,
+        date: header.date.into(),
+        channel_post: header.channel_post,
+        post_author: header.post_author,
+    };
+    (Some(forward), header.from_name)
+}
+
+/// Fills in what grammers does not tell about `messages` of `chat`: the
+/// names they only know by id, of the original sender of forwarded messages
+/// and of the users service messages are about, and the polls, games,
+/// invoices and link previews they carry. Those messages are fetched again as Telegram
+/// sends them, in one request.
+#[cfg(feature = "ssr")]
+pub async fn fetch_raw_details(client: &Client, chat: PackedChat, messages: &mut [ChatMessage]) -> Result<(), TelegramError> {
+    use grammers_client::types::ChatMap;
+    use grammers_tl_types as tl;
+    use std::collections::HashMap;
+    use tl::enums::messages::Messages;
+
+    let unnamed_forward = |message: &ChatMessage| {
+        message.forwarded_from.is_none()
+            && message.forward.as_ref().is_some_and(|forward| forward.from_user_id.or(forward.from_channel_id).is_some())
+    };
+    let unnamed_members = |message: &mut ChatMessage| {
+        message.service.as_mut().is_some_and(|action| action.members_mut().iter().any(|member| member.name.is_none()))
+    };
+    // Games and invoices are not media to grammers, so they look like
+    // service messages without an action
+    let hidden_media = |message: &ChatMessage| match message.message_type {
+        MessageType::WebPage => message.media_info.as_ref().is_some_and(|media| media.details.is_none()),
+        MessageType::System => message.service.is_none(),
+        MessageType::Poll => message.poll.is_none(),
+        _ => false,
+    };
+    let unnamed: Vec<i32> = messages
+        .iter_mut()
+        .filter_map(|message| {
+            (unnamed_forward(message) || unnamed_members(message) || hidden_media(message)).then_some(message.id)
+        })
+        .collect();
+    if unnamed.is_empty() {
+        return Ok(());
+    }
+
+    let id: Vec<tl::enums::InputMessage> = unnamed
+        .iter()
+        .map(|&id| tl::types::InputMessageId { id }.into())
+        .collect();
+    let response = match chat.try_to_input_channel() {
+        Some(channel) => client.invoke(&tl::functions::channels::GetMessages { channel, id }).await?,
+        None => client.invoke(&tl::functions::messages::GetMessages { id }).await?,
+    };
+    let (raw_messages, users, chats) = match response {
+        Messages::Messages(response) => (response.messages, response.users, response.chats),
+        Messages::Slice(response) => (response.messages, response.users, response.chats),
+        Messages::ChannelMessages(response) => (response.messages, response.users, response.chats),
+        Messages::NotModified(_) => return Ok(()),
+    };
+    let mut raw_media: HashMap<i32, tl::enums::MessageMedia> = raw_messages
+        .into_iter()
+        .filter_map(|message| match message {
+            tl::enums::Message::Message(message) => Some((message.id, message.media?)),
+            _ => None,
+        })
+        .collect();
+    let names = ChatMap::new(users, chats);
+    let user_name = |user_id: i64| {
+        names.get(&tl::types::PeerUser { user_id }.into()).map(|user| user.name().to_string())
+    };
+
+    for message in messages.iter_mut().filter(|message| unnamed.contains(&message.id)) {
+        if let (None, Some(forward)) = (&message.forwarded_from, &message.forward) {
+            message.forwarded_from = match (forward.from_user_id, forward.from_channel_id) {
+                (Some(user_id), _) => user_name(user_id),
+                (None, Some(channel_id)) => names
+                    .get(&tl::types::PeerChannel { channel_id }.into())
+                    .map(|channel| channel.name().to_string()),
+                (None, None) => None,
+            };
+        }
+        if let Some(action) = &mut message.service {
+            for member in action.members_mut() {
+                member.name = member.id.and_then(user_name);
+            }
+            message.text = action.describe(&message.sender);
+        }
+        if hidden_media(message) {
+            let media = raw_media.remove(&message.id);
+            if let Some(tl::enums::MessageMedia::Poll(media)) = media {
+                message.poll = Some(Poll::from_raw(media.poll, media.results));
+                continue;
+            }
+            let Some((message_type, details, placeholder)) = media.and_then(|media| raw_media_details(client, media))
+            else {
+                continue;
+            };
+            let caption = message.media_info.as_ref().and_then(|media| media.caption.clone());
+            if caption.is_none() {
+                message.text = placeholder;
+            }
+            message.message_type = message_type;
+            message.media_info = Some(MediaInfo::from_details(details, caption));
+        }
+    }
+    Ok(())
+}
+
+/// The media grammers has no type for, or does not let us look into, with
+/// the message type and text to show for it.
+#[cfg(feature = "ssr")]
+fn raw_media_details(client: &Client, media: grammers_tl_types::enums::MessageMedia) -> Option<(MessageType, MediaDetails, String)> {
+    use grammers_tl_types::enums::{Game, MessageMedia, WebPage};
+
+    match media {
+        MessageMedia::WebPage(media) => {
+            let preview = match media.webpage {
+                WebPage::Page(page) => {
+                    let image = page.photo.map(|photo| grammers_client::types::Photo::from_raw(photo, client.clone()));
+                    let image_name = image.as_ref().map(|photo| photo_file_name(photo.id()));
+                    if let Some(photo) = image {
+                        PHOTO_DOWNLOADS.enqueue_photo(client, photo);
+                    }
+                    WebPagePreview {
+                        url: page.url,
+                        site_name: page.site_name,
+                        title: page.title,
+                        description: page.description,
+                        image: image_name,
+                    }
+                }
+                // Previews Telegram has not made yet, or could not make
+                WebPage::Pending(page) => WebPagePreview::bare(page.url?),
+                WebPage::Empty(page) => WebPagePreview::bare(page.url?),
+                WebPage::NotModified(_) => return None,
+            };
+            Some((MessageType::WebPage, MediaDetails::WebPage(preview), String::new()))
+        }
+        MessageMedia::Game(media) => {
+            let Game::Game(game) = media.game;
+            let placeholder = format!("[Game: {}]", game.title);
+            let details = MediaDetails::Game {
+                title: game.title,
+                description: game.description,
+                short_name: game.short_name,
+            };
+            Some((MessageType::Game, details, placeholder))
+        }
+        MessageMedia::Invoice(invoice) => {
+            let placeholder = format!("[Invoice: {}]", invoice.title);
+            let details = MediaDetails::Invoice {
+                title: invoice.title,
+                description: invoice.description,
+                currency: invoice.currency,
+                total_amount: invoice.total_amount,
+                test: invoice.test,
+            };
+            Some((MessageType::Invoice, details, placeholder))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(feature = "ssr")]
+fn geo_point(geo: &grammers_client::types::media::Geo) -> GeoPoint {
+    GeoPoint {
+        latitude: geo.latitue(),
+        longitude: geo.longitude(),
+        accuracy_radius: geo.accuracy_radius(),
+    }
+}
+
 #[cfg(feature = "ssr")]
 fn classify_message(client: &grammers_client::Client, message: &grammers_client::types::Message) -> (MessageType, Option<MediaInfo>, String) {
     use grammers_client::types::Media;
@@ -210,39 +1395,63 @@ fn classify_message(client: &grammers_client::Client, message: &grammers_client:
     // Check if message has media
     if let Some(media) = message.media() {
         match media {
-            Media::Photo(_photo) => {
+            Media::Photo(photo) => {
+                use grammers_client::types::photo_sizes::VecExt;
+
                 let caption = message.text().to_string();
+                let thumbs = photo.thumbs();
+                let best = thumbs.largest();
+                let dimensions = best.and_then(photo_size_dimensions);
                 let media_info = MediaInfo {
-                    file_name: None,
-                    file_size: None, // TODO: Extract photo size properly
+                    // Photos that expired or were never sent have no sizes
+                    file_name: best.map(|_| photo_file_name(photo.id())),
+                    file_size: best.map(|size| size.size() as u64),
                     mime_type: Some("image/jpeg".to_string()),
                     caption: if caption.is_empty() { None } else { Some(caption.clone()) },
+                    width: dimensions.map(|(width, _)| width),
+                    height: dimensions.map(|(_, height)| height),
+                    document_id: None,
+                    details: None,
                 };
+
+                if best.is_some() {
+                    PHOTO_DOWNLOADS.enqueue_photo(client, photo.clone());
+                }
                 let text = if caption.is_empty() { "[Photo]".to_string() } else { caption };
                 (MessageType::Photo, Some(media_info), text)
             },
             Media::Document(doc) => {
                 let caption = message.text().to_string();
                 let mime_type = doc.mime_type().map(|m| m.to_string());
-                
+                let (voice, round) = recording_kind(&doc);
+
                 // Determine if it's a video, audio, or document
                 let message_type = match mime_type.as_deref() {
+                    _ if voice => MessageType::Voice,
+                    _ if round => MessageType::Video,
                     Some(t) if t.starts_with("video/") => MessageType::Video,
                     Some(t) if t.starts_with("audio/") => MessageType::Audio,
                     _ => MessageType::Document,
                 };
-                
+                let resolution = doc.resolution()
+                    .and_then(|(width, height)| Some((u32::try_from(width).ok()?, u32::try_from(height).ok()?)));
+
                 let media_info = MediaInfo {
-                    file_name: Some(doc.name().to_string()),
+                    file_name: (!doc.name().is_empty()).then(|| doc.name().to_string()),
                     file_size: Some(doc.size() as u64),
                     mime_type,
                     caption: if caption.is_empty() { None } else { Some(caption.clone()) },
+                    width: resolution.map(|(width, _)| width),
+                    height: resolution.map(|(_, height)| height),
+                    document_id: Some(doc.id()),
+                    details: None,
                 };
                 
                 let text = if caption.is_empty() {
                     match message_type {
                         MessageType::Video => "[Video]".to_string(),
                         MessageType::Audio => "[Audio]".to_string(),
+                        MessageType::Voice => "[Voice]".to_string(),
                         _ => format!("[Document: {}]", doc.name()),
                     }
                 } else { 
@@ -252,12 +1461,18 @@ fn classify_message(client: &grammers_client::Client, message: &grammers_client:
                 (message_type, Some(media_info), text)
             },
                                      Media::Sticker(sticker) => {
-                 let sticker_id = sticker.document.id().to_string();
+                 // Static stickers are WebP images, animated ones Lottie
+                 // (TGS) or WebM videos
+                 let mime_type = sticker.document.mime_type().unwrap_or("image/webp");
                  let media_info = MediaInfo {
-                     file_name: Some(format!("sticker_{}.webp", sticker_id)),
+                     file_name: Some(sticker_file_name(sticker.document.id(), Some(mime_type))),
                      file_size: Some(sticker.document.size() as u64),
-                     mime_type: Some("image/webp".to_string()),
+                     mime_type: Some(mime_type.to_string()),
                      caption: None,
+                     width: None,
+                     height: None,
+                     document_id: None,
+                     details: None,
                  };
                  let emoji = if !sticker.emoji().is_empty() { 
                      sticker.emoji() 
@@ -266,24 +1481,54 @@ fn classify_message(client: &grammers_client::Client, message: &grammers_client:
                  };
                  
                  // Trigger background download
-                 let client_clone = client.clone();
-                 let sticker_doc = sticker.document.clone();
-                 tokio::spawn(async move {
-                     if let Err(e) = download_sticker(&client_clone, sticker_doc).await {
-                         eprintln!("Failed to download sticker {}: {}", sticker_id, e);
-                     }
-                 });
+                 STICKER_DOWNLOADS.enqueue_sticker(client, sticker.document.clone());
                  
                  (MessageType::Sticker, Some(media_info), format!("[{} Sticker]", emoji))
              },
             Media::Contact(_) => {
                 (MessageType::Contact, None, "[Contact]".to_string())
             },
-            // Media::Location(_) => {
-            //     (MessageType::Location, None, "[Location]".to_string())
-            // },
-            Media::Poll(_) => {
-                (MessageType::Poll, None, "[Poll]".to_string())
+            Media::Geo(geo) => {
+                let details = MediaDetails::Location { point: geo_point(&geo) };
+                (MessageType::Location, Some(MediaInfo::from_details(details, None)), "[Location]".to_string())
+            },
+            Media::Venue(venue) => {
+                let text = format!("[Venue: {}]", venue.title());
+                let details = MediaDetails::Venue {
+                    point: venue.geo.as_ref().map(geo_point),
+                    title: venue.title().to_string(),
+                    address: venue.address().to_string(),
+                    provider: Some(venue.provider().to_string()).filter(|provider| !provider.is_empty()),
+                    venue_id: Some(venue.venue_id().to_string()).filter(|id| !id.is_empty()),
+                };
+                (MessageType::Venue, Some(MediaInfo::from_details(details, None)), text)
+            },
+            Media::GeoLive(live) => {
+                let details = MediaDetails::LiveLocation {
+                    point: live.geo.as_ref().map(geo_point),
+                    period: live.period(),
+                    heading: live.heading(),
+                };
+                (MessageType::LiveLocation, Some(MediaInfo::from_details(details, None)), "[Live Location]".to_string())
+            },
+            Media::Dice(dice) => {
+                let text = format!("[{} {}]", dice.emoji(), dice.value());
+                let details = MediaDetails::Dice { emoji: dice.emoji().to_string(), value: dice.value() };
+                (MessageType::Dice, Some(MediaInfo::from_details(details, None)), text)
+            },
+            // What the preview shows is filled in by `fetch_raw_details`
+            Media::WebPage(_) => {
+                let text = message.text().to_string();
+                let media_info = MediaInfo {
+                    caption: Some(text.clone()).filter(|text| !text.is_empty()),
+                    ..MediaInfo::default()
+                };
+                (MessageType::WebPage, Some(media_info), text)
+            },
+            // The rest of the poll is filled in by `fetch_raw_details`
+            Media::Poll(poll) => {
+                let grammers_tl_types::enums::TextWithEntities::Entities(question) = poll.question();
+                (MessageType::Poll, None, format!("[Poll: {}]", question.text))
             },
             _ => {
                 let text = message.text().to_string();
@@ -365,54 +1610,64 @@ fn try_extract_fmt_entities(message: &grammers_client::types::Message) -> Option
 
 #[cfg(feature = "ssr")]
 fn convert_grammers_entities_to_text_entities(entities: &[grammers_tl_types::enums::MessageEntity]) -> Vec<TextEntity> {
+    use grammers_tl_types::enums::{InputUser, MessageEntity};
+
     let mut result = Vec::new();
     
     for entity in entities {
-        let (offset, length, entity_type, url) = match entity {
-            grammers_tl_types::enums::MessageEntity::Bold(bold) => {
-                (bold.offset, bold.length, EntityType::Bold, None)
-            },
-            grammers_tl_types::enums::MessageEntity::Italic(italic) => {
-                (italic.offset, italic.length, EntityType::Italic, None)
-            },
-            grammers_tl_types::enums::MessageEntity::Code(code) => {
-                (code.offset, code.length, EntityType::Code, None)
-            },
-            grammers_tl_types::enums::MessageEntity::Pre(pre) => {
-                (pre.offset, pre.length, EntityType::Pre, None)
-            },
-            grammers_tl_types::enums::MessageEntity::TextUrl(text_url) => {
-                (text_url.offset, text_url.length, EntityType::TextLink, Some(text_url.url.clone()))
-            },
-            grammers_tl_types::enums::MessageEntity::Url(url) => {
-                (url.offset, url.length, EntityType::Link, None)
-            },
-            grammers_tl_types::enums::MessageEntity::Mention(mention) => {
-                (mention.offset, mention.length, EntityType::Mention, None)
-            },
-            grammers_tl_types::enums::MessageEntity::Hashtag(hashtag) => {
-                (hashtag.offset, hashtag.length, EntityType::Hashtag, None)
-            },
-            grammers_tl_types::enums::MessageEntity::BotCommand(bot_command) => {
-                (bot_command.offset, bot_command.length, EntityType::BotCommand, None)
-            },
-            grammers_tl_types::enums::MessageEntity::Email(email) => {
-                (email.offset, email.length, EntityType::Email, None)
-            },
-            grammers_tl_types::enums::MessageEntity::Phone(phone) => {
-                (phone.offset, phone.length, EntityType::Phone, None)
-            },
-            _ => {
+        let entity_type = match entity {
+            MessageEntity::Bold(_) => EntityType::Bold,
+            MessageEntity::Italic(_) => EntityType::Italic,
+            MessageEntity::Underline(_) => EntityType::Underline,
+            MessageEntity::Strike(_) => EntityType::Strikethrough,
+            MessageEntity::Spoiler(_) => EntityType::Spoiler,
+            MessageEntity::Code(_) => EntityType::Code,
+            MessageEntity::Pre(_) => EntityType::Pre,
+            MessageEntity::TextUrl(_) => EntityType::TextLink,
+            MessageEntity::Url(_) => EntityType::Link,
+            MessageEntity::Mention(_) => EntityType::Mention,
+            MessageEntity::MentionName(_) | MessageEntity::InputMessageEntityMentionName(_) => EntityType::MentionName,
+            MessageEntity::Hashtag(_) => EntityType::Hashtag,
+            MessageEntity::Cashtag(_) => EntityType::Cashtag,
+            MessageEntity::BotCommand(_) => EntityType::BotCommand,
+            MessageEntity::Email(_) => EntityType::Email,
+            MessageEntity::Phone(_) => EntityType::Phone,
+            MessageEntity::BankCard(_) => EntityType::BankCard,
+            MessageEntity::CustomEmoji(emoji) => EntityType::CustomEmoji { document_id: emoji.document_id },
+            // The API layer grammers speaks predates collapsed blockquotes,
+            // so all of them arrive expanded
+            MessageEntity::Blockquote(_) => EntityType::Blockquote,
+            MessageEntity::Unknown(_) => {
                 eprintln!("Unknown entity type: {:?}", entity);
                 continue;
             }
         };
+        let url = match entity {
+            MessageEntity::TextUrl(text_url) => Some(text_url.url.clone()),
+            _ => None,
+        };
+        let language = match entity {
+            MessageEntity::Pre(pre) if !pre.language.is_empty() => Some(pre.language.clone()),
+            _ => None,
+        };
+        let user_id = match entity {
+            MessageEntity::MentionName(mention) => Some(mention.user_id),
+            MessageEntity::InputMessageEntityMentionName(mention) => match &mention.user_id {
+                InputUser::User(user) => Some(user.user_id),
+                InputUser::FromMessage(user) => Some(user.user_id),
+                InputUser::Empty | InputUser::UserSelf => None,
+            },
+            _ => None,
+        };
+        let (offset, length) = (entity.offset(), entity.length());
         
         result.push(TextEntity {
             offset: offset as usize,
             length: length as usize,
             entity_type: entity_type.clone(),
             url,
+            language,
+            user_id,
         });
         
         eprintln!("Converted entity: offset={}, length={}, type={:?}", offset, length, entity_type);
@@ -422,39 +1677,38 @@ fn convert_grammers_entities_to_text_entities(entities: &[grammers_tl_types::enu
     result
 }
 
+/// Whether `document` was recorded in the app as a voice message or a round
+/// video message, rather than sent as a file.
 #[cfg(feature = "ssr")]
-pub async fn download_sticker(client: &grammers_client::Client, document: grammers_client::types::media::Document) -> Result<Vec<u8>, String> {
-    use std::path::Path;
-    use tokio::fs;
-    
-    let sticker_id = document.id().to_string();
-    let file_path = format!("target/site/stickers/sticker_{}.webp", sticker_id);
-    
-    // Check if already downloaded
-    if Path::new(&file_path).exists() {
-        eprintln!("Sticker {} already cached, reading from disk", sticker_id);
-        return fs::read(&file_path).await.map_err(|e| format!("Failed to read cached file: {}", e));
-    }
-    
-    eprintln!("Downloading sticker {} from Telegram", sticker_id);
-    
-    // Create stickers directory if it doesn't exist
-    if let Some(parent) = Path::new(&file_path).parent() {
-        fs::create_dir_all(parent).await.map_err(|e| format!("Failed to create directory: {}", e))?;
-    }
-    
-    // Download the sticker using grammers-client
-    use grammers_client::types::Downloadable;
-    let downloadable = Downloadable::Media(grammers_client::types::Media::Document(document));
-    client.download_media(&downloadable, &file_path).await.map_err(|e| format!("Download error: {}", e))?;
-    
-    // Read the downloaded file to return the data
-    let file_data = fs::read(&file_path).await.map_err(|e| format!("Failed to read downloaded file: {}", e))?;
-    
-    eprintln!("Downloaded {} bytes for sticker {}", file_data.len(), sticker_id);
-    
-    eprintln!("Sticker {} cached to {}", sticker_id, file_path);
-    Ok(file_data)
+fn recording_kind(document: &grammers_client::types::media::Document) -> (bool, bool) {
+    use grammers_client::types::Media;
+    use grammers_tl_types::enums::{Document, DocumentAttribute, MessageMedia};
+
+    let MessageMedia::Document(media) = MessageMedia::from(Media::Document(document.clone())) else {
+        return (false, false);
+    };
+    let Some(Document::Document(document)) = media.document else {
+        return (false, false);
+    };
+    document.attributes.iter().fold((false, false), |(voice, round), attribute| match attribute {
+        DocumentAttribute::Audio(audio) => (voice || audio.voice, round),
+        DocumentAttribute::Video(video) => (voice, round || video.round_message),
+        _ => (voice, round),
+    })
+}
+
+/// Pixel size of a photo size, unless it is a placeholder without one.
+#[cfg(feature = "ssr")]
+fn photo_size_dimensions(size: &grammers_client::types::photo_sizes::PhotoSize) -> Option<(u32, u32)> {
+    use grammers_client::types::photo_sizes::PhotoSize;
+
+    let (width, height) = match size {
+        PhotoSize::Size(size) => (size.width, size.height),
+        PhotoSize::Cached(size) => (size.width, size.height),
+        PhotoSize::Progressive(size) => (size.width, size.height),
+        _ => return None,
+    };
+    Some((u32::try_from(width).ok()?, u32::try_from(height).ok()?))
 }
 
 #[cfg(feature = "ssr")]
@@ -479,6 +1733,8 @@ fn extract_markdown_entities(text: &str) -> Vec<TextEntity> {
                 length: mat.len(),
                 entity_type: EntityType::Pre,
                 url: None,
+                language: None,
+                user_id: None,
             });
         }
     }
@@ -492,6 +1748,8 @@ fn extract_markdown_entities(text: &str) -> Vec<TextEntity> {
                 length: mat.len(),
                 entity_type: EntityType::Bold,
                 url: None,
+                language: None,
+                user_id: None,
             });
         }
     }
@@ -505,6 +1763,8 @@ fn extract_markdown_entities(text: &str) -> Vec<TextEntity> {
                 length: mat.len(),
                 entity_type: EntityType::Italic,
                 url: None,
+                language: None,
+                user_id: None,
             });
         }
     }
@@ -518,6 +1778,8 @@ fn extract_markdown_entities(text: &str) -> Vec<TextEntity> {
                 length: mat.len(),
                 entity_type: EntityType::Code,
                 url: None,
+                language: None,
+                user_id: None,
             });
         }
     }
@@ -531,6 +1793,8 @@ fn extract_markdown_entities(text: &str) -> Vec<TextEntity> {
                 length: mat.len(),
                 entity_type: EntityType::Link,
                 url: Some(mat.as_str().to_string()),
+                language: None,
+                user_id: None,
             });
         }
     }
@@ -543,3 +1807,135 @@ fn extract_markdown_entities(text: &str) -> Vec<TextEntity> {
 }
 
 
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use grammers_tl_types as tl;
+
+    fn text(text: &str) -> tl::enums::TextWithEntities {
+        tl::types::TextWithEntities { text: text.to_string(), entities: Vec::new() }.into()
+    }
+
+    fn raw_poll(quiz: bool) -> tl::enums::Poll {
+        tl::types::Poll {
+            id: 42,
+            closed: false,
+            public_voters: false,
+            multiple_choice: false,
+            quiz,
+            question: text("Best bread?"),
+            answers: ["Rye", "Sourdough", "Baguette"]
+                .into_iter()
+                .zip([b"0", b"1", b"2"])
+                .map(|(answer, option)| tl::types::PollAnswer { text: text(answer), option: option.to_vec() }.into())
+                .collect(),
+            close_period: None,
+            close_date: None,
+        }
+        .into()
+    }
+
+    fn voters(option: &[u8], voters: i32, chosen: bool, correct: bool) -> tl::enums::PollAnswerVoters {
+        tl::types::PollAnswerVoters { chosen, correct, option: option.to_vec(), voters }.into()
+    }
+
+    fn results(
+        min: bool,
+        results: Option<Vec<tl::enums::PollAnswerVoters>>,
+        total_voters: Option<i32>,
+        solution: Option<&str>,
+    ) -> tl::enums::PollResults {
+        tl::types::PollResults {
+            min,
+            results,
+            total_voters,
+            recent_voters: None,
+            solution: solution.map(str::to_string),
+            solution_entities: None,
+        }
+        .into()
+    }
+
+    /// `(voters, chosen, correct)` of each answer.
+    fn counts(poll: &Poll) -> Vec<(Option<i32>, bool, bool)> {
+        poll.answers.iter().map(|answer| (answer.voters, answer.chosen, answer.correct)).collect()
+    }
+
+    #[test]
+    fn polls_are_converted_with_their_results() {
+        let poll = Poll::from_raw(
+            raw_poll(false),
+            results(false, Some(vec![voters(b"0", 3, false, false), voters(b"1", 5, true, false)]), Some(8), None),
+        );
+        assert_eq!(poll.id, 42);
+        assert_eq!(poll.question, "Best bread?");
+        let answers: Vec<(&str, &[u8])> =
+            poll.answers.iter().map(|answer| (answer.text.as_str(), answer.option.as_slice())).collect();
+        assert_eq!(answers, [("Rye", &b"0"[..]), ("Sourdough", b"1"), ("Baguette", b"2")]);
+        assert!(poll.anonymous && !poll.quiz && !poll.multiple_choice && !poll.closed);
+        assert_eq!(counts(&poll), [(Some(3), false, false), (Some(5), true, false), (None, false, false)]);
+        assert_eq!(poll.total_voters, Some(8));
+        assert_eq!(poll.solution, None);
+    }
+
+    #[test]
+    fn hidden_results_leave_counts_unknown() {
+        let poll = Poll::from_raw(raw_poll(false), results(false, None, None, None));
+        assert_eq!(counts(&poll), [(None, false, false); 3]);
+        assert_eq!(poll.total_voters, None);
+        assert_eq!(poll.percentage(&poll.answers[0]), None);
+    }
+
+    #[test]
+    fn results_are_matched_by_option() {
+        let mut poll = Poll::from_raw(raw_poll(true), results(false, None, None, None));
+        poll.apply_results(results(
+            false,
+            Some(vec![
+                voters(b"2", 1, false, false),
+                voters(b"9", 7, true, true),
+                voters(b"1", 4, true, true),
+                voters(b"0", 2, false, false),
+            ]),
+            Some(7),
+            None,
+        ));
+        assert_eq!(counts(&poll), [(Some(2), false, false), (Some(4), true, true), (Some(1), false, false)]);
+        assert_eq!(poll.total_voters, Some(7));
+    }
+
+    #[test]
+    fn min_results_keep_what_they_leave_out() {
+        let mut poll = Poll::from_raw(
+            raw_poll(true),
+            results(false, Some(vec![voters(b"0", 1, true, false), voters(b"1", 1, false, true)]), Some(2), Some("Rye is dense")),
+        );
+
+        // Someone else voted: new counts, but nothing about the logged-in user
+        poll.apply_results(results(true, Some(vec![voters(b"0", 1, false, false), voters(b"1", 2, false, false)]), Some(3), None));
+        assert_eq!(counts(&poll), [(Some(1), true, false), (Some(2), false, true), (None, false, false)]);
+        assert_eq!(poll.total_voters, Some(3));
+        assert_eq!(poll.solution.as_deref(), Some("Rye is dense"));
+
+        // Results without counts change none of them
+        poll.apply_results(results(true, None, None, None));
+        assert_eq!(counts(&poll), [(Some(1), true, false), (Some(2), false, true), (None, false, false)]);
+        assert_eq!(poll.total_voters, Some(3));
+    }
+
+    #[test]
+    fn quiz_solutions_arrive_with_the_results() {
+        let mut poll = Poll::from_raw(raw_poll(true), results(false, None, None, None));
+        assert!(poll.quiz);
+        assert_eq!(poll.solution, None);
+
+        poll.apply_results(results(false, Some(vec![voters(b"1", 1, true, true)]), Some(1), Some("Sourdough rises slowly")));
+        assert_eq!(poll.solution.as_deref(), Some("Sourdough rises slowly"));
+        assert!(poll.answers[1].correct && poll.answers[1].chosen);
+        assert_eq!(poll.result_lines()[1], "Sourdough ✓ — 1 vote (100%)");
+
+        poll.apply_results(results(false, None, Some(1), Some("Edited")));
+        assert_eq!(poll.solution.as_deref(), Some("Edited"));
+    }
+}